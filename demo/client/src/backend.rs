@@ -2,9 +2,9 @@ use ed25519_dalek::*;
 use reqwasm::http::Request;
 use serde::{Deserialize, Serialize};
 
-const BACKEND: &str = "http://localhost:8080/backend";
+const BACKEND: &str = "/backend";
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Deserialize, PartialEq)]
 pub struct Swarm {
     pub pubkey: String,
     pub sacred_queens: i64,
@@ -12,6 +12,9 @@ pub struct Swarm {
     pub guardians: i64,
     pub berserkers: i64,
     pub eggs: i64,
+    /// Veteran berserkers, `veterans[0]` is the first tier.
+    #[serde(default)]
+    pub veterans: Vec<i64>,
 }
 
 impl Swarm {
@@ -23,8 +26,12 @@ impl Swarm {
             guardians: 0,
             berserkers: 0,
             eggs: 0,
+            veterans: Vec::new(),
         }
     }
+    pub fn all_berserkers(&self) -> i64 {
+        self.berserkers + self.veterans.iter().sum::<i64>()
+    }
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -42,12 +49,75 @@ pub struct Hive {
     pub eggs: i64,
 }
 
+/// Range the server rounds hidden hive numbers to.
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+pub struct Estimate {
+    pub min: i64,
+    pub max: i64,
+}
+
+impl std::fmt::Display for Estimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.min == self.max {
+            true => write!(f, "{}", self.min),
+            false => write!(f, "{}-{}", self.min, self.max),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, PartialEq)]
+pub struct HiveEstimate {
+    pub pubkey: String,
+    pub guardians: Estimate,
+    pub queens: Estimate,
+    pub eggs: i64,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoutPayment {
+    Berserkers,
+    Eggs,
+}
+
+#[derive(Serialize)]
+pub struct Scout {
+    pub pubkey: String,
+    pub hive_pubkey: String,
+    pub pay_with: ScoutPayment,
+}
+
+#[derive(Clone, Deserialize, PartialEq)]
+pub struct Intel {
+    pub scout: String,
+    pub hive: Hive,
+    pub scouted_at: i64,
+    pub expires_at: i64,
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct HatchRequest {
     pub pubkey: String,
     pub eggs: i64,
 }
 
+#[derive(Clone, Deserialize, PartialEq)]
+pub struct HatchJob {
+    pub id: String,
+    pub pubkey: String,
+    pub eggs: i64,
+    pub started_at: i64,
+    pub completes_at: i64,
+    pub hatched: Option<Swarm>,
+    pub claimed: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct HatchClaim {
+    pub pubkey: String,
+    pub job: String,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Attack {
     pub swarm_pubkey: String,
@@ -55,7 +125,26 @@ pub struct Attack {
     pub berserkers: i64,
 }
 
-#[derive(Clone)]
+#[derive(Deserialize, Serialize)]
+pub struct Challenge {
+    pub pubkey: String,
+    pub challenge: String,
+    pub expires_at: i64,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Login {
+    pub pubkey: String,
+    pub challenge: String,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Session {
+    pub token: String,
+    pub expires_at: i64,
+}
+
+#[derive(Clone, Deserialize)]
 pub struct Account {
     pub swarm: Swarm,
     pub sacred_hive: SacredHive,
@@ -83,18 +172,14 @@ impl Account {
         self.sacred_hive.sacred_queens.is_positive() || self.sacred_hive.eggs.is_positive()
     }
     pub fn can_attack(&self) -> bool {
-        self.swarm.berserkers.is_positive()
+        self.swarm.all_berserkers().is_positive()
+    }
+    pub fn can_scout(&self) -> bool {
+        self.swarm.berserkers.is_positive() || self.swarm.eggs.is_positive()
     }
     pub fn new() -> Self {
         Self {
-            swarm: Swarm {
-                pubkey: String::new(),
-                sacred_queens: 0,
-                queens: 0,
-                guardians: 0,
-                berserkers: 0,
-                eggs: 0,
-            },
+            swarm: Swarm::new(),
             hive: Hive {
                 pubkey: String::new(),
                 guardians: 0,
@@ -110,13 +195,17 @@ impl Account {
     }
 }
 
-pub async fn fetch_hives(eggs: i64) -> Result<Vec<Hive>, reqwasm::Error> {
+/// Hives around `eggs`, or the suggested opponents of `pubkey` when no eggs
+/// are searched for, or the best hives when nobody is logged in.
+pub async fn fetch_hives(eggs: i64, pubkey: String) -> Result<Vec<HiveEstimate>, reqwasm::Error> {
     let mut url = format!("{}/hive/list/top", BACKEND);
     if eggs > 0 {
         url = format!("{}/hive/list/neigh/{}", BACKEND, eggs);
+    } else if !pubkey.is_empty() {
+        url = format!("{}/hive/targets/{}", BACKEND, pubkey);
     }
     let resp = Request::get(&url).send().await?;
-    let body = resp.json::<Vec<Hive>>().await?;
+    let body = resp.json::<Vec<HiveEstimate>>().await?;
     Ok(body)
 }
 
@@ -139,6 +228,8 @@ pub async fn get_swarm(pubkey: String) -> Result<Swarm, reqwasm::Error> {
     Ok(body)
 }
 
+/// Balances of another player. Their hive only shows the lower end of its
+/// estimates, the exact numbers are revealed by scouting.
 pub async fn get_account(pubkey: String) -> Result<Account, reqwasm::Error> {
     let swarm = Request::get(&format!("{}/swarm/{}", BACKEND, pubkey))
         .send()
@@ -153,15 +244,26 @@ pub async fn get_account(pubkey: String) -> Result<Account, reqwasm::Error> {
     let hive = Request::get(&format!("{}/hive/get/{}", BACKEND, pubkey))
         .send()
         .await?
-        .json::<Hive>()
+        .json::<HiveEstimate>()
         .await?;
     Ok(Account {
         swarm,
         sacred_hive,
-        hive,
+        hive: Hive {
+            pubkey: hive.pubkey,
+            guardians: hive.guardians.min,
+            queens: hive.queens.min,
+            eggs: hive.eggs,
+        },
     })
 }
 
+/// Exact balances of the owner of `kp`, read with a session.
+pub async fn get_own_account(kp: Keypair) -> Result<Account, reqwasm::Error> {
+    let session = login(kp).await?;
+    get_session_account(&session).await
+}
+
 pub struct StakeResult(pub Result<bool, reqwasm::Error>);
 pub async fn stake_sacred_hive(sh: SacredHive, kp: Keypair) -> Result<bool, reqwasm::Error> {
     run_request(sh, kp, "sacred_hive/stake".to_string()).await
@@ -179,10 +281,66 @@ pub async fn unstake_hive(h: Hive, kp: Keypair) -> Result<bool, reqwasm::Error>
 pub async fn hatch(hr: HatchRequest, kp: Keypair) -> Result<bool, reqwasm::Error> {
     run_request(hr, kp, "hatchery".to_string()).await
 }
+pub async fn claim_hatch(hc: HatchClaim, kp: Keypair) -> Result<bool, reqwasm::Error> {
+    run_request(hc, kp, "hatchery/claim".to_string()).await
+}
+pub async fn get_hatch_jobs(pubkey: String) -> Result<Vec<HatchJob>, reqwasm::Error> {
+    let url = format!("{}/hatchery/jobs/{}", BACKEND, pubkey);
+    let resp = Request::get(&url).send().await?;
+    let body = resp.json::<Vec<HatchJob>>().await?;
+    Ok(body)
+}
 pub async fn attack(a: Attack, kp: Keypair) -> Result<bool, reqwasm::Error> {
     run_request(a, kp, "hive/attack".to_string()).await
 }
 
+/// Returns the intel of a successful scout.
+pub async fn scout(s: Scout, kp: Keypair) -> Result<Option<Intel>, reqwasm::Error> {
+    let encoded_signature = sign(&s, kp);
+    let bytes = serde_json::to_string(&s).expect("Failed to serialize scout to json");
+    let resp = Request::post(&format!("{}/scout", BACKEND))
+        .body(bytes)
+        .header("ed25519-singature", &encoded_signature)
+        .header("content-type", "application/json")
+        .send()
+        .await?;
+    match resp.ok() {
+        true => Ok(Some(resp.json::<Intel>().await?)),
+        false => Ok(None),
+    }
+}
+
+pub async fn login(kp: Keypair) -> Result<Session, reqwasm::Error> {
+    let pubkey = bs58::encode(kp.public.to_bytes()).into_string();
+    let challenge = Request::get(&format!("{}/auth/challenge/{}", BACKEND, pubkey))
+        .send()
+        .await?
+        .json::<Challenge>()
+        .await?;
+    let login = Login {
+        pubkey,
+        challenge: challenge.challenge,
+    };
+    let encoded_signature = sign(&login, kp);
+    let bytes = serde_json::to_string(&login).expect("Failed to serialize login to json");
+    let resp = Request::post(&format!("{}/auth/login", BACKEND))
+        .body(bytes)
+        .header("ed25519-singature", &encoded_signature)
+        .header("content-type", "application/json")
+        .send()
+        .await?;
+    resp.json::<Session>().await
+}
+
+pub async fn get_session_account(session: &Session) -> Result<Account, reqwasm::Error> {
+    Request::get(&format!("{}/account", BACKEND))
+        .header("Authorization", &format!("Bearer {}", session.token))
+        .send()
+        .await?
+        .json::<Account>()
+        .await
+}
+
 async fn run_request<T: Serialize>(t: T, kp: Keypair, url: String) -> Result<bool, reqwasm::Error> {
     let encoded_signature = sign(&t, kp);
     let url = format!("{}/{}", BACKEND, url);
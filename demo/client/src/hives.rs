@@ -18,7 +18,8 @@ async fn HivesTables<G: Html>(ctx: ScopeRef<'_>, eggs: i64) -> View<G> {
     let account = ctx.use_context::<RcSignal<Account>>();
     let publickey = ctx.use_context::<RcSignal<SearchPubKey>>();
     let request_failed = ctx.use_context::<RcSignal<bool>>();
-    let hives = match super::backend::fetch_hives(eggs).await {
+    let pubkey = account.get().swarm.pubkey.clone();
+    let hives = match super::backend::fetch_hives(eggs, pubkey).await {
         Ok(h) => h,
         Err(_) => {
             request_failed.set(true);
@@ -27,6 +28,7 @@ async fn HivesTables<G: Html>(ctx: ScopeRef<'_>, eggs: i64) -> View<G> {
     };
     request_failed.set(false);
     let hives = ctx.create_signal(hives);
+    let intel = ctx.create_ref(create_rc_signal(Vec::<Intel>::new()));
 
     let search_publickey = |s: String| {
         publickey.set(SearchPubKey(s));
@@ -36,7 +38,7 @@ async fn HivesTables<G: Html>(ctx: ScopeRef<'_>, eggs: i64) -> View<G> {
         let attack_request = Attack {
             swarm_pubkey: account.get().swarm.pubkey.clone(),
             hive_pubkey: s,
-            berserkers: account.get().swarm.berserkers,
+            berserkers: account.get().swarm.all_berserkers(),
         };
         log::info!("Trying to attack with {:?}", attack_request);
         {
@@ -52,12 +54,51 @@ async fn HivesTables<G: Html>(ctx: ScopeRef<'_>, eggs: i64) -> View<G> {
         }
     };
 
+    let scout_button = move |s: String| {
+        let pay_with = match account.get().swarm.berserkers.is_positive() {
+            true => ScoutPayment::Berserkers,
+            false => ScoutPayment::Eggs,
+        };
+        let scout_request = Scout {
+            pubkey: account.get().swarm.pubkey.clone(),
+            hive_pubkey: s,
+            pay_with,
+        };
+        {
+            let privatekey = privatekey.clone();
+            let stake_result = stake_result.clone();
+            let intel = intel.clone();
+            spawn_local(async move {
+                if let Ok(kp) = key_helpers::get_keypair(privatekey.get().0.to_string()) {
+                    let result = super::backend::scout(scout_request, kp).await;
+                    if let Ok(Some(found)) = &result {
+                        let mut known: Vec<Intel> = intel
+                            .get()
+                            .iter()
+                            .filter(|i| i.hive.pubkey != found.hive.pubkey)
+                            .cloned()
+                            .collect();
+                        known.push(found.clone());
+                        intel.set(known);
+                    }
+                    stake_result.set(StakeResult(result.map(|found| found.is_some())));
+                };
+            });
+        }
+    };
+
     view! { ctx, div(class="container") { div(class="columns is-multiline is-mobile is-gapless") {
         Indexed {
             iterable: hives,
-            view: move |ctx, Hive { pubkey, guardians, queens, eggs }| {
+            view: move |ctx, HiveEstimate { pubkey, guardians, queens, eggs }| {
                 let p1 = pubkey.clone();
                 let p2 = pubkey.clone();
+                let p3 = pubkey.clone();
+                let p4 = pubkey.clone();
+                // scouted hives show their exact numbers
+                let scouted = ctx.create_memo(move || {
+                    intel.get().iter().find(|i| i.hive.pubkey == p4).map(|i| i.hive.clone())
+                });
                 view! { ctx, div(class="column has-text-centered") {
                     button(
                         class="button is-light is-size-5 is-rounded has-text-success",
@@ -72,12 +113,18 @@ async fn HivesTables<G: Html>(ctx: ScopeRef<'_>, eggs: i64) -> View<G> {
                             style="padding-left:10px") {
                             i(class="fa-lg fa-solid fa-address-card") {}
                         }
-                        span(style="text-align:right; width:80px") { (guardians) }
+                        span(style="text-align:right; width:80px") {
+                            (scouted.get().as_ref().as_ref()
+                                .map_or(guardians.to_string(), |h| h.guardians.to_string()))
+                        }
                         span(class="icon is-medium",
                             style="padding-left:5px") {
                             i(class="fa-lg fa-solid fa-shield") {}
                         }
-                        span(style="text-align:right; width:60px") { (queens) }
+                        span(style="text-align:right; width:60px") {
+                            (scouted.get().as_ref().as_ref()
+                                .map_or(queens.to_string(), |h| h.queens.to_string()))
+                        }
                         span(class="icon is-medium",
                             style="padding-left:5px") {
                             i(class="fa-lg fa-solid fa-chess-queen") {}
@@ -88,6 +135,14 @@ async fn HivesTables<G: Html>(ctx: ScopeRef<'_>, eggs: i64) -> View<G> {
                             i(class="fa-lg fa-solid fa-egg") {}
                         }
 
+                        (if account.get().can_scout() {
+                            let p3 = p3.clone();
+                            view!{ ctx, button(class="button is-info is-small is-rounded",
+                                on:click=move |_| scout_button(p3.clone()),
+                                style="margin-left: 15px") { "Scout" } }
+                        } else {
+                            view!{ ctx, div {} }
+                        })
                         (if account.get().can_attack() {
                             let p2 = p2.clone();
                             view!{ ctx, button(class="button is-danger is-small is-rounded",
@@ -107,6 +162,7 @@ async fn HivesTables<G: Html>(ctx: ScopeRef<'_>, eggs: i64) -> View<G> {
 pub async fn HivesComponent<G: Html>(ctx: ScopeRef<'_>) -> View<G> {
     ctx.provide_context(create_rc_signal(false));
     let request_failed = ctx.use_context::<RcSignal<bool>>();
+    let account = ctx.use_context::<RcSignal<Account>>();
     let eggs = ctx.create_signal(SearchByEggs(0));
     let eggs_input = ctx.create_signal(String::new());
     let reset_eggs = move || {
@@ -129,7 +185,12 @@ pub async fn HivesComponent<G: Html>(ctx: ScopeRef<'_>) -> View<G> {
                 button(class=String::from("button ".to_owned() + (eggs.get().0.eq(&0)
                             .then(|| " is-success")
                             .unwrap_or(" is-light"))),
-                            on:click=move |_| reset_eggs()) { "List the best Hives" }
+                            on:click=move |_| reset_eggs()) {
+                    (match account.get().swarm.pubkey.is_empty() {
+                        true => "List the best Hives",
+                        false => "Suggested opponents",
+                    })
+                }
             }
 
             div(class="column is-auto") {
@@ -26,7 +26,16 @@ pub async fn Assets<G: Html>(ctx: ScopeRef<'_>, c: AssetsConfig) -> View<G> {
     if c.pubkey == "" {
         return view! { ctx, div{} };
     }
-    let account = match get_account(c.pubkey).await {
+    let account = match c.owned {
+        true => match key_helpers::get_keypair(
+            ctx.use_context::<RcSignal<PrivateKey>>().get().0.to_string(),
+        ) {
+            Ok(kp) => get_own_account(kp).await,
+            Err(_) => get_account(c.pubkey).await,
+        },
+        false => get_account(c.pubkey).await,
+    };
+    let account = match account {
         Ok(a) => a,
         _ => {
             return view! { ctx, div{
@@ -76,6 +85,22 @@ pub async fn Assets<G: Html>(ctx: ScopeRef<'_>, c: AssetsConfig) -> View<G> {
             (show_asset!("dark", account.swarm.queens, "chess-queen"))
             (show_asset!("dark", account.swarm.guardians, "shield"))
             (show_asset!("dark", account.swarm.berserkers, "shield-virus"))
+            (View::new_fragment(
+                account.swarm.veterans.iter().enumerate()
+                    .filter(|(_, count)| count.is_positive())
+                    .map(|(tier, count)| {
+                        let count = *count;
+                        let stars = "★".repeat(tier + 1);
+                        view! { ctx, span(class="tag is-size-5 is-rounded has-text-warning") {
+                            span { (count) }
+                            span(class="icon is-medium") {
+                                i(class="fa fa-solid fa-shield-virus") {}
+                            }
+                            span { (stars) }
+                        }}
+                    })
+                    .collect(),
+            ))
             (show_asset!("danger", account.sacred_hive.eggs, "egg"))
             (show_asset!("danger", account.sacred_hive.sacred_queens, "chess-king"))
             (show_asset!("success", account.hive.eggs, "egg"))
@@ -103,6 +128,39 @@ pub async fn Staking<G: Html>(ctx: ScopeRef<'_>) -> View<G> {
     let stake_eggs = ctx.create_signal(String::new());
     let stake_guardians = ctx.create_signal(String::new());
 
+    let hatch_jobs = ctx.create_ref(create_rc_signal(Vec::<HatchJob>::new()));
+    ctx.create_effect(move || {
+        let pubkey = account.get().swarm.pubkey.clone();
+        let hatch_jobs = hatch_jobs.clone();
+        if pubkey.is_empty() {
+            hatch_jobs.set(Vec::new());
+            return;
+        }
+        spawn_local(async move {
+            if let Ok(jobs) = get_hatch_jobs(pubkey).await {
+                hatch_jobs.set(jobs);
+            };
+        });
+    });
+
+    let claim_hatch_button = move |job: String| {
+        let hatch_claim = HatchClaim {
+            pubkey: account.get().swarm.pubkey.clone(),
+            job,
+        };
+        {
+            let privatekey = privatekey.clone();
+            let stake_result = stake_result.clone();
+            spawn_local(async move {
+                if let Ok(kp) = key_helpers::get_keypair(privatekey.get().0.to_string()) {
+                    stake_result.set(StakeResult(
+                        super::backend::claim_hatch(hatch_claim, kp).await,
+                    ));
+                };
+            });
+        }
+    };
+
     let hatch_eggs_button = move || {
         let hatch_request = HatchRequest {
             pubkey: account.get().swarm.pubkey.clone(),
@@ -230,6 +288,42 @@ pub async fn Staking<G: Html>(ctx: ScopeRef<'_>) -> View<G> {
             }
         }
 
+        (View::new_fragment(hatch_jobs.get().iter().cloned().map(|job| {
+            let HatchJob { id, eggs, hatched, .. } = job;
+            match hatched {
+                Some(units) => view! { ctx, div(class="columns is-mobile is-variable is-1",
+                    style="margin-bottom: -20px; margin-top: -20px;") {
+                    div(class="column") {
+                        div(class="tags are-small") {
+                            span(class="tag is-size-5 is-rounded has-text-dark") {
+                                span { (units.queens) }
+                                span(class="icon is-medium") { i(class="fa fa-solid fa-chess-queen") {} }
+                            }
+                            span(class="tag is-size-5 is-rounded has-text-dark") {
+                                span { (units.guardians) }
+                                span(class="icon is-medium") { i(class="fa fa-solid fa-shield") {} }
+                            }
+                            span(class="tag is-size-5 is-rounded has-text-dark") {
+                                span { (units.berserkers) }
+                                span(class="icon is-medium") { i(class="fa fa-solid fa-shield-virus") {} }
+                            }
+                        }
+                    }
+                    div(class="column is-2") {
+                        button(class="button is-light is-rounded is-fullwidth",
+                            on:click=move |_| claim_hatch_button(id.clone())) { "claim" }
+                    }
+                }},
+                None => view! { ctx, div(class="column is-full") {
+                    span(class="tag is-size-5 is-rounded has-text-grey") {
+                        span { (eggs) }
+                        span(class="icon is-medium") { i(class="fa fa-solid fa-egg") {} }
+                        span { "incubating" }
+                    }
+                }},
+            }
+        }).collect()))
+
         div(class="columns is-mobile is-variable is-1",
             style=String::from("margin-bottom: -20px; margin-top: -20px; ".to_owned()
                 + (account.get().can_unstake_s().then(|| "").unwrap_or("display: none")))) {
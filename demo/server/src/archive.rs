@@ -0,0 +1,413 @@
+use {
+    super::{
+        bounties::BOUNTIES_COLL_NAME,
+        delegation::DELEGATIONS_COLL_NAME,
+        economy::{self, SNAPSHOTS_COLL_NAME},
+        fortifications::FORTIFICATIONS_COLL_NAME,
+        incubation::HATCH_JOBS_COLL_NAME,
+        ledger::LEDGER_COLL_NAME,
+        locks::STAKE_LOCKS_COLL_NAME,
+        march::MARCHES_COLL_NAME,
+        mercenaries::MERCENARIES_COLL_NAME,
+        migrations::{self, SCHEMA_VERSION_COLL_NAME},
+        model::*,
+        notifications::NOTIFICATIONS_COLL_NAME,
+        production::HIVE_CLOCKS_COLL_NAME,
+        ratings::RATINGS_COLL_NAME,
+        scouting::INTEL_COLL_NAME,
+        transparency::TRANSPARENCY_COLL_NAME,
+        world::World,
+    },
+    anyhow::Result,
+    flate2::{read::GzDecoder, write::GzEncoder, Compression},
+    futures::stream::TryStreamExt,
+    mongodb::{
+        bson::{doc, Bson, Document},
+        options::SessionOptions,
+        ClientSession, Database,
+    },
+    serde::{de::DeserializeOwned, Deserialize, Serialize},
+    serde_json::Value,
+    sha2::{Digest, Sha256},
+    std::{
+        collections::BTreeMap,
+        fs::File,
+        io::{BufRead, BufReader, BufWriter, Write},
+    },
+};
+
+pub const FORMAT_VERSION: i64 = 1;
+const BATCH_SIZE: usize = 1000;
+
+/// History and bookkeeping collections are archived as canonical extended
+/// JSON so that ids, dates and integer widths survive the round trip.
+const HISTORY_COLLECTIONS: [&str; 15] = [
+    HIVE_CLOCKS_COLL_NAME,
+    DELEGATIONS_COLL_NAME,
+    FORTIFICATIONS_COLL_NAME,
+    RATINGS_COLL_NAME,
+    HATCH_JOBS_COLL_NAME,
+    BOUNTIES_COLL_NAME,
+    MERCENARIES_COLL_NAME,
+    STAKE_LOCKS_COLL_NAME,
+    MARCHES_COLL_NAME,
+    INTEL_COLL_NAME,
+    NOTIFICATIONS_COLL_NAME,
+    LEDGER_COLL_NAME,
+    TRANSPARENCY_COLL_NAME,
+    SNAPSHOTS_COLL_NAME,
+    SCHEMA_VERSION_COLL_NAME,
+];
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct CollectionSummary {
+    pub name: String,
+    pub documents: u64,
+    /// Hex encoded sha256 over the archive lines of the collection.
+    pub sha256: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Manifest {
+    pub format_version: i64,
+    pub schema_version: i64,
+    pub created_at: i64,
+    pub collections: Vec<CollectionSummary>,
+}
+
+/// One line of the gzip compressed archive. The documents of a collection
+/// are contiguous and the manifest is the last line.
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum ArchiveLine {
+    Document { collection: String, document: Value },
+    Manifest(Manifest),
+}
+
+fn write_line(out: &mut impl Write, line: &ArchiveLine, hasher: &mut Sha256) -> Result<()> {
+    let mut line = serde_json::to_vec(line)?;
+    line.push(b'\n');
+    hasher.update(&line);
+    out.write_all(&line)?;
+    Ok(())
+}
+
+async fn export_collection<T>(
+    db: &Database,
+    session: &mut ClientSession,
+    name: &str,
+    to_json: fn(T) -> Result<Value>,
+    out: &mut impl Write,
+) -> Result<CollectionSummary>
+where
+    T: DeserializeOwned + Unpin + Send + Sync,
+{
+    let mut cursor = db
+        .collection::<T>(name)
+        .find_with_session(None, None, session)
+        .await?;
+    let mut hasher = Sha256::new();
+    let mut documents = 0;
+    while let Some(t) = cursor.next(session).await.transpose()? {
+        let line = ArchiveLine::Document {
+            collection: name.to_string(),
+            document: to_json(t)?,
+        };
+        write_line(out, &line, &mut hasher)?;
+        documents += 1;
+    }
+    Ok(CollectionSummary {
+        name: name.to_string(),
+        documents,
+        sha256: format!("{:x}", hasher.finalize()),
+    })
+}
+
+fn contract_to_json<T: Contract>(t: T) -> Result<Value> {
+    Ok(serde_json::to_value(t)?)
+}
+
+fn document_to_json(document: Document) -> Result<Value> {
+    Ok(Bson::Document(document).into_canonical_extjson())
+}
+
+/// Writes all game and history collections of the world to `path`. They are
+/// read in one snapshot session, so the archive is consistent even while the
+/// world is live, as long as the export finishes within the snapshot history
+/// the deployment keeps (5 minutes by default).
+pub async fn export(world: &World, path: &str) -> Result<Manifest> {
+    let db = &world.db;
+    let options = SessionOptions::builder().snapshot(true).build();
+    let session = &mut world.client.start_session(Some(options)).await?;
+    let mut out = GzEncoder::new(BufWriter::new(File::create(path)?), Compression::default());
+    let mut collections = vec![
+        export_collection(
+            db,
+            session,
+            SWARMS_COLL_NAME,
+            contract_to_json::<Swarm>,
+            &mut out,
+        )
+        .await?,
+        export_collection(
+            db,
+            session,
+            HIVE_COLL_NAME,
+            contract_to_json::<Hive>,
+            &mut out,
+        )
+        .await?,
+        export_collection(
+            db,
+            session,
+            SACRED_HIVE_COLL_NAME,
+            contract_to_json::<SacredHive>,
+            &mut out,
+        )
+        .await?,
+    ];
+    for name in HISTORY_COLLECTIONS {
+        collections.push(export_collection(db, session, name, document_to_json, &mut out).await?);
+    }
+    let manifest = Manifest {
+        format_version: FORMAT_VERSION,
+        schema_version: migrations::current_version(db).await?,
+        created_at: chrono::Utc::now().timestamp(),
+        collections,
+    };
+    let line = ArchiveLine::Manifest(manifest.clone());
+    write_line(&mut out, &line, &mut Sha256::new())?;
+    out.finish()?.flush()?;
+    Ok(manifest)
+}
+
+fn read_lines(path: &str) -> Result<impl Iterator<Item = std::io::Result<String>>> {
+    Ok(BufReader::new(GzDecoder::new(File::open(path)?)).lines())
+}
+
+/// Parses a document of the archive into the type it is imported as, which
+/// rejects documents that do not match the schema of this build.
+fn check_document(collection: &str, document: Value) -> Result<()> {
+    match collection {
+        SWARMS_COLL_NAME => drop(serde_json::from_value::<Swarm>(document)?),
+        HIVE_COLL_NAME => drop(serde_json::from_value::<Hive>(document)?),
+        SACRED_HIVE_COLL_NAME => drop(serde_json::from_value::<SacredHive>(document)?),
+        name if HISTORY_COLLECTIONS.contains(&name) => drop(Bson::try_from(document)?),
+        name => return Err(anyhow::Error::msg(format!("unknown collection {}", name))),
+    }
+    Ok(())
+}
+
+/// Reads the whole archive and checks the format, the schema version, every
+/// document and the checksums against the manifest.
+pub fn validate(path: &str) -> Result<Manifest> {
+    let mut summaries: BTreeMap<String, (u64, Sha256)> = BTreeMap::new();
+    let mut manifest = None;
+    for line in read_lines(path)? {
+        let line = line?;
+        if manifest.is_some() {
+            return Err(anyhow::Error::msg("data after the manifest"));
+        }
+        match serde_json::from_str(&line)? {
+            ArchiveLine::Document {
+                collection,
+                document,
+            } => {
+                let (documents, hasher) = summaries.entry(collection.clone()).or_default();
+                hasher.update(line.as_bytes());
+                hasher.update(b"\n");
+                *documents += 1;
+                check_document(&collection, document)?;
+            }
+            ArchiveLine::Manifest(m) => manifest = Some(m),
+        }
+    }
+    let manifest = manifest.ok_or_else(|| anyhow::Error::msg("archive has no manifest"))?;
+    if manifest.format_version != FORMAT_VERSION {
+        return Err(anyhow::Error::msg(format!(
+            "unsupported archive format {}",
+            manifest.format_version
+        )));
+    }
+    if manifest.schema_version > migrations::latest_version() {
+        return Err(anyhow::Error::msg(format!(
+            "archive schema version {} is newer than the supported version {}",
+            manifest.schema_version,
+            migrations::latest_version()
+        )));
+    }
+    for summary in &manifest.collections {
+        let (documents, sha256) = summaries
+            .remove(&summary.name)
+            .map(|(documents, hasher)| (documents, format!("{:x}", hasher.finalize())))
+            .unwrap_or((0, format!("{:x}", Sha256::new().finalize())));
+        if documents != summary.documents || sha256 != summary.sha256 {
+            return Err(anyhow::Error::msg(format!(
+                "checksum mismatch in {}",
+                summary.name
+            )));
+        }
+    }
+    if let Some(name) = summaries.keys().next() {
+        return Err(anyhow::Error::msg(format!(
+            "{} is not in the manifest",
+            name
+        )));
+    }
+    Ok(manifest)
+}
+
+async fn import_batch(db: &Database, collection: &str, batch: Vec<Value>) -> Result<()> {
+    async fn insert<T: Serialize>(
+        db: &Database,
+        collection: &str,
+        documents: Vec<T>,
+    ) -> Result<()> {
+        db.collection::<T>(collection)
+            .insert_many(documents, None)
+            .await?;
+        Ok(())
+    }
+    fn typed<T: Contract>(batch: Vec<Value>) -> Result<Vec<T>> {
+        Ok(batch
+            .into_iter()
+            .map(serde_json::from_value)
+            .collect::<Result<_, _>>()?)
+    }
+    match collection {
+        SWARMS_COLL_NAME => insert(db, collection, typed::<Swarm>(batch)?).await,
+        HIVE_COLL_NAME => insert(db, collection, typed::<Hive>(batch)?).await,
+        SACRED_HIVE_COLL_NAME => insert(db, collection, typed::<SacredHive>(batch)?).await,
+        _ => {
+            let documents = batch
+                .into_iter()
+                .map(|document| match Bson::try_from(document)? {
+                    Bson::Document(document) => Ok(document),
+                    _ => Err(anyhow::Error::msg("document is not an object")),
+                })
+                .collect::<Result<Vec<Document>>>()?;
+            insert(db, collection, documents).await
+        }
+    }
+}
+
+/// Inserts the documents of the archive in batches. Collections are added
+/// to `written` before their first document is inserted.
+async fn import(path: &str, db: &Database, written: &mut Vec<String>) -> Result<()> {
+    let mut batch: Vec<Value> = vec![];
+    let mut batch_collection = String::new();
+    for line in read_lines(path)? {
+        if let ArchiveLine::Document {
+            collection,
+            document,
+        } = serde_json::from_str(&line?)?
+        {
+            if collection != batch_collection || batch.len() == BATCH_SIZE {
+                if !batch.is_empty() {
+                    import_batch(db, &batch_collection, std::mem::take(&mut batch)).await?;
+                }
+                if !written.contains(&collection) {
+                    written.push(collection.clone());
+                }
+                batch_collection = collection;
+            }
+            batch.push(document);
+        }
+    }
+    if !batch.is_empty() {
+        import_batch(db, &batch_collection, batch).await?;
+    }
+    Ok(())
+}
+
+/// Imports a validated archive into `db`, which must not hold any game or
+/// history documents. The schema version of the archive replaces the one
+/// of `db`, pending migrations run on the next start. A restore that fails
+/// halfway empties the collections it wrote to and puts the schema version
+/// of `db` back.
+pub async fn restore(path: &str, db: &Database) -> Result<Manifest> {
+    let manifest = validate(path)?;
+    for summary in &manifest.collections {
+        if summary.name != SCHEMA_VERSION_COLL_NAME
+            && db
+                .collection::<Document>(&summary.name)
+                .find_one(None, None)
+                .await?
+                .is_some()
+        {
+            return Err(anyhow::Error::msg(format!(
+                "{} is not empty in {}",
+                summary.name,
+                db.name()
+            )));
+        }
+    }
+    let versions = db.collection::<Document>(SCHEMA_VERSION_COLL_NAME);
+    let previous: Vec<Document> = versions.find(None, None).await?.try_collect().await?;
+    versions.delete_many(doc! {}, None).await?;
+    economy::create_snapshot_collection(db).await;
+
+    let mut written = vec![];
+    if let Err(e) = import(path, db, &mut written).await {
+        for collection in &written {
+            db.collection::<Document>(collection)
+                .delete_many(doc! {}, None)
+                .await?;
+        }
+        if !previous.is_empty() {
+            versions.insert_many(previous, None).await?;
+        }
+        return Err(e);
+    }
+    Ok(manifest)
+}
+
+fn print_manifest(manifest: &Manifest) {
+    for summary in &manifest.collections {
+        println!(
+            "{} {} documents {}",
+            summary.name, summary.documents, summary.sha256
+        );
+    }
+    println!(
+        "archive format {} schema version {}",
+        manifest.format_version, manifest.schema_version
+    );
+}
+
+/// `export <file>`: writes the configured database to `file`, as one
+/// consistent snapshot even while the server is running.
+pub async fn run_export(args: &[String], world: &World) -> Result<bool> {
+    let path = match args.first() {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: server export <file>");
+            return Ok(false);
+        }
+    };
+    print_manifest(&export(world, path).await?);
+    Ok(true)
+}
+
+/// `restore <file> [target_db]`: imports `file` into `target_db` (default
+/// the configured database) after validating it.
+pub async fn run_restore(args: &[String], db: Database) -> Result<bool> {
+    let path = match args.first() {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: server restore <file> [target_db]");
+            return Ok(false);
+        }
+    };
+    match restore(path, &db).await {
+        Ok(manifest) => {
+            print_manifest(&manifest);
+            println!("restored into {}", db.name());
+            Ok(true)
+        }
+        Err(e) => {
+            println!("restore failed: {}", e);
+            Ok(false)
+        }
+    }
+}
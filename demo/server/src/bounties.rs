@@ -0,0 +1,420 @@
+use {
+    super::{
+        ledger::{self, Change, Operation},
+        model::*,
+        notifications::{self, Event},
+        units::EGGS,
+        world::World,
+    },
+    futures::stream::TryStreamExt,
+    mongodb::{
+        bson::{doc, oid::ObjectId},
+        error::Error as MongoError,
+        options::{FindOptions, IndexOptions},
+        ClientSession, Database, IndexModel,
+    },
+    serde::{Deserialize, Serialize},
+    std::time::Duration,
+};
+
+pub const BOUNTIES_COLL_NAME: &str = "bounties";
+pub const BOUNTY_SECS: i64 = 7 * 24 * 3600;
+pub const BOUNTY_TICK_SECS: u64 = 60;
+pub const BOUNTY_PAGE_SIZE: i64 = 100;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BountyStatus {
+    Open,
+    Claimed,
+    Refunded,
+}
+
+/// Eggs `sponsor` put on the hive of `hive_pubkey`. The next swarm that
+/// wins a raid on the hive before `expires_at` (unix seconds) takes them,
+/// otherwise they go back to the sponsor. The eggs only count while the
+/// bounty is open.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Bounty {
+    pub id: String,
+    pub sponsor: String,
+    pub hive_pubkey: String,
+    pub eggs: i64,
+    pub placed_at: i64,
+    pub expires_at: i64,
+    pub status: BountyStatus,
+    pub claimed_by: Option<String>,
+}
+
+/// Body of `/bounty`, signed by the sponsor.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct PlaceBounty {
+    pub pubkey: String,
+    pub hive_pubkey: String,
+    pub eggs: i64,
+}
+
+impl KeyCloner for PlaceBounty {
+    fn clone_pubkey(&self) -> String {
+        self.pubkey.clone()
+    }
+}
+
+/// Order of `/bounty/list/{sort}`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BountySort {
+    /// Most eggs first.
+    Eggs,
+    /// Soonest expiry first.
+    Expiry,
+}
+
+pub enum BountyError {
+    InvalidPubkey,
+    NotFound,
+    NotEnoughTokens,
+    /// Sponsors can not put a bounty on their own hive.
+    OwnHive,
+    DBError(MongoError),
+}
+
+impl From<SearchError> for BountyError {
+    fn from(e: SearchError) -> BountyError {
+        match e {
+            SearchError::NotFound => BountyError::NotEnoughTokens,
+            SearchError::InvalidPubkey => BountyError::InvalidPubkey,
+            SearchError::DBError(e) => BountyError::DBError(e),
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for BountyError {
+    fn from(e: mongodb::error::Error) -> BountyError {
+        BountyError::DBError(e)
+    }
+}
+
+impl TransactionError for BountyError {
+    fn db_error(&self) -> Option<&MongoError> {
+        match self {
+            BountyError::DBError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Ledger change of the eggs a sponsor has in open bounties. The ledger
+/// tracks all bounties of a sponsor as one balance.
+pub fn escrow_change(sponsor: &str, before: i64, after: i64) -> Change {
+    let escrowed = |eggs| Swarm::empty(sponsor.to_string()).with(EGGS, eggs);
+    Change {
+        collection: BOUNTIES_COLL_NAME.to_string(),
+        before: escrowed(before),
+        after: escrowed(after),
+    }
+}
+
+async fn write_swarm(
+    swarm: &Swarm,
+    db: &Database,
+    session: &mut ClientSession,
+) -> Result<(), MongoError> {
+    db.collection::<Swarm>(Swarm::get_collection())
+        .replace_one_with_session(doc! { "pubkey": &swarm.pubkey }, swarm, None, session)
+        .await?;
+    Ok(())
+}
+
+/// Moves eggs of the swarm of the sponsor into a bounty on another hive
+/// for `bounty_secs`. The owner of the hive is notified.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        world = %world.name,
+        pubkey = %request.pubkey,
+        hive_pubkey = %request.hive_pubkey,
+        eggs = request.eggs,
+    )
+)]
+pub async fn place(request: PlaceBounty, world: &World) -> Result<Bounty, BountyError> {
+    if !pubkey_is_valid(&request.hive_pubkey) {
+        return Err(BountyError::InvalidPubkey);
+    }
+    if request.eggs <= 0 {
+        return Err(BountyError::NotEnoughTokens);
+    }
+    if request.pubkey == request.hive_pubkey {
+        return Err(BountyError::OwnHive);
+    }
+    let mut session = world.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    let db = world.db.clone();
+    let mut swarm =
+        db_search_with_session::<Swarm>(request.pubkey.clone(), db.clone(), &mut session).await?;
+    match db_search_with_session::<Hive>(request.hive_pubkey.clone(), db.clone(), &mut session)
+        .await
+    {
+        Err(SearchError::NotFound) => return Err(BountyError::NotFound),
+        result => result?,
+    };
+    if swarm.get(EGGS) < request.eggs {
+        tracing::info!(available = swarm.get(EGGS), "not enough eggs");
+        return Err(BountyError::NotEnoughTokens);
+    }
+    let before = swarm.clone();
+    swarm.change(EGGS, -request.eggs);
+    let now = chrono::Utc::now().timestamp();
+    let bounty = Bounty {
+        id: ObjectId::new().to_hex(),
+        sponsor: request.pubkey.clone(),
+        hive_pubkey: request.hive_pubkey.clone(),
+        eggs: request.eggs,
+        placed_at: now,
+        expires_at: now + world.config.bounty_secs,
+        status: BountyStatus::Open,
+        claimed_by: None,
+    };
+    write_swarm(&swarm, &db, &mut session).await?;
+    db.collection::<Bounty>(BOUNTIES_COLL_NAME)
+        .insert_one_with_session(&bounty, None, &mut session)
+        .await?;
+    ledger::append(
+        &db,
+        &mut session,
+        Operation::Bounty,
+        &swarm.pubkey,
+        vec![
+            Change::new(&before, &swarm),
+            escrow_change(&swarm.pubkey, 0, bounty.eggs),
+        ],
+    )
+    .await?;
+    notifications::notify_with_session(
+        &db,
+        &mut session,
+        &bounty.hive_pubkey,
+        Event::BountyPlaced {
+            bounty: bounty.id.clone(),
+            sponsor: bounty.sponsor.clone(),
+            eggs: bounty.eggs,
+            expires_at: bounty.expires_at,
+        },
+    )
+    .await?;
+    commit_with_retry(&mut session).await?;
+    tracing::info!(bounty = %bounty.id, expires_at = bounty.expires_at, "bounty placed");
+    Ok(bounty)
+}
+
+/// Pays the open bounties on the hive of `hive_pubkey` to `hunter` inside
+/// the transaction of a won raid. The sponsors are notified. Returns the
+/// eggs paid and their ledger changes, the eggs of `hunter` are left to the
+/// caller. Nothing is paid to the owner of the hive.
+pub async fn claim_with_session(
+    hive_pubkey: &str,
+    hunter: &str,
+    db: &Database,
+    session: &mut ClientSession,
+) -> Result<(i64, Vec<Change>), MongoError> {
+    if hunter == hive_pubkey {
+        return Ok((0, vec![]));
+    }
+    let filter = doc! {
+        "hive_pubkey": hive_pubkey,
+        "status": "open",
+        "expires_at": { "$gt": chrono::Utc::now().timestamp() },
+    };
+    let mut bounties = vec![];
+    let mut cursor = db
+        .collection::<Bounty>(BOUNTIES_COLL_NAME)
+        .find_with_session(filter.clone(), None, session)
+        .await?;
+    while let Some(bounty) = cursor.next(session).await.transpose()? {
+        bounties.push(bounty);
+    }
+    db.collection::<Bounty>(BOUNTIES_COLL_NAME)
+        .update_many_with_session(
+            filter,
+            doc! { "$set": { "status": "claimed", "claimed_by": hunter } },
+            None,
+            session,
+        )
+        .await?;
+    let mut paid = 0;
+    let mut changes = vec![];
+    for bounty in bounties {
+        paid += bounty.eggs;
+        changes.push(escrow_change(&bounty.sponsor, bounty.eggs, 0));
+        let event = Event::BountyClaimed {
+            bounty: bounty.id.clone(),
+            hive: bounty.hive_pubkey.clone(),
+            hunter: hunter.to_string(),
+            eggs: bounty.eggs,
+        };
+        notifications::notify_with_session(db, session, &bounty.sponsor, event).await?;
+    }
+    Ok((paid, changes))
+}
+
+/// Gives the eggs of an expired bounty back to its sponsor.
+#[tracing::instrument(skip(world), fields(world = %world.name))]
+pub async fn refund(id: &str, world: &World) -> Result<Bounty, BountyError> {
+    let mut session = world.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    let db = world.db.clone();
+    let filter = doc! {
+        "id": id,
+        "status": "open",
+        "expires_at": { "$lte": chrono::Utc::now().timestamp() },
+    };
+    let mut bounty = db
+        .collection::<Bounty>(BOUNTIES_COLL_NAME)
+        .find_one_with_session(filter, None, &mut session)
+        .await?
+        .ok_or(BountyError::NotFound)?;
+    let mut swarm =
+        db_search_with_session::<Swarm>(bounty.sponsor.clone(), db.clone(), &mut session).await?;
+    let before = swarm.clone();
+    swarm.change(EGGS, bounty.eggs);
+    bounty.status = BountyStatus::Refunded;
+    write_swarm(&swarm, &db, &mut session).await?;
+    db.collection::<Bounty>(BOUNTIES_COLL_NAME)
+        .replace_one_with_session(doc! { "id": &bounty.id }, &bounty, None, &mut session)
+        .await?;
+    ledger::append(
+        &db,
+        &mut session,
+        Operation::Refund,
+        &swarm.pubkey,
+        vec![
+            Change::new(&before, &swarm),
+            escrow_change(&swarm.pubkey, bounty.eggs, 0),
+        ],
+    )
+    .await?;
+    notifications::notify_with_session(
+        &db,
+        &mut session,
+        &bounty.sponsor,
+        Event::BountyRefunded {
+            bounty: bounty.id.clone(),
+            eggs: bounty.eggs,
+        },
+    )
+    .await?;
+    commit_with_retry(&mut session).await?;
+    tracing::info!(eggs = bounty.eggs, "bounty refunded");
+    Ok(bounty)
+}
+
+/// Refunds the expired bounties and returns how many were refunded.
+pub async fn refund_expired(world: &World) -> Result<usize, MongoError> {
+    let filter = doc! {
+        "status": "open",
+        "expires_at": { "$lte": chrono::Utc::now().timestamp() },
+    };
+    let options = FindOptions::builder()
+        .sort(doc! { "expires_at": 1 })
+        .limit(BOUNTY_PAGE_SIZE)
+        .build();
+    let expired: Vec<Bounty> = world
+        .db
+        .collection::<Bounty>(BOUNTIES_COLL_NAME)
+        .find(filter, options)
+        .await?
+        .try_collect()
+        .await?;
+    let mut refunded = 0;
+    for bounty in expired {
+        match refund(&bounty.id, world).await {
+            Ok(_) => refunded += 1,
+            // claimed or refunded by another server in the meantime
+            Err(BountyError::NotFound) => (),
+            Err(BountyError::DBError(e)) => return Err(e),
+            Err(_) => tracing::warn!(bounty = %bounty.id, "bounty can not be refunded"),
+        }
+    }
+    Ok(refunded)
+}
+
+/// Refunds expired bounties every `BOUNTY_TICK_SECS`.
+pub async fn bounty_task(world: World) {
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(BOUNTY_TICK_SECS));
+    loop {
+        interval.tick().await;
+        match refund_expired(&world).await {
+            Ok(0) => (),
+            Ok(refunded) => tracing::info!(world = %world.name, refunded, "bounties refunded"),
+            Err(e) => tracing::warn!(world = %world.name, error = %e, "refunding bounties failed"),
+        }
+    }
+}
+
+/// Open bounties in the order of `sort`.
+#[tracing::instrument(skip(db))]
+pub async fn open_bounties(sort: BountySort, db: Database) -> Result<Vec<Bounty>, SearchError> {
+    let filter = doc! {
+        "status": "open",
+        "expires_at": { "$gt": chrono::Utc::now().timestamp() },
+    };
+    let order = match sort {
+        BountySort::Eggs => doc! { "eggs": -1, "expires_at": 1 },
+        BountySort::Expiry => doc! { "expires_at": 1, "eggs": -1 },
+    };
+    let options = FindOptions::builder()
+        .sort(order)
+        .limit(BOUNTY_PAGE_SIZE)
+        .build();
+    Ok(db
+        .collection::<Bounty>(BOUNTIES_COLL_NAME)
+        .find(filter, options)
+        .await?
+        .try_collect()
+        .await?)
+}
+
+/// Adds the eggs each sponsor has in open bounties to `state`.
+pub async fn load_bounties(
+    db: &Database,
+    pubkey: Option<&str>,
+    state: &mut ledger::State,
+) -> Result<(), MongoError> {
+    let filter = pubkey.map(|pubkey| doc! { "sponsor": pubkey });
+    let mut cursor = db
+        .collection::<Bounty>(BOUNTIES_COLL_NAME)
+        .find(filter, None)
+        .await?;
+    while let Some(bounty) = cursor.try_next().await? {
+        let open = bounty.status == BountyStatus::Open;
+        state
+            .entry((BOUNTIES_COLL_NAME.to_string(), bounty.sponsor.clone()))
+            .or_insert_with(|| Swarm::empty(bounty.sponsor.clone()))
+            .change(EGGS, open as i64 * bounty.eggs);
+    }
+    Ok(())
+}
+
+pub async fn create_bounty_indexes(db: &Database) {
+    let bounties = db.collection::<Bounty>(BOUNTIES_COLL_NAME);
+    let options = IndexOptions::builder().unique(true).build();
+    let model = IndexModel::builder()
+        .keys(doc! { "id": 1 })
+        .options(options)
+        .build();
+    bounties
+        .create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+    for keys in [
+        doc! { "status": 1, "expires_at": 1 },
+        doc! { "status": 1, "eggs": -1 },
+        doc! { "hive_pubkey": 1, "status": 1 },
+        doc! { "sponsor": 1 },
+    ] {
+        bounties
+            .create_index(IndexModel::builder().keys(keys).build(), None)
+            .await
+            .expect("creating an index should succeed");
+    }
+}
@@ -0,0 +1,93 @@
+use {
+    actix_cors::Cors,
+    rustls::{Certificate, PrivateKey, ServerConfig as TlsConfig},
+    std::{fs::File, io::BufReader, path::PathBuf},
+};
+
+/// Runtime settings of the HTTP server, read from the environment.
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    pub allowed_origins: Vec<String>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub body_limit: usize,
+    pub client_dir: Option<PathBuf>,
+    pub worlds_file: Option<PathBuf>,
+}
+
+impl ServerConfig {
+    /// Reads `BIND_ADDRESS`, `PORT`, `ALLOWED_ORIGINS` (comma separated),
+    /// `TLS_CERT`, `TLS_KEY`, `BODY_LIMIT` (bytes), `CLIENT_DIR` and
+    /// `WORLDS_FILE`.
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        ServerConfig {
+            host: var("BIND_ADDRESS").unwrap_or_else(|| "127.0.0.1".into()),
+            port: var("PORT").map_or(9000, |p| p.parse().expect("PORT is not a number")),
+            allowed_origins: var("ALLOWED_ORIGINS")
+                .map(|origins| origins.split(',').map(|o| o.trim().to_string()).collect())
+                .unwrap_or_default(),
+            tls_cert: var("TLS_CERT").map(PathBuf::from),
+            tls_key: var("TLS_KEY").map(PathBuf::from),
+            body_limit: var("BODY_LIMIT").map_or(16 * 1024, |l| {
+                l.parse().expect("BODY_LIMIT is not a number")
+            }),
+            client_dir: var("CLIENT_DIR").map(PathBuf::from),
+            worlds_file: var("WORLDS_FILE").map(PathBuf::from),
+        }
+    }
+
+    /// The API lives under `/backend` when the client bundle is served from
+    /// the same process, which is the path the client already proxies to.
+    pub fn api_prefix(&self) -> &'static str {
+        match self.client_dir {
+            Some(_) => "/backend",
+            None => "",
+        }
+    }
+
+    /// Without allowed origins no CORS headers are sent, so browsers only
+    /// accept same origin requests.
+    pub fn cors(&self) -> Cors {
+        let cors = Cors::default()
+            .allowed_methods(vec!["GET", "POST"])
+            .allow_any_header()
+            .expose_headers(vec![super::logging::REQUEST_ID_HEADER])
+            .max_age(3600);
+        match self.allowed_origins.iter().any(|o| o == "*") {
+            true => cors.allow_any_origin(),
+            false => self
+                .allowed_origins
+                .iter()
+                .fold(cors, |cors, origin| cors.allowed_origin(origin)),
+        }
+    }
+
+    /// Loads the rustls configuration if both `TLS_CERT` and `TLS_KEY` are set.
+    pub fn tls(&self) -> std::io::Result<Option<TlsConfig>> {
+        let (cert_path, key_path) = match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => (cert, key),
+            (None, None) => return Ok(None),
+            _ => return Err(invalid_data("TLS_CERT and TLS_KEY must be set together")),
+        };
+        let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))?
+            .into_iter()
+            .map(Certificate)
+            .collect();
+        let key = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(key_path)?))?
+            .into_iter()
+            .next()
+            .ok_or_else(|| invalid_data("TLS_KEY has no PKCS8 private key"))?;
+        let config = TlsConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certs, PrivateKey(key))
+            .map_err(|e| invalid_data(&e.to_string()))?;
+        Ok(Some(config))
+    }
+}
+
+fn invalid_data(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string())
+}
@@ -0,0 +1,273 @@
+use {
+    super::model::{pubkey_is_valid, KeyCloner},
+    mongodb::{
+        bson::{doc, to_document},
+        error::Error as MongoError,
+        options::IndexOptions,
+        Database, IndexModel,
+    },
+    serde::{Deserialize, Serialize},
+};
+
+pub const DELEGATIONS_COLL_NAME: &str = "delegations";
+
+/// Signed actions that a master key can hand over to a delegate key.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    SacredHiveStake,
+    SacredHiveUnstake,
+    HiveStake,
+    HiveUnstake,
+    Hatch,
+    Attack,
+    /// Calling for help and reinforcing hives under attack.
+    Defend,
+    Scout,
+    Fortify,
+    Bounty,
+    /// Listing and withdrawing berserkers for hire.
+    Mercenaries,
+}
+
+/// Certificate signed by the master key (`pubkey`) that authorizes the
+/// ephemeral `delegate` key until `expires_at` (unix seconds) for `actions`.
+/// `max_berserkers` and `max_eggs` cap what all requests of the delegate
+/// take from the swarm together.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Delegation {
+    pub pubkey: String,
+    pub delegate: String,
+    pub expires_at: i64,
+    pub actions: Vec<Action>,
+    pub max_berserkers: Option<i64>,
+    pub max_eggs: Option<i64>,
+    /// Berserkers and eggs the delegate spent so far, zero in a new
+    /// certificate.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub spent_berserkers: i64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub spent_eggs: i64,
+}
+
+fn is_zero(amount: &i64) -> bool {
+    *amount == 0
+}
+
+/// Berserkers and eggs a delegated request takes from the swarm: the
+/// berserkers sent to an attack, listed for hire or paid to scout and the
+/// eggs hatched, staked or paid.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Spend {
+    pub berserkers: i64,
+    pub eggs: i64,
+}
+
+impl Spend {
+    pub fn berserkers(berserkers: i64) -> Self {
+        Spend {
+            berserkers,
+            ..Spend::default()
+        }
+    }
+
+    pub fn eggs(eggs: i64) -> Self {
+        Spend {
+            eggs,
+            ..Spend::default()
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RevokeDelegation {
+    pub pubkey: String,
+    pub delegate: String,
+}
+
+pub enum DelegationError {
+    InvalidPubkey,
+    InvalidCertificate,
+    AlreadyExists,
+    NotFound,
+    DBError(MongoError),
+}
+
+impl From<mongodb::error::Error> for DelegationError {
+    fn from(e: mongodb::error::Error) -> DelegationError {
+        DelegationError::DBError(e)
+    }
+}
+
+impl KeyCloner for Delegation {
+    fn clone_pubkey(&self) -> String {
+        self.pubkey.clone()
+    }
+}
+
+impl KeyCloner for RevokeDelegation {
+    fn clone_pubkey(&self) -> String {
+        self.pubkey.clone()
+    }
+}
+
+impl Delegation {
+    /// Checks that the delegate may perform `action` at `now`.
+    pub fn permits(&self, action: Action, now: i64) -> bool {
+        now < self.expires_at && self.actions.contains(&action)
+    }
+}
+
+/// Adds `spend` to what the delegate of `delegation` spent if the totals
+/// stay within its caps. Checking and adding is one write, so concurrent
+/// requests can not spend past the caps. Returns whether it was added.
+pub async fn reserve(
+    delegation: &Delegation,
+    spend: Spend,
+    db: &Database,
+) -> Result<bool, MongoError> {
+    let spend = Spend {
+        berserkers: spend.berserkers.max(0),
+        eggs: spend.eggs.max(0),
+    };
+    let mut caps = vec![];
+    for (field, amount, cap) in [
+        (
+            "$spent_berserkers",
+            spend.berserkers,
+            delegation.max_berserkers,
+        ),
+        ("$spent_eggs", spend.eggs, delegation.max_eggs),
+    ] {
+        if let Some(cap) = cap {
+            caps.push(doc! { "$lte": [{ "$add": [{ "$ifNull": [field, 0] }, amount] }, cap] });
+        }
+    }
+    let mut filter = doc! { "delegate": &delegation.delegate, "revoked": false };
+    if !caps.is_empty() {
+        filter.insert("$expr", doc! { "$and": caps });
+    }
+    let result = db
+        .collection::<Delegation>(DELEGATIONS_COLL_NAME)
+        .update_one(
+            filter,
+            doc! { "$inc": { "spent_berserkers": spend.berserkers, "spent_eggs": spend.eggs } },
+            None,
+        )
+        .await?;
+    Ok(result.matched_count == 1)
+}
+
+/// What `reserve` added for a request that was not answered yet.
+pub struct Reservation {
+    pub delegate: String,
+    pub spend: Spend,
+    pub db: Database,
+}
+
+/// Gives back what `reserve` added for a request that was rejected.
+pub async fn release(delegate: &str, spend: Spend, db: &Database) -> Result<(), MongoError> {
+    db.collection::<Delegation>(DELEGATIONS_COLL_NAME)
+        .update_one(
+            doc! { "delegate": delegate },
+            doc! {
+                "$inc": {
+                    "spent_berserkers": -spend.berserkers.max(0),
+                    "spent_eggs": -spend.eggs.max(0),
+                }
+            },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, fields(pubkey = %delegation.pubkey, delegate = %delegation.delegate))]
+pub async fn create_delegation(
+    delegation: Delegation,
+    db: Database,
+) -> Result<(), DelegationError> {
+    if !pubkey_is_valid(&delegation.pubkey) || !pubkey_is_valid(&delegation.delegate) {
+        return Err(DelegationError::InvalidPubkey);
+    }
+    if delegation.pubkey == delegation.delegate
+        || delegation.expires_at <= chrono::Utc::now().timestamp()
+        || delegation.actions.is_empty()
+        || delegation.spent_berserkers != 0
+        || delegation.spent_eggs != 0
+    {
+        return Err(DelegationError::InvalidCertificate);
+    }
+    let collection = db.collection::<Delegation>(DELEGATIONS_COLL_NAME);
+    // revoked certificates are kept so that a replayed request can not
+    // bring the same delegate key back to life
+    match collection
+        .find_one(doc! { "delegate": &delegation.delegate }, None)
+        .await?
+    {
+        Some(_) => Err(DelegationError::AlreadyExists),
+        None => {
+            let mut document = to_document(&delegation).map_err(MongoError::from)?;
+            document.insert("revoked", false);
+            db.collection(DELEGATIONS_COLL_NAME)
+                .insert_one(document, None)
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+#[tracing::instrument(skip_all, fields(pubkey = %request.pubkey, delegate = %request.delegate))]
+pub async fn revoke_delegation(
+    request: RevokeDelegation,
+    db: Database,
+) -> Result<(), DelegationError> {
+    if !pubkey_is_valid(&request.pubkey) || !pubkey_is_valid(&request.delegate) {
+        return Err(DelegationError::InvalidPubkey);
+    }
+    let result = db
+        .collection::<Delegation>(DELEGATIONS_COLL_NAME)
+        .update_one(
+            doc! { "pubkey": &request.pubkey, "delegate": &request.delegate, "revoked": false },
+            doc! { "$set": { "revoked": true } },
+            None,
+        )
+        .await?;
+    match result.matched_count {
+        0 => Err(DelegationError::NotFound),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the active (not revoked) delegation of `pubkey` to `delegate`.
+#[tracing::instrument(skip(db))]
+pub async fn db_search_delegation(
+    pubkey: String,
+    delegate: String,
+    db: Database,
+) -> Result<Delegation, DelegationError> {
+    match db
+        .collection::<Delegation>(DELEGATIONS_COLL_NAME)
+        .find_one(
+            doc! { "pubkey": pubkey, "delegate": delegate, "revoked": false },
+            None,
+        )
+        .await?
+    {
+        Some(delegation) => Ok(delegation),
+        None => Err(DelegationError::NotFound),
+    }
+}
+
+pub async fn create_delegation_indexes(db: &Database) {
+    let options = IndexOptions::builder().unique(true).build();
+    let model = IndexModel::builder()
+        .keys(doc! { "delegate": 1 })
+        .options(options)
+        .build();
+
+    db.collection::<Delegation>(DELEGATIONS_COLL_NAME)
+        .create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+}
@@ -0,0 +1,585 @@
+use {
+    super::{
+        bounties::BOUNTIES_COLL_NAME,
+        incubation::HATCH_JOBS_COLL_NAME,
+        ledger::{self, LedgerEntry, Operation, LEDGER_COLL_NAME},
+        locks::{self, STAKE_LOCKS_COLL_NAME},
+        march::MARCHES_COLL_NAME,
+        mercenaries::MERCENARIES_COLL_NAME,
+        metrics,
+        model::*,
+        units::{self, Balances, BERSERKERS, EGGS, SACRED_QUEENS},
+        world::{GameConfig, World},
+    },
+    futures::stream::TryStreamExt,
+    mongodb::{
+        bson::{doc, from_document, DateTime, Document},
+        error::{Error as MongoError, ErrorKind},
+        options::FindOptions,
+        Database,
+    },
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        time::Duration,
+    },
+};
+
+pub const SNAPSHOTS_COLL_NAME: &str = "economySnapshots";
+pub const SNAPSHOT_INTERVAL_SECS: u64 = 3600;
+pub const SNAPSHOT_PAGE_SIZE: i64 = 1000;
+
+/// A broken invariant. `subject` names the account, ledger entry or token
+/// supply the rule was checked against.
+#[derive(Debug, PartialEq)]
+pub struct Violation {
+    pub subject: String,
+    pub message: String,
+}
+
+impl Violation {
+    fn new(subject: impl Into<String>, message: impl Into<String>) -> Self {
+        Violation {
+            subject: subject.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Sum of `after - before` over all changes of an entry.
+fn entry_delta(entry: &LedgerEntry) -> Swarm {
+    let mut delta = Swarm::empty(entry.pubkey.clone());
+    for change in &entry.changes {
+        delta.add(&change.after);
+        delta.add(&change.before.negative());
+    }
+    delta
+}
+
+/// Checks that a ledger entry only created or destroyed the tokens its
+/// operation is allowed to.
+pub fn check_entry(entry: &LedgerEntry, config: &GameConfig) -> Option<String> {
+    let delta = entry_delta(entry);
+    // units of the registry, hatched from eggs
+    let units = || {
+        delta
+            .balances
+            .iter()
+            .filter(|(id, _)| ![SACRED_QUEENS, EGGS].contains(&id.as_str()))
+    };
+    let hatched: i64 = units().map(|(_, count)| count).sum();
+    let ok = match entry.operation {
+        Operation::Genesis => !delta.is_negative(),
+        Operation::Airdrop => {
+            delta
+                == Swarm::empty(entry.pubkey.clone())
+                    .with(SACRED_QUEENS, config.airdrop_sacred_queens)
+        }
+        Operation::Stake
+        | Operation::Unstake
+        | Operation::March
+        | Operation::Recall
+        | Operation::Reinforce => delta == Swarm::empty(entry.pubkey.clone()),
+        Operation::Incubate => {
+            delta == Swarm::empty(entry.pubkey.clone())
+                && entry.changes.iter().all(|change| {
+                    [SWARMS_COLL_NAME, HATCH_JOBS_COLL_NAME].contains(&change.collection.as_str())
+                })
+        }
+        // bounties only move eggs between the sponsor and the escrow
+        Operation::Bounty | Operation::Refund => {
+            delta == Swarm::empty(entry.pubkey.clone())
+                && entry.changes.iter().all(|change| {
+                    [SWARMS_COLL_NAME, BOUNTIES_COLL_NAME].contains(&change.collection.as_str())
+                })
+        }
+        // listed mercenaries only move between the swarm and the offers
+        Operation::Enlist | Operation::Discharge => {
+            delta == Swarm::empty(entry.pubkey.clone())
+                && entry.changes.iter().all(|change| {
+                    [SWARMS_COLL_NAME, MERCENARIES_COLL_NAME].contains(&change.collection.as_str())
+                })
+        }
+        // locked sacred queens only move between the swarm and the locks
+        Operation::Lock | Operation::Unlock => {
+            delta == Swarm::empty(entry.pubkey.clone())
+                && entry.changes.iter().all(|change| {
+                    [SWARMS_COLL_NAME, STAKE_LOCKS_COLL_NAME].contains(&change.collection.as_str())
+                })
+        }
+        // locked sacred queens lay at least as many eggs as staked ones and
+        // at most as many as the best lock yields
+        Operation::Trigger => {
+            let staked = |collection: &str| -> i64 {
+                entry
+                    .changes
+                    .iter()
+                    .filter(|change| change.collection == collection)
+                    .map(|change| change.before.get(SACRED_QUEENS))
+                    .sum()
+            };
+            let locked = staked(STAKE_LOCKS_COLL_NAME);
+            let base = (staked(SACRED_HIVE_COLL_NAME) + locked) * config.eggs_per_sacred_queen;
+            let bonus = locked
+                * config.eggs_per_sacred_queen
+                * (locks::max_yield_percent(&config.stake_locks) - 100)
+                / 100;
+            delta == Swarm::empty(entry.pubkey.clone()).with(EGGS, delta.get(EGGS))
+                && (base..=base + bonus).contains(&delta.get(EGGS))
+        }
+        Operation::Hatch => {
+            delta.get(SACRED_QUEENS) == 0
+                && delta.veterans.is_empty()
+                && units().all(|(_, count)| *count >= 0)
+                && delta.get(EGGS) == -hatched
+        }
+        Operation::Produce => {
+            delta == Swarm::empty(entry.pubkey.clone()).with(EGGS, delta.get(EGGS).max(0))
+                && entry
+                    .changes
+                    .iter()
+                    .all(|change| change.collection == HIVE_COLL_NAME)
+        }
+        // scouting burns its cost, paid in berserkers or eggs
+        Operation::Scout => {
+            let paid = |berserkers: i64, eggs: i64| {
+                Swarm::empty(entry.pubkey.clone())
+                    .with(BERSERKERS, -berserkers)
+                    .with(EGGS, -eggs)
+            };
+            (delta == paid(config.scout_berserkers, 0) || delta == paid(0, config.scout_eggs))
+                && entry
+                    .changes
+                    .iter()
+                    .all(|change| change.collection == SWARMS_COLL_NAME)
+        }
+        // buildings burn their price in eggs
+        Operation::Fortify => {
+            delta == Swarm::empty(entry.pubkey.clone()).with(EGGS, delta.get(EGGS))
+                && config.fortifications.is_cost(-delta.get(EGGS))
+                && entry
+                    .changes
+                    .iter()
+                    .all(|change| change.collection == SWARMS_COLL_NAME)
+        }
+        // berserkers sent to an attack and defeated defenders and
+        // reinforcements die, survivors are promoted and the eggs only move
+        // from the hive, the bounties on it and the mercenary price held by
+        // the march to the swarms
+        Operation::Attack => {
+            delta.get(SACRED_QUEENS) == 0
+                && delta.all_berserkers() <= 0
+                && units()
+                    .filter(|(id, _)| id.as_str() != BERSERKERS)
+                    .all(|(_, count)| *count <= 0)
+                && delta.get(EGGS) == 0
+        }
+    };
+    match ok {
+        true => None,
+        false => Some(format!(
+            "{:?} changed the supply by {}",
+            entry.operation,
+            serde_json::to_string(&delta).unwrap_or_default()
+        )),
+    }
+}
+
+/// Reports negative balances of `T` and collects the pubkeys that own one.
+async fn check_documents<T: Contract>(
+    db: &Database,
+    owners: &mut BTreeMap<String, BTreeSet<&'static str>>,
+    violations: &mut Vec<Violation>,
+) -> Result<(), MongoError> {
+    let mut cursor = db
+        .collection::<T>(T::get_collection())
+        .find(None, None)
+        .await?;
+    while let Some(t) = cursor.try_next().await? {
+        if t.is_negative() {
+            violations.push(Violation::new(
+                t.clone_pubkey(),
+                format!("negative balance in {}", T::get_collection()),
+            ));
+        }
+        owners
+            .entry(t.clone_pubkey())
+            .or_default()
+            .insert(T::get_collection());
+    }
+    Ok(())
+}
+
+/// Per account invariants: no negative balances and a swarm, a hive and a
+/// sacred hive for every pubkey.
+pub async fn check_accounts(db: &Database) -> Result<Vec<Violation>, MongoError> {
+    let mut owners = BTreeMap::new();
+    let mut violations = vec![];
+    check_documents::<Swarm>(db, &mut owners, &mut violations).await?;
+    check_documents::<Hive>(db, &mut owners, &mut violations).await?;
+    check_documents::<SacredHive>(db, &mut owners, &mut violations).await?;
+    for (pubkey, collections) in owners {
+        for collection in [SWARMS_COLL_NAME, HIVE_COLL_NAME, SACRED_HIVE_COLL_NAME] {
+            if !collections.contains(collection) {
+                violations.push(Violation::new(
+                    pubkey.clone(),
+                    format!("missing document in {}", collection),
+                ));
+            }
+        }
+    }
+    Ok(violations)
+}
+
+/// Global conservation: every ledger entry follows the rules of its
+/// operation and the live supply equals the supply recorded in the ledger.
+pub async fn check_conservation(
+    db: &Database,
+    config: &GameConfig,
+) -> Result<Vec<Violation>, MongoError> {
+    let mut violations = vec![];
+    let options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+    let mut cursor = db
+        .collection::<LedgerEntry>(LEDGER_COLL_NAME)
+        .find(None, options)
+        .await?;
+    let mut recorded = Swarm::empty(String::new());
+    while let Some(entry) = cursor.try_next().await? {
+        if let Some(message) = check_entry(&entry, config) {
+            violations.push(Violation::new(
+                format!("ledger {} {}", entry.timestamp, entry.pubkey),
+                message,
+            ));
+        }
+        recorded.add(&entry_delta(&entry));
+    }
+    let mut live = Swarm::empty(String::new());
+    for balance in ledger::live_state(db, None).await?.values() {
+        live.add(balance);
+    }
+    recorded.pubkey = live.pubkey.clone();
+    if recorded != live {
+        violations.push(Violation::new(
+            "supply",
+            format!(
+                "ledger records {} but the collections hold {}",
+                serde_json::to_string(&recorded).unwrap_or_default(),
+                serde_json::to_string(&live).unwrap_or_default()
+            ),
+        ));
+    }
+    Ok(violations)
+}
+
+/// `check-economy`: prints every broken invariant. Returns false if any
+/// were found.
+pub async fn run_check(world: &World) -> Result<bool, MongoError> {
+    let mut violations = check_accounts(&world.db).await?;
+    violations.extend(check_conservation(&world.db, &world.config).await?);
+    for violation in &violations {
+        println!("{}: {}", violation.subject, violation.message);
+    }
+    println!("{} violations", violations.len());
+    Ok(violations.is_empty())
+}
+
+/// Total of each token and unit in one collection, keyed by unit id.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct Supply {
+    /// Veterans of all tiers.
+    pub veterans: i64,
+    #[serde(flatten, with = "units::flat")]
+    pub balances: Balances,
+}
+
+impl Holdings for Supply {
+    fn holdings(&self) -> &Balances {
+        &self.balances
+    }
+    fn holdings_mut(&mut self) -> &mut Balances {
+        &mut self.balances
+    }
+}
+
+impl Supply {
+    fn add(&mut self, addend: &Supply) {
+        self.veterans += addend.veterans;
+        units::add(&mut self.balances, &addend.balances);
+    }
+
+    /// Adds the balances of one document, in the `as_swarm` form.
+    fn add_balance(&mut self, balance: &Swarm) {
+        self.veterans += balance.veterans.iter().sum::<i64>();
+        units::add(&mut self.balances, &balance.balances);
+    }
+}
+
+/// Supply held in swarms, staked in hives and sacred hives, incubating in
+/// the hatchery, on the road, in open bounties, listed for hire and in
+/// stake locks, together with the egg flows and raid volume recorded in the ledger.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct EconomyStats {
+    pub total: Supply,
+    pub swarms: Supply,
+    pub hives: Supply,
+    pub sacred_hives: Supply,
+    #[serde(default)]
+    pub incubating: Supply,
+    #[serde(default)]
+    pub marching: Supply,
+    #[serde(default)]
+    pub bounties: Supply,
+    #[serde(default)]
+    pub mercenaries: Supply,
+    #[serde(default)]
+    pub locked: Supply,
+    pub eggs_minted: i64,
+    pub eggs_produced: i64,
+    pub eggs_hatched: i64,
+    pub raids: i64,
+    pub raids_won: i64,
+    pub eggs_looted: i64,
+    pub berserkers_lost: i64,
+    #[serde(default)]
+    pub scouts: i64,
+    #[serde(default)]
+    pub eggs_scouted: i64,
+    #[serde(default)]
+    pub eggs_fortified: i64,
+    /// Eggs paid to the winners of raids on hives with bounties.
+    #[serde(default)]
+    pub bounties_paid: i64,
+}
+
+/// Stats at `timestamp` (unix milliseconds).
+#[derive(Deserialize, Serialize)]
+pub struct Snapshot {
+    pub timestamp: i64,
+    pub stats: EconomyStats,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SnapshotDocument {
+    timestamp: DateTime,
+    stats: EconomyStats,
+}
+
+#[derive(Deserialize)]
+struct FlowKey {
+    operation: Operation,
+    collection: String,
+}
+
+/// Egg and berserker deltas of one operation on one collection.
+#[derive(Deserialize)]
+struct Flow {
+    #[serde(rename = "_id")]
+    key: FlowKey,
+    entries: i64,
+    lost_eggs: i64,
+    eggs: i64,
+    berserkers: i64,
+}
+
+fn delta(token: &str) -> Document {
+    doc! {
+        "$sum": {
+            "$subtract": [
+                format!("$changes.after.{}", token),
+                format!("$changes.before.{}", token),
+            ]
+        }
+    }
+}
+
+/// Like `delta` for the berserkers of all tiers.
+fn berserkers_delta() -> Document {
+    let all = |side: &str| {
+        doc! {
+            "$add": [
+                format!("$changes.{}.berserkers", side),
+                { "$sum": format!("$changes.{}.veterans", side) },
+            ]
+        }
+    };
+    doc! { "$sum": { "$subtract": [all("after"), all("before")] } }
+}
+
+async fn flows(db: &Database) -> Result<Vec<Flow>, MongoError> {
+    let pipeline = [
+        doc! { "$match": { "operation": { "$in": ["trigger", "produce", "hatch", "attack", "scout", "fortify"] } } },
+        doc! { "$unwind": "$changes" },
+        doc! {
+            "$group": {
+                "_id": {
+                    "entry": "$_id",
+                    "operation": "$operation",
+                    "collection": "$changes.collection",
+                },
+                "eggs": delta("eggs"),
+                "berserkers": berserkers_delta(),
+            }
+        },
+        doc! {
+            "$group": {
+                "_id": {
+                    "operation": "$_id.operation",
+                    "collection": "$_id.collection",
+                },
+                "entries": { "$sum": 1 },
+                "lost_eggs": { "$sum": { "$cond": [{ "$lt": ["$eggs", 0] }, 1, 0] } },
+                "eggs": { "$sum": "$eggs" },
+                "berserkers": { "$sum": "$berserkers" },
+            }
+        },
+    ];
+    let mut cursor = db
+        .collection::<LedgerEntry>(LEDGER_COLL_NAME)
+        .aggregate(pipeline, None)
+        .await?;
+    let mut flows = vec![];
+    while let Some(flow) = cursor.try_next().await? {
+        flows.push(from_document(flow)?);
+    }
+    Ok(flows)
+}
+
+pub async fn stats(db: &Database) -> Result<EconomyStats, MongoError> {
+    let mut supplies: BTreeMap<String, Supply> = BTreeMap::new();
+    for ((collection, _), balance) in ledger::live_state(db, None).await? {
+        supplies
+            .entry(collection)
+            .or_default()
+            .add_balance(&balance);
+    }
+    let mut supply = |collection: &str| supplies.remove(collection).unwrap_or_default();
+    let mut stats = EconomyStats {
+        swarms: supply(SWARMS_COLL_NAME),
+        hives: supply(HIVE_COLL_NAME),
+        sacred_hives: supply(SACRED_HIVE_COLL_NAME),
+        incubating: supply(HATCH_JOBS_COLL_NAME),
+        marching: supply(MARCHES_COLL_NAME),
+        bounties: supply(BOUNTIES_COLL_NAME),
+        mercenaries: supply(MERCENARIES_COLL_NAME),
+        locked: supply(STAKE_LOCKS_COLL_NAME),
+        ..EconomyStats::default()
+    };
+    for supply in [
+        &stats.swarms,
+        &stats.hives,
+        &stats.sacred_hives,
+        &stats.incubating,
+        &stats.marching,
+        &stats.bounties,
+        &stats.mercenaries,
+        &stats.locked,
+    ] {
+        stats.total.add(supply);
+    }
+    for flow in flows(db).await? {
+        match (flow.key.operation, flow.key.collection.as_str()) {
+            (Operation::Trigger, SACRED_HIVE_COLL_NAME) => stats.eggs_minted += flow.eggs,
+            (Operation::Produce, HIVE_COLL_NAME) => stats.eggs_produced += flow.eggs,
+            // hatching took the eggs from the swarm before the hatchery
+            (Operation::Hatch, SWARMS_COLL_NAME | HATCH_JOBS_COLL_NAME) => {
+                stats.eggs_hatched -= flow.eggs
+            }
+            (Operation::Attack, SWARMS_COLL_NAME) => {
+                stats.raids += flow.entries;
+                stats.eggs_looted += flow.eggs;
+                stats.berserkers_lost -= flow.berserkers;
+            }
+            // marching berserkers left the swarm when they were launched and
+            // the mercenary price held by the march is not loot
+            (Operation::Attack, MARCHES_COLL_NAME) => {
+                stats.berserkers_lost -= flow.berserkers;
+                stats.eggs_looted += flow.eggs;
+            }
+            (Operation::Attack, HIVE_COLL_NAME) => stats.raids_won += flow.lost_eggs,
+            // bounty payouts reached the swarm with the loot
+            (Operation::Attack, BOUNTIES_COLL_NAME) => {
+                stats.bounties_paid -= flow.eggs;
+                stats.eggs_looted += flow.eggs;
+            }
+            (Operation::Scout, SWARMS_COLL_NAME) => {
+                stats.scouts += flow.entries;
+                stats.eggs_scouted -= flow.eggs;
+                stats.berserkers_lost -= flow.berserkers;
+            }
+            (Operation::Fortify, SWARMS_COLL_NAME) => stats.eggs_fortified -= flow.eggs,
+            _ => (),
+        }
+    }
+    Ok(stats)
+}
+
+/// Creates the time-series collection of the hourly snapshots.
+pub async fn create_snapshot_collection(db: &Database) {
+    let command = doc! {
+        "create": SNAPSHOTS_COLL_NAME,
+        "timeseries": { "timeField": "timestamp", "granularity": "hours" },
+    };
+    match db.run_command(command, None).await {
+        Ok(_) => (),
+        // NamespaceExists
+        Err(e) if matches!(e.kind.as_ref(), ErrorKind::Command(c) if c.code == 48) => (),
+        Err(e) => panic!("creating the snapshot collection should succeed: {}", e),
+    }
+}
+
+pub async fn take_snapshot(db: &Database) -> Result<Snapshot, MongoError> {
+    let snapshot = SnapshotDocument {
+        timestamp: DateTime::now(),
+        stats: stats(db).await?,
+    };
+    db.collection::<SnapshotDocument>(SNAPSHOTS_COLL_NAME)
+        .insert_one(&snapshot, None)
+        .await?;
+    Ok(Snapshot {
+        timestamp: snapshot.timestamp.timestamp_millis(),
+        stats: snapshot.stats,
+    })
+}
+
+/// Snapshots taken between `from` and `to` (unix seconds), oldest first.
+pub async fn snapshots(from: i64, to: i64, db: &Database) -> Result<Vec<Snapshot>, MongoError> {
+    let filter = doc! {
+        "timestamp": {
+            "$gte": DateTime::from_millis(from * 1000),
+            "$lte": DateTime::from_millis(to * 1000),
+        }
+    };
+    let options = FindOptions::builder()
+        .sort(doc! { "timestamp": 1 })
+        .limit(SNAPSHOT_PAGE_SIZE)
+        .build();
+    let mut cursor = db
+        .collection::<SnapshotDocument>(SNAPSHOTS_COLL_NAME)
+        .find(filter, options)
+        .await?;
+    let mut snapshots = vec![];
+    while let Some(snapshot) = cursor.try_next().await? {
+        snapshots.push(Snapshot {
+            timestamp: snapshot.timestamp.timestamp_millis(),
+            stats: snapshot.stats,
+        });
+    }
+    Ok(snapshots)
+}
+
+/// Takes a snapshot every `SNAPSHOT_INTERVAL_SECS`, starting right away,
+/// and refreshes the token supply gauges of the world from it.
+pub async fn snapshot_task(world: World) {
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(SNAPSHOT_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        match take_snapshot(&world.db).await {
+            Ok(snapshot) => {
+                metrics::set_token_supply(&world.name, &snapshot.stats);
+                tracing::info!(timestamp = snapshot.timestamp, "economy snapshot taken")
+            }
+            Err(e) => tracing::warn!(error = %e, "economy snapshot failed"),
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use {
+    super::{
+        ledger::{self, Change, Operation},
+        model::*,
+        units::EGGS,
+        world::{GameConfig, World},
+    },
+    mongodb::{
+        bson::doc,
+        error::Error as MongoError,
+        options::{IndexOptions, ReplaceOptions},
+        ClientSession, Database, IndexModel,
+    },
+    serde::{Deserialize, Serialize},
+};
+
+pub const FORTIFICATIONS_COLL_NAME: &str = "fortifications";
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Building {
+    /// Multiplies the defense of the hive.
+    Walls,
+    /// Shortens the incubation of the eggs of the owner.
+    Nursery,
+    /// Keeps a share of the hive eggs out of the loot.
+    Vault,
+}
+
+/// Price and effect of one building level. `percent` is the defense of the
+/// walls, the incubation time of the nursery and the protected egg share
+/// of the vault, all in percent.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct FortificationLevel {
+    pub cost: i64,
+    pub percent: i64,
+}
+
+/// Levels of each building, the first entry is level 1.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct FortificationConfig {
+    pub walls: Vec<FortificationLevel>,
+    pub nursery: Vec<FortificationLevel>,
+    pub vault: Vec<FortificationLevel>,
+}
+
+impl Default for FortificationConfig {
+    fn default() -> Self {
+        let levels = |levels: [(i64, i64); 3]| {
+            levels
+                .iter()
+                .map(|&(cost, percent)| FortificationLevel { cost, percent })
+                .collect()
+        };
+        FortificationConfig {
+            walls: levels([(500, 125), (2000, 150), (8000, 200)]),
+            nursery: levels([(500, 80), (2000, 60), (8000, 40)]),
+            vault: levels([(500, 10), (2000, 25), (8000, 50)]),
+        }
+    }
+}
+
+impl FortificationConfig {
+    pub fn levels(&self, building: Building) -> &[FortificationLevel] {
+        match building {
+            Building::Walls => &self.walls,
+            Building::Nursery => &self.nursery,
+            Building::Vault => &self.vault,
+        }
+    }
+
+    /// Price of `level` of `building`, zero for levels that do not exist.
+    pub fn level_cost(&self, building: Building, level: i64) -> i64 {
+        usize::try_from(level - 1)
+            .ok()
+            .and_then(|index| self.levels(building).get(index))
+            .map_or(0, |level| level.cost)
+    }
+
+    /// Whether `eggs` is the price of any building level.
+    pub fn is_cost(&self, eggs: i64) -> bool {
+        [&self.walls, &self.nursery, &self.vault]
+            .iter()
+            .any(|levels| levels.iter().any(|level| level.cost == eggs))
+    }
+}
+
+/// Building levels of the hive of `pubkey`. Hives without a document have
+/// no buildings.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Fortifications {
+    pub pubkey: String,
+    pub walls: i64,
+    pub nursery: i64,
+    pub vault: i64,
+}
+
+impl Fortifications {
+    pub fn empty(pubkey: String) -> Self {
+        Fortifications {
+            pubkey,
+            walls: 0,
+            nursery: 0,
+            vault: 0,
+        }
+    }
+
+    pub fn level(&self, building: Building) -> i64 {
+        match building {
+            Building::Walls => self.walls,
+            Building::Nursery => self.nursery,
+            Building::Vault => self.vault,
+        }
+    }
+
+    fn level_mut(&mut self, building: Building) -> &mut i64 {
+        match building {
+            Building::Walls => &mut self.walls,
+            Building::Nursery => &mut self.nursery,
+            Building::Vault => &mut self.vault,
+        }
+    }
+
+    fn percent(&self, building: Building, config: &GameConfig, base: i64) -> i64 {
+        match self.level(building) {
+            0 => base,
+            level => config
+                .fortifications
+                .levels(building)
+                .get(level as usize - 1)
+                .map_or(base, |level| level.percent),
+        }
+    }
+
+    /// Defense of the hive in percent of its units.
+    pub fn defense_percent(&self, config: &GameConfig) -> i64 {
+        self.percent(Building::Walls, config, 100)
+    }
+
+    /// Incubation time of the eggs of the owner in percent.
+    pub fn hatch_percent(&self, config: &GameConfig) -> i64 {
+        self.percent(Building::Nursery, config, 100)
+    }
+
+    /// Share of the hive eggs that can not be looted, in percent.
+    pub fn vault_percent(&self, config: &GameConfig) -> i64 {
+        self.percent(Building::Vault, config, 0)
+    }
+}
+
+/// Body of `/hive/fortify`, signed by the hive owner. `level` is the level
+/// the building is raised to, always the next one, so that a request can
+/// not buy twice.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Fortify {
+    pub pubkey: String,
+    pub building: Building,
+    pub level: i64,
+}
+
+impl KeyCloner for Fortify {
+    fn clone_pubkey(&self) -> String {
+        self.pubkey.clone()
+    }
+}
+
+pub enum FortifyError {
+    InvalidPubkey,
+    InvalidLevel,
+    NotEnoughTokens,
+    DBError(MongoError),
+}
+
+impl From<SearchError> for FortifyError {
+    fn from(e: SearchError) -> FortifyError {
+        match e {
+            SearchError::NotFound => FortifyError::NotEnoughTokens,
+            SearchError::InvalidPubkey => FortifyError::InvalidPubkey,
+            SearchError::DBError(e) => FortifyError::DBError(e),
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for FortifyError {
+    fn from(e: mongodb::error::Error) -> FortifyError {
+        FortifyError::DBError(e)
+    }
+}
+
+impl TransactionError for FortifyError {
+    fn db_error(&self) -> Option<&MongoError> {
+        match self {
+            FortifyError::DBError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Buildings of `pubkey` read inside the transaction of `session`.
+pub async fn load_with_session(
+    pubkey: &str,
+    db: &Database,
+    session: &mut ClientSession,
+) -> Result<Fortifications, MongoError> {
+    Ok(db
+        .collection::<Fortifications>(FORTIFICATIONS_COLL_NAME)
+        .find_one_with_session(doc! { "pubkey": pubkey }, None, session)
+        .await?
+        .unwrap_or_else(|| Fortifications::empty(pubkey.to_string())))
+}
+
+#[tracing::instrument(skip(db))]
+pub async fn db_search_fortifications(
+    pubkey: String,
+    db: Database,
+) -> Result<Fortifications, SearchError> {
+    if !pubkey_is_valid(&pubkey) {
+        return Err(SearchError::InvalidPubkey);
+    }
+    Ok(db
+        .collection::<Fortifications>(FORTIFICATIONS_COLL_NAME)
+        .find_one(doc! { "pubkey": &pubkey }, None)
+        .await?
+        .unwrap_or_else(|| Fortifications::empty(pubkey)))
+}
+
+/// Raises a building of the hive of the caller by one level, paid with the
+/// eggs of the swarm. The eggs are gone.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        world = %world.name,
+        pubkey = %request.pubkey,
+        building = ?request.building,
+        level = request.level,
+    )
+)]
+pub async fn fortify(request: Fortify, world: &World) -> Result<Fortifications, FortifyError> {
+    let mut session = world.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    let db = world.db.clone();
+    let mut swarm =
+        db_search_with_session::<Swarm>(request.pubkey.clone(), db.clone(), &mut session).await?;
+    let mut fortifications = load_with_session(&request.pubkey, &db, &mut session).await?;
+    let level = fortifications.level_mut(request.building);
+    let price = match world
+        .config
+        .fortifications
+        .levels(request.building)
+        .get(request.level.max(1) as usize - 1)
+    {
+        Some(price) if request.level == *level + 1 => price,
+        _ => {
+            tracing::info!(current = *level, "not the next level of the building");
+            return Err(FortifyError::InvalidLevel);
+        }
+    };
+    if swarm.get(EGGS) < price.cost {
+        tracing::info!(
+            available = swarm.get(EGGS),
+            cost = price.cost,
+            "not enough eggs"
+        );
+        return Err(FortifyError::NotEnoughTokens);
+    }
+    *level = request.level;
+    let before = swarm.clone();
+    swarm.change(EGGS, -price.cost);
+    db.collection::<Swarm>(Swarm::get_collection())
+        .replace_one_with_session(doc! { "pubkey": &swarm.pubkey }, &swarm, None, &mut session)
+        .await?;
+    db.collection::<Fortifications>(FORTIFICATIONS_COLL_NAME)
+        .replace_one_with_session(
+            doc! { "pubkey": &fortifications.pubkey },
+            &fortifications,
+            ReplaceOptions::builder().upsert(true).build(),
+            &mut session,
+        )
+        .await?;
+    ledger::append(
+        &db,
+        &mut session,
+        Operation::Fortify,
+        &swarm.pubkey,
+        vec![Change::new(&before, &swarm)],
+    )
+    .await?;
+    commit_with_retry(&mut session).await?;
+    tracing::info!("hive fortified");
+    Ok(fortifications)
+}
+
+pub async fn create_fortification_indexes(db: &Database) {
+    let options = IndexOptions::builder().unique(true).build();
+    let model = IndexModel::builder()
+        .keys(doc! { "pubkey": 1 })
+        .options(options)
+        .build();
+    db.collection::<Fortifications>(FORTIFICATIONS_COLL_NAME)
+        .create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+}
@@ -0,0 +1,385 @@
+use {
+    super::{
+        fortifications,
+        ledger::{self, Change, Operation},
+        matchmaking, metrics,
+        model::*,
+        units::{self, Balances, UnitConfig, BERSERKERS, EGGS, GUARDIANS, QUEENS},
+        world::World,
+    },
+    futures::stream::TryStreamExt,
+    mongodb::{
+        bson::{doc, oid::ObjectId, Document},
+        error::Error as MongoError,
+        options::{FindOptions, IndexOptions},
+        ClientSession, Database, IndexModel,
+    },
+    serde::{Deserialize, Serialize},
+};
+
+pub const HATCH_JOBS_COLL_NAME: &str = "hatchJobs";
+pub const HATCH_SECS_PER_EGG: i64 = 3;
+
+/// Eggs sent to the hatchery. The units are rolled once `completes_at`
+/// (unix seconds) has passed and move to the swarm when the job is claimed.
+/// `eggs` counts the eggs still held by the job, it drops to zero when the
+/// job is claimed or its eggs are raided.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct HatchJob {
+    pub id: String,
+    pub pubkey: String,
+    pub eggs: i64,
+    pub started_at: i64,
+    pub completes_at: i64,
+    pub hatched: Option<Swarm>,
+    pub claimed: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct HatchClaim {
+    pub pubkey: String,
+    pub job: String,
+}
+
+impl KeyCloner for HatchClaim {
+    fn clone_pubkey(&self) -> String {
+        self.pubkey.clone()
+    }
+}
+
+pub enum ClaimError {
+    InvalidPubkey,
+    NotFound,
+    NotReady,
+    DBError(MongoError),
+}
+
+impl From<SearchError> for ClaimError {
+    fn from(e: SearchError) -> ClaimError {
+        match e {
+            SearchError::NotFound => ClaimError::NotFound,
+            SearchError::InvalidPubkey => ClaimError::InvalidPubkey,
+            SearchError::DBError(e) => ClaimError::DBError(e),
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for ClaimError {
+    fn from(e: mongodb::error::Error) -> ClaimError {
+        ClaimError::DBError(e)
+    }
+}
+
+impl TransactionError for ClaimError {
+    fn db_error(&self) -> Option<&MongoError> {
+        match self {
+            ClaimError::DBError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Ledger change of the eggs that `pubkey` has in the hatchery. The ledger
+/// tracks the hatchery as one balance per player.
+pub fn incubating_change(pubkey: &str, before: i64, after: i64) -> Change {
+    let incubating = |eggs| Swarm::empty(pubkey.to_string()).with(EGGS, eggs);
+    Change {
+        collection: HATCH_JOBS_COLL_NAME.to_string(),
+        before: incubating(before),
+        after: incubating(after),
+    }
+}
+
+/// Hatches every egg into a unit of the registry, drawn by hatch weight.
+pub fn roll(pubkey: &str, eggs: i64, units: &[UnitConfig]) -> Swarm {
+    let mut hatched = Balances::new();
+    let total_weight = units::total_hatch_weight(units);
+    for _ in 0..eggs {
+        if total_weight <= 0 {
+            break;
+        }
+        let draw = (rand::random::<u64>() % total_weight as u64) as i64;
+        if let Some(id) = units::hatch_unit(units, draw) {
+            *hatched.entry(id.to_string()).or_insert(0) += 1;
+        }
+    }
+    Swarm::from_balances(pubkey.to_string(), &hatched)
+}
+
+/// Moves the eggs of `request` from the swarm into a new hatch job that
+/// completes after `hatch_secs_per_egg` seconds per egg, shortened by the
+/// nursery of the owner.
+#[tracing::instrument(
+    skip_all,
+    fields(world = %world.name, pubkey = %request.pubkey, eggs = request.eggs)
+)]
+pub async fn incubate(request: HatchRequest, world: &World) -> Result<HatchJob, StakeError> {
+    if request.eggs <= 0 {
+        return Err(StakeError::NotEnoughTokens);
+    }
+    let mut session = world.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    let db = world.db.clone();
+    let mut swarm =
+        db_search_with_session::<Swarm>(request.pubkey.clone(), db.clone(), &mut session).await?;
+    if swarm.get(EGGS) < request.eggs {
+        tracing::info!(available = swarm.get(EGGS), "not enough eggs to hatch");
+        return Err(StakeError::NotEnoughTokens);
+    }
+    let nursery = fortifications::load_with_session(&request.pubkey, &db, &mut session)
+        .await?
+        .hatch_percent(&world.config);
+    let before = swarm.clone();
+    swarm.change(EGGS, -request.eggs);
+    let now = chrono::Utc::now().timestamp();
+    let job = HatchJob {
+        id: ObjectId::new().to_hex(),
+        pubkey: request.pubkey.clone(),
+        eggs: request.eggs,
+        started_at: now,
+        completes_at: now + request.eggs * world.config.hatch_secs_per_egg * nursery / 100,
+        hatched: None,
+        claimed: false,
+    };
+    db.collection::<Swarm>(Swarm::get_collection())
+        .replace_one_with_session(
+            doc! { "pubkey": swarm.clone_pubkey() },
+            &swarm,
+            None,
+            &mut session,
+        )
+        .await?;
+    db.collection::<HatchJob>(HATCH_JOBS_COLL_NAME)
+        .insert_one_with_session(&job, None, &mut session)
+        .await?;
+    ledger::append(
+        &db,
+        &mut session,
+        Operation::Incubate,
+        &swarm.pubkey,
+        vec![
+            Change::new(&before, &swarm),
+            incubating_change(&swarm.pubkey, 0, job.eggs),
+        ],
+    )
+    .await?;
+    commit_with_retry(&mut session).await?;
+    metrics::HATCHED
+        .with_label_values(&["eggs"])
+        .inc_by(request.eggs as u64);
+    tracing::info!(job = %job.id, completes_at = job.completes_at, "eggs incubating");
+    Ok(job)
+}
+
+/// Rolls the units of the completed jobs of `pubkey` that were not rolled
+/// yet. Rolling does not change any balance, the units stay in the job until
+/// it is claimed.
+async fn roll_completed(
+    pubkey: &str,
+    now: i64,
+    units: &[UnitConfig],
+    db: &Database,
+) -> Result<(), MongoError> {
+    let jobs = db.collection::<HatchJob>(HATCH_JOBS_COLL_NAME);
+    let filter = doc! {
+        "pubkey": pubkey,
+        "claimed": false,
+        "hatched": null,
+        "completes_at": { "$lte": now },
+    };
+    let mut cursor = jobs.find(filter, None).await?;
+    while let Some(job) = cursor.try_next().await? {
+        let hatched = mongodb::bson::to_bson(&roll(pubkey, job.eggs, units))?;
+        // a concurrent roll of the same job wins, the filter keeps it
+        jobs.update_one(
+            doc! { "id": &job.id, "hatched": null },
+            doc! { "$set": { "hatched": hatched } },
+            None,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Unclaimed jobs of `pubkey`, oldest first, with the units of completed
+/// jobs rolled.
+#[tracing::instrument(skip(world), fields(world = %world.name))]
+pub async fn pending_jobs(pubkey: String, world: &World) -> Result<Vec<HatchJob>, SearchError> {
+    if !pubkey_is_valid(&pubkey) {
+        return Err(SearchError::InvalidPubkey);
+    }
+    let db = world.db.clone();
+    roll_completed(
+        &pubkey,
+        chrono::Utc::now().timestamp(),
+        &world.config.units,
+        &db,
+    )
+    .await?;
+    let options = FindOptions::builder()
+        .sort(doc! { "started_at": 1 })
+        .build();
+    let mut cursor = db
+        .collection::<HatchJob>(HATCH_JOBS_COLL_NAME)
+        .find(doc! { "pubkey": &pubkey, "claimed": false }, options)
+        .await?;
+    let mut jobs = vec![];
+    while let Some(job) = cursor.try_next().await? {
+        jobs.push(job);
+    }
+    Ok(jobs)
+}
+
+/// Moves the units of a completed job to the swarm.
+#[tracing::instrument(
+    skip_all,
+    fields(world = %world.name, pubkey = %request.pubkey, job = %request.job)
+)]
+pub async fn claim(request: HatchClaim, world: &World) -> Result<Swarm, ClaimError> {
+    if !pubkey_is_valid(&request.pubkey) {
+        return Err(ClaimError::InvalidPubkey);
+    }
+    let now = chrono::Utc::now().timestamp();
+    let mut session = world.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    let db = world.db.clone();
+    let jobs = db.collection::<HatchJob>(HATCH_JOBS_COLL_NAME);
+    let filter = doc! { "id": &request.job, "pubkey": &request.pubkey, "claimed": false };
+    let job = match jobs
+        .find_one_with_session(filter, None, &mut session)
+        .await?
+    {
+        Some(job) => job,
+        None => return Err(ClaimError::NotFound),
+    };
+    if job.completes_at > now {
+        tracing::info!(completes_at = job.completes_at, "hatch job not completed");
+        return Err(ClaimError::NotReady);
+    }
+    let hatched = job
+        .hatched
+        .clone()
+        .unwrap_or_else(|| roll(&job.pubkey, job.eggs, &world.config.units));
+    let mut swarm =
+        db_search_with_session::<Swarm>(request.pubkey.clone(), db.clone(), &mut session).await?;
+    let before = swarm.clone();
+    swarm.add(&hatched);
+    jobs.update_one_with_session(
+        doc! { "id": &job.id },
+        doc! {
+            "$set": {
+                "eggs": 0,
+                "claimed": true,
+                "hatched": mongodb::bson::to_bson(&hatched).map_err(MongoError::from)?,
+            }
+        },
+        None,
+        &mut session,
+    )
+    .await?;
+    db.collection::<Swarm>(Swarm::get_collection())
+        .replace_one_with_session(
+            doc! { "pubkey": swarm.clone_pubkey() },
+            &swarm,
+            None,
+            &mut session,
+        )
+        .await?;
+    let changes = vec![
+        Change::new(&before, &swarm),
+        incubating_change(&swarm.pubkey, job.eggs, 0),
+    ];
+    matchmaking::store_powers_with_session(&changes, world, &mut session).await?;
+    ledger::append(&db, &mut session, Operation::Hatch, &swarm.pubkey, changes).await?;
+    commit_with_retry(&mut session).await?;
+    for (token, amount) in hatched.balances() {
+        metrics::HATCHED
+            .with_label_values(&[&token])
+            .inc_by(amount.max(0) as u64);
+    }
+    tracing::info!(
+        queens = hatched.get(QUEENS),
+        guardians = hatched.get(GUARDIANS),
+        berserkers = hatched.get(BERSERKERS),
+        "eggs hatched"
+    );
+    Ok(hatched)
+}
+
+fn incubating_filter(pubkey: &str) -> Document {
+    let now = chrono::Utc::now().timestamp();
+    doc! { "pubkey": pubkey, "claimed": false, "completes_at": { "$gt": now } }
+}
+
+/// Eggs of `pubkey` that are still incubating, read inside the transaction
+/// of `session`. Completed jobs are not counted.
+pub async fn incubating_eggs(
+    pubkey: &str,
+    db: &Database,
+    session: &mut ClientSession,
+) -> Result<i64, MongoError> {
+    let mut eggs = 0;
+    let mut cursor = db
+        .collection::<HatchJob>(HATCH_JOBS_COLL_NAME)
+        .find_with_session(incubating_filter(pubkey), None, session)
+        .await?;
+    while let Some(job) = cursor.next(session).await.transpose()? {
+        eggs += job.eggs;
+    }
+    Ok(eggs)
+}
+
+/// Empties the jobs counted by `incubating_eggs`.
+pub async fn raid_with_session(
+    pubkey: &str,
+    db: &Database,
+    session: &mut ClientSession,
+) -> Result<(), MongoError> {
+    db.collection::<HatchJob>(HATCH_JOBS_COLL_NAME)
+        .update_many_with_session(
+            incubating_filter(pubkey),
+            doc! { "$set": { "eggs": 0 } },
+            None,
+            session,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Adds the eggs each player has in the hatchery to `state`.
+pub async fn load_incubating(
+    db: &Database,
+    pubkey: Option<&str>,
+    state: &mut ledger::State,
+) -> Result<(), MongoError> {
+    let filter = pubkey.map(|pubkey| doc! { "pubkey": pubkey });
+    let mut cursor = db
+        .collection::<HatchJob>(HATCH_JOBS_COLL_NAME)
+        .find(filter, None)
+        .await?;
+    while let Some(job) = cursor.try_next().await? {
+        state
+            .entry((HATCH_JOBS_COLL_NAME.to_string(), job.pubkey.clone()))
+            .or_insert_with(|| Swarm::empty(job.pubkey.clone()))
+            .change(EGGS, job.eggs);
+    }
+    Ok(())
+}
+
+pub async fn create_incubation_indexes(db: &Database) {
+    let jobs = db.collection::<HatchJob>(HATCH_JOBS_COLL_NAME);
+    let options = IndexOptions::builder().unique(true).build();
+    let model = IndexModel::builder()
+        .keys(doc! { "id": 1 })
+        .options(options)
+        .build();
+    jobs.create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+    let model = IndexModel::builder()
+        .keys(doc! { "pubkey": 1, "claimed": 1 })
+        .build();
+    jobs.create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+}
@@ -0,0 +1,297 @@
+use {
+    super::{bounties, incubation, locks, march, mercenaries, model::*},
+    anyhow::Result,
+    futures::stream::TryStreamExt,
+    mongodb::{
+        bson::doc, error::Error as MongoError, options::FindOptions, ClientSession, Database,
+        IndexModel,
+    },
+    serde::{Deserialize, Serialize},
+    std::collections::BTreeMap,
+};
+
+pub const LEDGER_COLL_NAME: &str = "ledger";
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Genesis,
+    Airdrop,
+    Stake,
+    Unstake,
+    Hatch,
+    Attack,
+    Trigger,
+    Produce,
+    Incubate,
+    March,
+    Recall,
+    Reinforce,
+    Scout,
+    Fortify,
+    Bounty,
+    Refund,
+    Enlist,
+    Discharge,
+    Lock,
+    Unlock,
+}
+
+/// Balances of one document before and after a mutation. Hives and sacred
+/// hives are stored in their `as_swarm` form.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Change {
+    pub collection: String,
+    pub before: Swarm,
+    pub after: Swarm,
+}
+
+impl Change {
+    pub fn new<T: Helpers>(before: &T, after: &T) -> Self {
+        Change {
+            collection: T::get_collection().to_string(),
+            before: before.as_swarm(),
+            after: after.as_swarm(),
+        }
+    }
+
+    /// Change of a document that did not exist before the mutation.
+    pub fn created<T: Helpers>(after: &T) -> Self {
+        let after = after.as_swarm();
+        Change {
+            collection: T::get_collection().to_string(),
+            before: Swarm::empty(after.pubkey.clone()),
+            after,
+        }
+    }
+}
+
+/// Immutable record of a mutation, written in the same transaction.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct LedgerEntry {
+    pub timestamp: i64,
+    pub operation: Operation,
+    pub pubkey: String,
+    pub changes: Vec<Change>,
+}
+
+pub async fn append(
+    db: &Database,
+    session: &mut ClientSession,
+    operation: Operation,
+    pubkey: &str,
+    changes: Vec<Change>,
+) -> Result<(), MongoError> {
+    let entry = LedgerEntry {
+        timestamp: chrono::Utc::now().timestamp_millis(),
+        operation,
+        pubkey: pubkey.to_string(),
+        changes,
+    };
+    db.collection::<LedgerEntry>(LEDGER_COLL_NAME)
+        .insert_one_with_session(entry, None, session)
+        .await?;
+    Ok(())
+}
+
+/// Balances keyed by collection name and pubkey.
+pub type State = BTreeMap<(String, String), Swarm>;
+
+/// Replays the deltas of all ledger entries, optionally only for `pubkey`.
+pub async fn rebuild(db: &Database, pubkey: Option<&str>) -> Result<State, MongoError> {
+    let filter = pubkey.map(|pubkey| doc! { "changes.after.pubkey": pubkey });
+    let options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+    let mut cursor = db
+        .collection::<LedgerEntry>(LEDGER_COLL_NAME)
+        .find(filter, options)
+        .await?;
+    let mut state = State::new();
+    while let Some(entry) = cursor.try_next().await? {
+        for change in entry.changes {
+            if pubkey.is_some_and(|pubkey| pubkey != change.after.pubkey) {
+                continue;
+            }
+            let balance = state
+                .entry((change.collection, change.after.pubkey.clone()))
+                .or_insert_with(|| Swarm::empty(change.after.pubkey.clone()));
+            balance.add(&change.after);
+            balance.add(&change.before.negative());
+        }
+    }
+    Ok(state)
+}
+
+async fn load<T: Contract>(
+    db: &Database,
+    pubkey: Option<&str>,
+    state: &mut State,
+) -> Result<(), MongoError> {
+    let filter = pubkey.map(|pubkey| doc! { "pubkey": pubkey });
+    let mut cursor = db
+        .collection::<T>(T::get_collection())
+        .find(filter, None)
+        .await?;
+    while let Some(t) = cursor.try_next().await? {
+        state.insert(
+            (T::get_collection().to_string(), t.clone_pubkey()),
+            t.as_swarm(),
+        );
+    }
+    Ok(())
+}
+
+/// Reads the live balances of the swarms, hives, sacred hives, eggs in the
+/// hatchery, units on the road, eggs in open bounties, berserkers listed
+/// for hire and locked sacred queens.
+pub async fn live_state(db: &Database, pubkey: Option<&str>) -> Result<State, MongoError> {
+    let mut state = State::new();
+    load::<Swarm>(db, pubkey, &mut state).await?;
+    load::<Hive>(db, pubkey, &mut state).await?;
+    load::<SacredHive>(db, pubkey, &mut state).await?;
+    incubation::load_incubating(db, pubkey, &mut state).await?;
+    march::load_marching(db, pubkey, &mut state).await?;
+    bounties::load_bounties(db, pubkey, &mut state).await?;
+    mercenaries::load_listed(db, pubkey, &mut state).await?;
+    locks::load_locked(db, pubkey, &mut state).await?;
+    Ok(state)
+}
+
+/// Writes a genesis entry for every live balance the ledger has no history
+/// of, such as documents written before the ledger existed, so that the
+/// rebuilt state matches the live one. Balances with history are left
+/// alone, running it again writes nothing. Optionally only for `pubkey`.
+pub async fn backfill_genesis(db: &Database, pubkey: Option<&str>) -> Result<(), MongoError> {
+    let rebuilt = rebuild(db, pubkey).await?;
+    let live = live_state(db, pubkey).await?;
+    let mut changes: BTreeMap<String, Vec<Change>> = BTreeMap::new();
+    for ((collection, pubkey), balance) in live {
+        if !rebuilt.contains_key(&(collection.clone(), pubkey.clone())) {
+            changes.entry(pubkey.clone()).or_default().push(Change {
+                collection,
+                before: Swarm::empty(pubkey),
+                after: balance,
+            });
+        }
+    }
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let timestamp = chrono::Utc::now().timestamp_millis();
+    let entries: Vec<LedgerEntry> = changes
+        .into_iter()
+        .map(|(pubkey, changes)| LedgerEntry {
+            timestamp,
+            operation: Operation::Genesis,
+            pubkey,
+            changes,
+        })
+        .collect();
+    db.collection::<LedgerEntry>(LEDGER_COLL_NAME)
+        .insert_many(entries, None)
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct Difference {
+    pub collection: String,
+    pub pubkey: String,
+    pub rebuilt: Option<Swarm>,
+    pub live: Option<Swarm>,
+}
+
+pub fn diff(rebuilt: &State, live: &State) -> Vec<Difference> {
+    let mut keys: Vec<&(String, String)> = rebuilt.keys().chain(live.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter(|key| rebuilt.get(*key) != live.get(*key))
+        .map(|(collection, pubkey)| Difference {
+            collection: collection.clone(),
+            pubkey: pubkey.clone(),
+            rebuilt: rebuilt.get(&(collection.clone(), pubkey.clone())).cloned(),
+            live: live.get(&(collection.clone(), pubkey.clone())).cloned(),
+        })
+        .collect()
+}
+
+async fn write<T: Contract>(state: &State, target: &Database) -> Result<(), MongoError> {
+    let documents: Vec<T> = state
+        .iter()
+        .filter(|((collection, _), _)| collection == T::get_collection())
+        .map(|(_, balance)| T::from_swarm(balance))
+        .collect();
+    let collection = target.collection::<T>(T::get_collection());
+    collection.drop(None).await?;
+    if !documents.is_empty() {
+        collection.insert_many(documents, None).await?;
+    }
+    Ok(())
+}
+
+/// Writes a rebuilt state into `target`, replacing its game collections.
+/// Refuses a target that already holds swarms, hives or sacred hives unless
+/// `force` is set. Hatch jobs, marches, bounties, mercenary offers and stake
+/// locks are not written, the ledger only knows their totals.
+pub async fn write_state(state: &State, target: &Database, force: bool) -> Result<()> {
+    for collection in [
+        Swarm::get_collection(),
+        Hive::get_collection(),
+        SacredHive::get_collection(),
+    ] {
+        let documents = target
+            .collection::<Swarm>(collection)
+            .estimated_document_count(None)
+            .await?;
+        if !force && documents > 0 {
+            return Err(anyhow::Error::msg(format!(
+                "{} is not empty in {}, pass --force to replace it",
+                collection,
+                target.name()
+            )));
+        }
+    }
+    write::<Swarm>(state, target).await?;
+    write::<Hive>(state, target).await?;
+    write::<SacredHive>(state, target).await?;
+    Ok(())
+}
+
+pub async fn create_ledger_indexes(db: &Database) {
+    let model = IndexModel::builder()
+        .keys(doc! { "changes.after.pubkey": 1 })
+        .build();
+    db.collection::<LedgerEntry>(LEDGER_COLL_NAME)
+        .create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+}
+
+/// `ledger-rebuild [target_db] [--force]`: rebuilds the state from the
+/// ledger, prints every difference to the live state and optionally writes
+/// the rebuilt collections into `target_db`, which must be empty unless
+/// `--force` is given. Returns false if differences were found.
+pub async fn run_rebuild(db: &Database, target: Option<Database>, force: bool) -> Result<bool> {
+    let rebuilt = rebuild(db, None).await?;
+    let live = live_state(db, None).await?;
+    let differences = diff(&rebuilt, &live);
+    for difference in &differences {
+        println!(
+            "{} {}: rebuilt {} live {}",
+            difference.collection,
+            difference.pubkey,
+            serde_json::to_string(&difference.rebuilt).unwrap_or_default(),
+            serde_json::to_string(&difference.live).unwrap_or_default(),
+        );
+    }
+    println!(
+        "{} documents rebuilt, {} differences",
+        rebuilt.len(),
+        differences.len()
+    );
+    if let Some(target) = target {
+        write_state(&rebuilt, &target, force).await?;
+        println!("rebuilt state written to {}", target.name());
+    }
+    Ok(differences.is_empty())
+}
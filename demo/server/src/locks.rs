@@ -0,0 +1,335 @@
+use {
+    super::{
+        ledger::{self, Change, Operation},
+        model::*,
+        units::SACRED_QUEENS,
+        world::World,
+    },
+    futures::stream::TryStreamExt,
+    mongodb::{
+        bson::{doc, oid::ObjectId},
+        error::Error as MongoError,
+        options::{FindOptions, IndexOptions},
+        ClientSession, Database, IndexModel,
+    },
+    serde::{Deserialize, Serialize},
+};
+
+pub const STAKE_LOCKS_COLL_NAME: &str = "stakeLocks";
+
+/// A lock period sacred queens can be staked for and the eggs they lay per
+/// trigger while locked, in percent of unlocked sacred queens. Locks never
+/// lay less than unlocked sacred queens.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct LockConfig {
+    pub secs: i64,
+    pub yield_percent: i64,
+}
+
+/// Locks for a week, a month and three months.
+pub fn default_locks() -> Vec<LockConfig> {
+    [(7, 125), (30, 150), (90, 200)]
+        .iter()
+        .map(|&(days, yield_percent)| LockConfig {
+            secs: days * 24 * 3600,
+            yield_percent,
+        })
+        .collect()
+}
+
+/// The highest yield of `locks` in percent, 100 without locks.
+pub fn max_yield_percent(locks: &[LockConfig]) -> i64 {
+    locks
+        .iter()
+        .map(|lock| lock.yield_percent)
+        .fold(100, i64::max)
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LockStatus {
+    Locked,
+    Unlocked,
+}
+
+/// Sacred queens of `pubkey` staked until `unlocks_at` (unix seconds). They
+/// are kept out of the sacred hive and lay `yield_percent` of its eggs
+/// until then, afterwards as much as unlocked ones. The sacred queens only
+/// count while the position is locked.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct StakeLock {
+    pub id: String,
+    pub pubkey: String,
+    pub sacred_queens: i64,
+    pub yield_percent: i64,
+    pub locked_at: i64,
+    pub unlocks_at: i64,
+    pub status: LockStatus,
+}
+
+impl StakeLock {
+    /// Eggs the position lays per trigger at `now`.
+    pub fn eggs(&self, eggs_per_sacred_queen: i64, now: i64) -> i64 {
+        let yield_percent = match now < self.unlocks_at {
+            true => self.yield_percent.max(100),
+            false => 100,
+        };
+        self.sacred_queens * eggs_per_sacred_queen * yield_percent / 100
+    }
+}
+
+/// Body of `/sacred_hive/lock`, signed by the staker. `lock_secs` is one of
+/// the lock periods of the world.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct LockStake {
+    pub pubkey: String,
+    pub sacred_queens: i64,
+    pub lock_secs: i64,
+}
+
+/// Body of `/sacred_hive/unlock`, signed by the staker.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Unlock {
+    pub pubkey: String,
+    pub lock: String,
+}
+
+impl KeyCloner for LockStake {
+    fn clone_pubkey(&self) -> String {
+        self.pubkey.clone()
+    }
+}
+
+impl KeyCloner for Unlock {
+    fn clone_pubkey(&self) -> String {
+        self.pubkey.clone()
+    }
+}
+
+/// Ledger change of the sacred queens a player has locked. The ledger
+/// tracks all positions of a player as one balance.
+pub fn locked_change(pubkey: &str, before: i64, after: i64) -> Change {
+    let locked =
+        |sacred_queens| Swarm::empty(pubkey.to_string()).with(SACRED_QUEENS, sacred_queens);
+    Change {
+        collection: STAKE_LOCKS_COLL_NAME.to_string(),
+        before: locked(before),
+        after: locked(after),
+    }
+}
+
+async fn write_swarm(
+    swarm: &Swarm,
+    db: &Database,
+    session: &mut ClientSession,
+) -> Result<(), MongoError> {
+    db.collection::<Swarm>(Swarm::get_collection())
+        .replace_one_with_session(doc! { "pubkey": &swarm.pubkey }, swarm, None, session)
+        .await?;
+    Ok(())
+}
+
+/// Stakes sacred queens of the swarm into a new position locked for
+/// `lock_secs`.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        world = %world.name,
+        pubkey = %request.pubkey,
+        sacred_queens = request.sacred_queens,
+        lock_secs = request.lock_secs,
+    )
+)]
+pub async fn lock(request: LockStake, world: &World) -> Result<StakeLock, StakeError> {
+    if !pubkey_is_valid(&request.pubkey) {
+        return Err(StakeError::InvalidPubkey);
+    }
+    let config = match world
+        .config
+        .stake_locks
+        .iter()
+        .find(|lock| lock.secs == request.lock_secs)
+    {
+        Some(config) => config,
+        None => {
+            tracing::info!("not a lock period of the world");
+            return Err(StakeError::InvalidLock);
+        }
+    };
+    if request.sacred_queens <= 0 {
+        return Err(StakeError::NotEnoughTokens);
+    }
+    let mut session = world.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    let db = world.db.clone();
+    let mut swarm =
+        db_search_with_session::<Swarm>(request.pubkey.clone(), db.clone(), &mut session).await?;
+    if swarm.get(SACRED_QUEENS) < request.sacred_queens {
+        tracing::info!(
+            available = swarm.get(SACRED_QUEENS),
+            "not enough sacred queens"
+        );
+        return Err(StakeError::NotEnoughTokens);
+    }
+    let before = swarm.clone();
+    swarm.change(SACRED_QUEENS, -request.sacred_queens);
+    let now = chrono::Utc::now().timestamp();
+    let lock = StakeLock {
+        id: ObjectId::new().to_hex(),
+        pubkey: request.pubkey.clone(),
+        sacred_queens: request.sacred_queens,
+        yield_percent: config.yield_percent,
+        locked_at: now,
+        unlocks_at: now + config.secs,
+        status: LockStatus::Locked,
+    };
+    write_swarm(&swarm, &db, &mut session).await?;
+    db.collection::<StakeLock>(STAKE_LOCKS_COLL_NAME)
+        .insert_one_with_session(&lock, None, &mut session)
+        .await?;
+    ledger::append(
+        &db,
+        &mut session,
+        Operation::Lock,
+        &swarm.pubkey,
+        vec![
+            Change::new(&before, &swarm),
+            locked_change(&swarm.pubkey, 0, lock.sacred_queens),
+        ],
+    )
+    .await?;
+    commit_with_retry(&mut session).await?;
+    tracing::info!(lock = %lock.id, unlocks_at = lock.unlocks_at, "sacred queens locked");
+    Ok(lock)
+}
+
+/// Brings the sacred queens of a position whose lock ran out back to the
+/// swarm.
+#[tracing::instrument(
+    skip_all,
+    fields(world = %world.name, pubkey = %request.pubkey, lock = %request.lock)
+)]
+pub async fn unlock(request: Unlock, world: &World) -> Result<(), StakeError> {
+    if !pubkey_is_valid(&request.pubkey) {
+        return Err(StakeError::InvalidPubkey);
+    }
+    let mut session = world.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    let db = world.db.clone();
+    let filter = doc! { "id": &request.lock, "pubkey": &request.pubkey, "status": "locked" };
+    let lock = db
+        .collection::<StakeLock>(STAKE_LOCKS_COLL_NAME)
+        .find_one_with_session(filter.clone(), None, &mut session)
+        .await?
+        .ok_or(StakeError::NotEnoughTokens)?;
+    if chrono::Utc::now().timestamp() < lock.unlocks_at {
+        tracing::info!(unlocks_at = lock.unlocks_at, "position still locked");
+        return Err(StakeError::Locked);
+    }
+    let mut swarm =
+        db_search_with_session::<Swarm>(request.pubkey.clone(), db.clone(), &mut session).await?;
+    let before = swarm.clone();
+    swarm.change(SACRED_QUEENS, lock.sacred_queens);
+    write_swarm(&swarm, &db, &mut session).await?;
+    db.collection::<StakeLock>(STAKE_LOCKS_COLL_NAME)
+        .update_one_with_session(
+            filter,
+            doc! { "$set": { "status": "unlocked" } },
+            None,
+            &mut session,
+        )
+        .await?;
+    ledger::append(
+        &db,
+        &mut session,
+        Operation::Unlock,
+        &swarm.pubkey,
+        vec![
+            Change::new(&before, &swarm),
+            locked_change(&swarm.pubkey, lock.sacred_queens, 0),
+        ],
+    )
+    .await?;
+    commit_with_retry(&mut session).await?;
+    tracing::info!(sacred_queens = lock.sacred_queens, "sacred queens unlocked");
+    Ok(())
+}
+
+/// Sacred queens `pubkey` has in positions and the eggs they lay per
+/// trigger, read inside the transaction of `session`.
+pub async fn accrual_with_session(
+    pubkey: &str,
+    eggs_per_sacred_queen: i64,
+    db: &Database,
+    session: &mut ClientSession,
+) -> Result<(i64, i64), MongoError> {
+    let now = chrono::Utc::now().timestamp();
+    let (mut locked, mut eggs) = (0, 0);
+    let mut cursor = db
+        .collection::<StakeLock>(STAKE_LOCKS_COLL_NAME)
+        .find_with_session(doc! { "pubkey": pubkey, "status": "locked" }, None, session)
+        .await?;
+    while let Some(lock) = cursor.next(session).await.transpose()? {
+        locked += lock.sacred_queens;
+        eggs += lock.eggs(eggs_per_sacred_queen, now);
+    }
+    Ok((locked, eggs))
+}
+
+/// Positions of `pubkey` that were not unlocked, soonest unlock first.
+#[tracing::instrument(skip(db))]
+pub async fn positions(pubkey: String, db: Database) -> Result<Vec<StakeLock>, SearchError> {
+    if !pubkey_is_valid(&pubkey) {
+        return Err(SearchError::InvalidPubkey);
+    }
+    let options = FindOptions::builder()
+        .sort(doc! { "unlocks_at": 1 })
+        .build();
+    Ok(db
+        .collection::<StakeLock>(STAKE_LOCKS_COLL_NAME)
+        .find(doc! { "pubkey": &pubkey, "status": "locked" }, options)
+        .await?
+        .try_collect()
+        .await?)
+}
+
+/// Adds the sacred queens each player has in positions to `state`.
+pub async fn load_locked(
+    db: &Database,
+    pubkey: Option<&str>,
+    state: &mut ledger::State,
+) -> Result<(), MongoError> {
+    let filter = pubkey.map(|pubkey| doc! { "pubkey": pubkey });
+    let mut cursor = db
+        .collection::<StakeLock>(STAKE_LOCKS_COLL_NAME)
+        .find(filter, None)
+        .await?;
+    while let Some(lock) = cursor.try_next().await? {
+        let locked = lock.status == LockStatus::Locked;
+        state
+            .entry((STAKE_LOCKS_COLL_NAME.to_string(), lock.pubkey.clone()))
+            .or_insert_with(|| Swarm::empty(lock.pubkey.clone()))
+            .change(SACRED_QUEENS, locked as i64 * lock.sacred_queens);
+    }
+    Ok(())
+}
+
+pub async fn create_lock_indexes(db: &Database) {
+    let locks = db.collection::<StakeLock>(STAKE_LOCKS_COLL_NAME);
+    let options = IndexOptions::builder().unique(true).build();
+    let model = IndexModel::builder()
+        .keys(doc! { "id": 1 })
+        .options(options)
+        .build();
+    locks
+        .create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+    let model = IndexModel::builder()
+        .keys(doc! { "pubkey": 1, "status": 1, "unlocks_at": 1 })
+        .build();
+    locks
+        .create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+}
@@ -0,0 +1,66 @@
+use {
+    actix_web::{
+        dev::{Service, ServiceRequest, ServiceResponse},
+        http::header::{HeaderName, HeaderValue},
+        Error,
+    },
+    std::future::Future,
+    tracing::Instrument,
+    tracing_subscriber::EnvFilter,
+};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Installs a JSON subscriber. The level is read from `RUST_LOG` and
+/// defaults to `info`.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(filter)
+        .with_current_span(true)
+        .init();
+}
+
+/// Reuses the request ID sent by a proxy or generates a new one.
+pub fn request_id(req: &ServiceRequest) -> String {
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 64)
+        .map(String::from)
+        .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()))
+}
+
+/// Logs the outcome of a request and echoes its ID back to the player.
+pub fn finish_request<B>(res: &mut ServiceResponse<B>, request_id: &str) {
+    tracing::info!(status = res.status().as_u16(), "request finished");
+    if let Ok(value) = HeaderValue::from_str(request_id) {
+        res.headers_mut()
+            .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+}
+
+/// Middleware for `wrap_fn`: runs the request in a span with its ID, method
+/// and path, logs the outcome and echoes the ID.
+pub fn trace_request<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let request_id = request_id(&req);
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.path(),
+    );
+    let response = srv.call(req).instrument(span.clone());
+    async move {
+        let mut response = response.await?;
+        span.in_scope(|| finish_request(&mut response, &request_id));
+        Ok(response)
+    }
+}
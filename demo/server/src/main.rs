@@ -1,38 +1,225 @@
+mod archive;
+mod bounties;
+mod config;
+mod delegation;
+mod economy;
+mod fortifications;
+mod incubation;
+mod ledger;
+mod locks;
+mod logging;
+mod march;
+mod matchmaking;
+mod mercenaries;
+mod metrics;
+mod migrations;
 mod model;
+mod notifications;
+mod production;
+mod ratings;
+mod scouting;
+mod seed;
+mod session;
 #[cfg(test)]
 mod test;
+mod transparency;
+mod units;
+mod world;
 
 use {
-    actix_web::{get, post, web, App, HttpRequest, HttpResponse, HttpServer},
+    actix_files::Files,
+    actix_web::{
+        dev::{Service, ServiceResponse},
+        get,
+        http::StatusCode,
+        post, web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer,
+    },
     anyhow::Result,
-    ed25519_dalek::*,
+    config::ServerConfig,
+    delegation::*,
     model::*,
     mongodb::Client,
     serde::Serialize,
+    session::*,
+    transparency::{verify_signed_body, SignedRequest},
+    world::{World, Worlds},
 };
 
-fn verify_singature<T: KeyCloner + Serialize>(req_data: &HttpRequest, req_json: &T) -> Result<()> {
+fn verify_signer<T: Serialize>(
+    req_data: &HttpRequest,
+    req_json: &T,
+    signer: &str,
+) -> Result<SignedRequest> {
     let encoded_signature = req_data
         .headers()
         .get("ed25519-singature")
-        .ok_or_else(|| anyhow::Error::msg(""))?;
-    let decoded_signature: &[u8] = &bs58::decode(encoded_signature).into_vec()?;
-    let decoded_signature = Signature::from_bytes(decoded_signature)?;
+        .ok_or_else(|| anyhow::Error::msg(""))?
+        .to_str()?;
+    let message_string = serde_json::to_string(&req_json)?;
+    verify_signed_body(&message_string, encoded_signature, signer)?;
+    Ok(SignedRequest {
+        pubkey: signer.to_string(),
+        body: message_string,
+        signature: encoded_signature.to_string(),
+    })
+}
 
-    let decoded_pubkey: &[u8] = &bs58::decode(req_json.clone_pubkey()).into_vec()?;
-    let decoded_pubkey = PublicKey::from_bytes(decoded_pubkey)?;
+/// Accepts requests signed either by the master key of the request or by a
+/// delegate key (sent in the `ed25519-delegate` header) that holds a valid,
+/// unexpired delegation covering `action`, which is returned with it.
+async fn verify_singature<T: KeyCloner + Serialize>(
+    req_data: &HttpRequest,
+    req_json: &T,
+    action: Action,
+    world: &World,
+) -> Result<(SignedRequest, Option<Delegation>)> {
+    let delegate = match req_data.headers().get("ed25519-delegate") {
+        Some(delegate) => delegate.to_str()?.to_string(),
+        None => {
+            let signed = verify_signer(req_data, req_json, &req_json.clone_pubkey())?;
+            return Ok((signed, None));
+        }
+    };
+    let delegation =
+        db_search_delegation(req_json.clone_pubkey(), delegate.clone(), world.db.clone())
+            .await
+            .map_err(|_| anyhow::Error::msg("delegation not found"))?;
+    if !delegation.permits(action, chrono::Utc::now().timestamp()) {
+        return Err(anyhow::Error::msg("action not delegated"));
+    }
+    let signed = verify_signer(req_data, req_json, &delegate)?;
+    Ok((signed, Some(delegation)))
+}
 
-    let message_string = serde_json::to_string(&req_json)?;
-    let message: &[u8] = message_string.as_bytes();
-    decoded_pubkey.verify(message, &decoded_signature)?;
+/// Reserves what a delegated request spends against the caps of the
+/// delegation. It is given back by `finish_signed_request` if the request
+/// is rejected.
+async fn reserve_spend(
+    req_data: &HttpRequest,
+    delegation: &Delegation,
+    spend: Spend,
+    world: &World,
+) -> Result<()> {
+    let db = world.db.clone();
+    if !reserve(delegation, spend, &db).await? {
+        return Err(anyhow::Error::msg("over the delegated caps"));
+    }
+    req_data.extensions_mut().insert(Reservation {
+        delegate: delegation.delegate.clone(),
+        spend,
+        db,
+    });
     Ok(())
 }
 
-async fn db_search_as_http<T: Contract>(
-    mc: web::Data<Client>,
-    pubkey: web::Path<String>,
-) -> HttpResponse {
-    let db = mc.default_database().expect("default db not specified");
+/// Queues a verified request for the transparency log. It is appended by
+/// `finish_signed_request` once the handler answered it.
+fn queue_signed_request(req_data: &HttpRequest, signed: SignedRequest, world: &World) {
+    let route = req_data
+        .match_pattern()
+        .unwrap_or_else(|| req_data.path().to_string());
+    req_data
+        .extensions_mut()
+        .insert(transparency::PendingRecord {
+            route,
+            request: signed,
+            db: world.db.clone(),
+        });
+}
+
+/// Gives back what a rejected delegated request reserved and appends the
+/// request queued by the handler to the transparency log with the status it
+/// was answered with, so rejected requests show as such.
+async fn finish_signed_request<B>(response: &ServiceResponse<B>) {
+    let status = response.status();
+    let reservation = response.request().extensions_mut().remove::<Reservation>();
+    if let Some(reservation) = reservation.filter(|_| !status.is_success()) {
+        if let Err(e) = release(&reservation.delegate, reservation.spend, &reservation.db).await {
+            tracing::error!(error = %e, "delegated spend was not released");
+        }
+    }
+    let pending = response
+        .request()
+        .extensions_mut()
+        .remove::<transparency::PendingRecord>();
+    if let Some(pending) = pending {
+        let status = status.as_u16();
+        if let Err(e) =
+            transparency::append(&pending.route, pending.request, status, &pending.db).await
+        {
+            tracing::error!(error = %e, status, "signed request was not logged");
+        }
+    }
+}
+
+/// Verifies a game action with `verify_singature`, reserves its spend if it
+/// is delegated and queues it for the transparency log.
+async fn accept_signed_request<T: KeyCloner + Serialize>(
+    req_data: &HttpRequest,
+    req_json: &T,
+    action: Action,
+    spend: Spend,
+    world: &World,
+) -> Result<(), HttpResponse> {
+    match verify_singature(req_data, req_json, action, world).await {
+        Ok((signed, delegation)) => {
+            accept_verified_request(req_data, signed, delegation, spend, world).await
+        }
+        Err(_) => Err(HttpResponse::Unauthorized().body("{}")),
+    }
+}
+
+/// Reserves the spend of a request `verify_singature` accepted and queues it
+/// for the transparency log, for handlers that price the request only once
+/// the signer is known.
+async fn accept_verified_request(
+    req_data: &HttpRequest,
+    signed: SignedRequest,
+    delegation: Option<Delegation>,
+    spend: Spend,
+    world: &World,
+) -> Result<(), HttpResponse> {
+    if let Some(delegation) = delegation {
+        if reserve_spend(req_data, &delegation, spend, world)
+            .await
+            .is_err()
+        {
+            return Err(HttpResponse::Unauthorized().body("{}"));
+        }
+    }
+    queue_signed_request(req_data, signed, world);
+    Ok(())
+}
+
+/// Verifies and queues a request that only the master key may sign.
+async fn accept_master_request<T: KeyCloner + Serialize>(
+    req_data: &HttpRequest,
+    req_json: &T,
+    world: &World,
+) -> Result<(), HttpResponse> {
+    match verify_signer(req_data, req_json, &req_json.clone_pubkey()) {
+        Ok(signed) => {
+            queue_signed_request(req_data, signed, world);
+            Ok(())
+        }
+        Err(_) => Err(HttpResponse::Unauthorized().body("{}")),
+    }
+}
+
+/// Returns the pubkey of the `Authorization: Bearer <token>` session token.
+fn verify_session(req_data: &HttpRequest, key: &SessionKey) -> Result<String> {
+    let token = req_data
+        .headers()
+        .get("Authorization")
+        .ok_or_else(|| anyhow::Error::msg(""))?
+        .to_str()?
+        .strip_prefix("Bearer ")
+        .ok_or_else(|| anyhow::Error::msg("not a bearer token"))?;
+    key.verify(token, chrono::Utc::now().timestamp())
+}
+
+async fn db_search_as_http<T: Contract>(world: World, pubkey: web::Path<String>) -> HttpResponse {
+    let db = world.db.clone();
     let pubkey = pubkey.into_inner();
     match db_search::<T>(pubkey, db).await {
         Ok(my_t) => HttpResponse::Ok().json(my_t),
@@ -43,20 +230,59 @@ async fn db_search_as_http<T: Contract>(
 }
 
 #[get("/swarm/{pubkey}")]
-async fn get_swarm(mc: web::Data<Client>, pubkey: web::Path<String>) -> HttpResponse {
-    db_search_as_http::<Swarm>(mc, pubkey).await
+async fn get_swarm(world: World, pubkey: web::Path<String>) -> HttpResponse {
+    let db = world.db.clone();
+    match ratings::db_search_swarm_rated(pubkey.into_inner(), db).await {
+        Ok(swarm) => HttpResponse::Ok().json(swarm),
+        Err(SearchError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(SearchError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(SearchError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Best rated attackers or defenders.
+#[get("/ratings/top/{kind}")]
+async fn get_leaderboard(world: World, kind: web::Path<ratings::RatingKind>) -> HttpResponse {
+    let db = world.db.clone();
+    match ratings::leaderboard(kind.into_inner(), db).await {
+        Ok(ratings) => HttpResponse::Ok().json(ratings),
+        Err(SearchError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(SearchError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(SearchError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Lays the eggs a hive produced since it was last settled. A failed
+/// settlement only leaves the read a little stale.
+async fn settle_hive(pubkey: &str, world: &World) {
+    if let Err(SearchError::DBError(e)) = production::settle(pubkey.to_string(), world).await {
+        tracing::warn!(error = %e, "hive production not settled");
+    }
+}
+
+/// Guardians and queens of other hives are only shown as estimates, the
+/// owner reads the exact hive from `/account` and scouts from `/scout/intel`.
+fn hive_estimates(hives: &[Hive]) -> Vec<scouting::HiveEstimate> {
+    hives.iter().map(scouting::HiveEstimate::from).collect()
 }
 
 #[get("/hive/get/{pubkey}")]
-async fn get_hive(mc: web::Data<Client>, pubkey: web::Path<String>) -> HttpResponse {
-    db_search_as_http::<Hive>(mc, pubkey).await
+async fn get_hive(world: World, pubkey: web::Path<String>) -> HttpResponse {
+    settle_hive(&pubkey, &world).await;
+    let db = world.db.clone();
+    match db_search::<Hive>(pubkey.into_inner(), db).await {
+        Ok(hive) => HttpResponse::Ok().json(scouting::HiveEstimate::from(&hive)),
+        Err(SearchError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(SearchError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(SearchError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
 }
 
 #[get("/hive/list/top")]
-async fn get_hive_top(mc: web::Data<Client>) -> HttpResponse {
-    let db = mc.default_database().expect("default db not specified");
+async fn get_hive_top(world: World) -> HttpResponse {
+    let db = world.db.clone();
     match db_search_hive_top(db).await {
-        Ok(hives) => HttpResponse::Ok().json(hives),
+        Ok(hives) => HttpResponse::Ok().json(hive_estimates(&hives)),
         Err(SearchError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
         Err(SearchError::NotFound) => HttpResponse::NotFound().body("{}"),
         Err(SearchError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
@@ -64,10 +290,21 @@ async fn get_hive_top(mc: web::Data<Client>) -> HttpResponse {
 }
 
 #[get("/hive/list/neigh/{pubkey}")]
-async fn get_hive_neigh(mc: web::Data<Client>, eggs: web::Path<i64>) -> HttpResponse {
-    let db = mc.default_database().expect("default db not specified");
+async fn get_hive_neigh(world: World, eggs: web::Path<i64>) -> HttpResponse {
+    let db = world.db.clone();
     match db_search_hive_neigh(eggs.into_inner(), db).await {
-        Ok(hives) => HttpResponse::Ok().json(hives),
+        Ok(hives) => HttpResponse::Ok().json(hive_estimates(&hives)),
+        Err(SearchError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(SearchError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(SearchError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Hives within the power band of `pubkey`, as estimates.
+#[get("/hive/targets/{pubkey}")]
+async fn get_hive_targets(world: World, pubkey: web::Path<String>) -> HttpResponse {
+    match matchmaking::suggested_targets(pubkey.into_inner(), &world).await {
+        Ok(hives) => HttpResponse::Ok().json(hive_estimates(&hives)),
         Err(SearchError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
         Err(SearchError::NotFound) => HttpResponse::NotFound().body("{}"),
         Err(SearchError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
@@ -75,13 +312,16 @@ async fn get_hive_neigh(mc: web::Data<Client>, eggs: web::Path<i64>) -> HttpResp
 }
 
 #[get("/sacred_hive/get/{pubkey}")]
-async fn get_sacred_hive(mc: web::Data<Client>, pubkey: web::Path<String>) -> HttpResponse {
-    db_search_as_http::<SacredHive>(mc, pubkey).await
+async fn get_sacred_hive(world: World, pubkey: web::Path<String>) -> HttpResponse {
+    db_search_as_http::<SacredHive>(world, pubkey).await
 }
 
 #[get("/sacred_hive/trigger/{pubkey}")]
-async fn trigger_sacred_hive(mc: web::Data<Client>, pubkey: web::Path<String>) -> HttpResponse {
-    match trigger(pubkey.into_inner(), mc.into_inner()).await {
+async fn trigger_sacred_hive(world: World, pubkey: web::Path<String>) -> HttpResponse {
+    let pubkey = pubkey.into_inner();
+    let result = retry_transient(|| trigger(pubkey.clone(), &world)).await;
+    metrics::observe_transaction("trigger", &result);
+    match result {
         Ok(_) => HttpResponse::Ok().body("{}"),
         Err(SearchError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
         Err(SearchError::NotFound) => HttpResponse::NotFound().body("{}"),
@@ -90,10 +330,8 @@ async fn trigger_sacred_hive(mc: web::Data<Client>, pubkey: web::Path<String>) -
 }
 
 #[get("/airdrop/{pubkey}")]
-async fn get_airdrop(mc: web::Data<Client>, pubkey: web::Path<String>) -> HttpResponse {
-    let db = mc.default_database().expect("default db not specified");
-    let pubkey = pubkey.into_inner();
-    match airdrop(pubkey, db).await {
+async fn get_airdrop(world: World, pubkey: web::Path<String>) -> HttpResponse {
+    match airdrop(pubkey.into_inner(), &world).await {
         Ok(()) => HttpResponse::Ok().body("{}"),
         Err(AirdropError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
         Err(AirdropError::AlreadyExists) => HttpResponse::Forbidden().body("{}"),
@@ -103,128 +341,937 @@ async fn get_airdrop(mc: web::Data<Client>, pubkey: web::Path<String>) -> HttpRe
 
 #[post("/hatchery")]
 async fn post_hatchery(
-    mc: web::Data<Client>,
+    world: World,
     req: HttpRequest,
     item: web::Json<HatchRequest>,
 ) -> HttpResponse {
     let req_json = item.into_inner();
-    if verify_singature(&req, &req_json).is_err() {
-        return HttpResponse::Unauthorized().body("{}");
+    let spend = Spend::eggs(req_json.eggs);
+    if let Err(response) =
+        accept_signed_request(&req, &req_json, Action::Hatch, spend, &world).await
+    {
+        return response;
     }
-    match process_hatch_request(req_json, mc.into_inner()).await {
-        Ok(()) => HttpResponse::Ok().body("{}"),
+    let result = retry_transient(|| incubation::incubate(req_json.clone(), &world)).await;
+    metrics::observe_transaction("hatch", &result);
+    match result {
+        Ok(_) => HttpResponse::Ok().body("{}"),
         Err(StakeError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
         Err(StakeError::NotEnoughTokens) => HttpResponse::Forbidden().body("{}"),
+        Err(StakeError::NotStakeable) => HttpResponse::Conflict().body("{}"),
+        Err(StakeError::InvalidLock) => HttpResponse::Conflict().body("{}"),
+        Err(StakeError::Locked) => HttpResponse::build(StatusCode::LOCKED).body("{}"),
         Err(StakeError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
     }
 }
 
-fn parse_stake_result(r: Result<(), StakeError>) -> HttpResponse {
+/// The hatch queue of `pubkey`, only for a session of that pubkey.
+#[get("/hatchery/jobs/{pubkey}")]
+async fn get_hatch_jobs(
+    world: World,
+    key: web::Data<SessionKey>,
+    req: HttpRequest,
+    pubkey: web::Path<String>,
+) -> HttpResponse {
+    let pubkey = pubkey.into_inner();
+    match verify_session(&req, &key) {
+        Ok(owner) if owner == pubkey => (),
+        _ => return HttpResponse::Unauthorized().body("{}"),
+    }
+    match incubation::pending_jobs(pubkey, &world).await {
+        Ok(jobs) => HttpResponse::Ok().json(jobs),
+        Err(SearchError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(SearchError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(SearchError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Claims a completed hatch job. Delegates that may hatch may also claim.
+#[post("/hatchery/claim")]
+async fn post_hatch_claim(
+    world: World,
+    req: HttpRequest,
+    item: web::Json<incubation::HatchClaim>,
+) -> HttpResponse {
+    let req_json = item.into_inner();
+    if let Err(response) =
+        accept_signed_request(&req, &req_json, Action::Hatch, Spend::default(), &world).await
+    {
+        return response;
+    }
+    let result = retry_transient(|| incubation::claim(req_json.clone(), &world)).await;
+    metrics::observe_transaction("hatch_claim", &result);
+    match result {
+        Ok(hatched) => HttpResponse::Ok().json(hatched),
+        Err(incubation::ClaimError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(incubation::ClaimError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(incubation::ClaimError::NotReady) => HttpResponse::Forbidden().body("{}"),
+        Err(incubation::ClaimError::DBError(e)) => {
+            HttpResponse::InternalServerError().body(e.to_string())
+        }
+    }
+}
+
+fn parse_stake_result(operation: &str, r: Result<(), StakeError>) -> HttpResponse {
+    metrics::observe_transaction(operation, &r);
     match r {
         Ok(()) => HttpResponse::Ok().body("{}"),
         Err(StakeError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
         Err(StakeError::NotEnoughTokens) => HttpResponse::Forbidden().body("{}"),
+        Err(StakeError::NotStakeable) => HttpResponse::Conflict().body("{}"),
+        Err(StakeError::InvalidLock) => HttpResponse::Conflict().body("{}"),
+        Err(StakeError::Locked) => HttpResponse::build(StatusCode::LOCKED).body("{}"),
         Err(StakeError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
     }
 }
 
 #[post("/sacred_hive/stake")]
 async fn stake_sacred_hive(
-    mc: web::Data<Client>,
+    world: World,
     req: HttpRequest,
     item: web::Json<SacredHive>,
 ) -> HttpResponse {
     let req_json = item.into_inner();
-    if verify_singature(&req, &req_json).is_err() {
-        return HttpResponse::Unauthorized().body("{}");
+    let spend = Spend::eggs(req_json.eggs);
+    if let Err(response) =
+        accept_signed_request(&req, &req_json, Action::SacredHiveStake, spend, &world).await
+    {
+        return response;
     }
-    parse_stake_result(stake::<SacredHive>(req_json, mc.into_inner()).await)
+    parse_stake_result(
+        "sacred_hive_stake",
+        retry_transient(|| stake::<SacredHive>(req_json.clone(), &world)).await,
+    )
 }
 
 #[post("/sacred_hive/unstake")]
 async fn unstake_sacred_hive(
-    mc: web::Data<Client>,
+    world: World,
     req: HttpRequest,
     item: web::Json<SacredHive>,
 ) -> HttpResponse {
     let req_json = item.into_inner();
-    if verify_singature(&req, &req_json).is_err() {
-        return HttpResponse::Unauthorized().body("{}");
+    // unstaking returns eggs, nothing counts against a delegate
+    if let Err(response) = accept_signed_request(
+        &req,
+        &req_json,
+        Action::SacredHiveUnstake,
+        Spend::default(),
+        &world,
+    )
+    .await
+    {
+        return response;
     }
-    parse_stake_result(unstake::<SacredHive>(req_json, mc.into_inner()).await)
+    parse_stake_result(
+        "sacred_hive_unstake",
+        retry_transient(|| unstake::<SacredHive>(req_json.clone(), &world)).await,
+    )
 }
 
-#[post("/hive/stake")]
-async fn stake_hive(
-    mc: web::Data<Client>,
+#[post("/sacred_hive/lock")]
+async fn lock_sacred_hive(
+    world: World,
     req: HttpRequest,
-    item: web::Json<Hive>,
+    item: web::Json<locks::LockStake>,
 ) -> HttpResponse {
     let req_json = item.into_inner();
-    if verify_singature(&req, &req_json).is_err() {
-        return HttpResponse::Unauthorized().body("{}");
+    if let Err(response) = accept_signed_request(
+        &req,
+        &req_json,
+        Action::SacredHiveStake,
+        Spend::default(),
+        &world,
+    )
+    .await
+    {
+        return response;
+    }
+    let result = retry_transient(|| locks::lock(req_json.clone(), &world)).await;
+    metrics::observe_transaction("sacred_hive_lock", &result);
+    match result {
+        Ok(lock) => HttpResponse::Ok().json(lock),
+        Err(StakeError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(StakeError::NotEnoughTokens) => HttpResponse::Forbidden().body("{}"),
+        Err(StakeError::NotStakeable) => HttpResponse::Conflict().body("{}"),
+        Err(StakeError::InvalidLock) => HttpResponse::Conflict().body("{}"),
+        Err(StakeError::Locked) => HttpResponse::build(StatusCode::LOCKED).body("{}"),
+        Err(StakeError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[post("/sacred_hive/unlock")]
+async fn unlock_sacred_hive(
+    world: World,
+    req: HttpRequest,
+    item: web::Json<locks::Unlock>,
+) -> HttpResponse {
+    let req_json = item.into_inner();
+    if let Err(response) = accept_signed_request(
+        &req,
+        &req_json,
+        Action::SacredHiveUnstake,
+        Spend::default(),
+        &world,
+    )
+    .await
+    {
+        return response;
+    }
+    parse_stake_result(
+        "sacred_hive_unlock",
+        retry_transient(|| locks::unlock(req_json.clone(), &world)).await,
+    )
+}
+
+#[get("/sacred_hive/locks/{pubkey}")]
+async fn get_sacred_hive_locks(world: World, pubkey: web::Path<String>) -> HttpResponse {
+    let db = world.db.clone();
+    match locks::positions(pubkey.into_inner(), db).await {
+        Ok(locks) => HttpResponse::Ok().json(locks),
+        Err(SearchError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(SearchError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(SearchError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[post("/hive/stake")]
+async fn stake_hive(world: World, req: HttpRequest, item: web::Json<HiveRequest>) -> HttpResponse {
+    let req_json = item.into_inner();
+    let spend = Spend::eggs(req_json.eggs);
+    if let Err(response) =
+        accept_signed_request(&req, &req_json, Action::HiveStake, spend, &world).await
+    {
+        return response;
     }
-    parse_stake_result(stake::<Hive>(req_json, mc.into_inner()).await)
+    parse_stake_result(
+        "hive_stake",
+        retry_transient(|| stake::<Hive>(req_json.to_hive(), &world)).await,
+    )
 }
 
 #[post("/hive/unstake")]
 async fn unstake_hive(
-    mc: web::Data<Client>,
+    world: World,
     req: HttpRequest,
-    item: web::Json<Hive>,
+    item: web::Json<HiveRequest>,
 ) -> HttpResponse {
     let req_json = item.into_inner();
-    if verify_singature(&req, &req_json).is_err() {
-        return HttpResponse::Unauthorized().body("{}");
+    // unstaking returns eggs, nothing counts against a delegate
+    if let Err(response) = accept_signed_request(
+        &req,
+        &req_json,
+        Action::HiveUnstake,
+        Spend::default(),
+        &world,
+    )
+    .await
+    {
+        return response;
     }
-    parse_stake_result(unstake::<Hive>(req_json, mc.into_inner()).await)
+    parse_stake_result(
+        "hive_unstake",
+        retry_transient(|| unstake::<Hive>(req_json.to_hive(), &world)).await,
+    )
 }
 
 #[post("/hive/attack")]
-async fn post_attack(
-    mc: web::Data<Client>,
-    req: HttpRequest,
-    item: web::Json<Attack>,
-) -> HttpResponse {
+async fn post_attack(world: World, req: HttpRequest, item: web::Json<Attack>) -> HttpResponse {
     let req_json = item.into_inner();
-    if verify_singature(&req, &req_json).is_err() {
-        return HttpResponse::Unauthorized().body("{}");
+    let (signed, delegation) = match verify_singature(&req, &req_json, Action::Attack, &world).await
+    {
+        Ok(verified) => verified,
+        Err(_) => return HttpResponse::Unauthorized().body("{}"),
+    };
+    // the price of hired mercenaries counts against the eggs of a delegate,
+    // it is only looked up for verified requests
+    let price = match (&delegation, &req_json.mercenaries) {
+        (Some(_), Some(offer)) => match mercenaries::listed_price(offer, &world.db).await {
+            Ok(price) => price.unwrap_or(0),
+            Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+        },
+        _ => 0,
+    };
+    let spend = Spend {
+        berserkers: req_json.berserkers,
+        eggs: price,
+    };
+    if let Err(response) = accept_verified_request(&req, signed, delegation, spend, &world).await {
+        return response;
     }
-    match attack(req_json, mc.into_inner()).await {
-        Ok(()) => HttpResponse::Ok().body("{}"),
+    let result = retry_transient(|| march::launch(req_json.clone(), &world)).await;
+    metrics::observe_transaction("attack", &result);
+    match result {
+        Ok(_) => HttpResponse::Ok().body("{}"),
         Err(AttackError::NotEnoughTokens) => HttpResponse::Forbidden().body("{}"),
         Err(AttackError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
         Err(AttackError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(AttackError::OutOfBand) => HttpResponse::Conflict().body("{}"),
+        Err(AttackError::OwnHive) => HttpResponse::Conflict().body("{}"),
         Err(AttackError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
     }
 }
 
+fn parse_march_result(operation: &str, r: Result<(), march::MarchError>) -> HttpResponse {
+    metrics::observe_transaction(operation, &r);
+    match r {
+        Ok(()) => HttpResponse::Ok().body("{}"),
+        Err(march::MarchError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(march::MarchError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(march::MarchError::NotEnoughTokens) => HttpResponse::Forbidden().body("{}"),
+        Err(march::MarchError::Arrived) => HttpResponse::Conflict().body("{}"),
+        Err(march::MarchError::DBError(e)) => {
+            HttpResponse::InternalServerError().body(e.to_string())
+        }
+    }
+}
+
+#[post("/march/recall")]
+async fn post_recall(
+    world: World,
+    req: HttpRequest,
+    item: web::Json<march::Recall>,
+) -> HttpResponse {
+    let req_json = item.into_inner();
+    if let Err(response) =
+        accept_signed_request(&req, &req_json, Action::Attack, Spend::default(), &world).await
+    {
+        return response;
+    }
+    parse_march_result(
+        "recall",
+        retry_transient(|| march::recall(req_json.clone(), &world)).await,
+    )
+}
+
+#[post("/march/help")]
+async fn post_help_call(
+    world: World,
+    req: HttpRequest,
+    item: web::Json<march::HelpCall>,
+) -> HttpResponse {
+    let req_json = item.into_inner();
+    if let Err(response) =
+        accept_signed_request(&req, &req_json, Action::Defend, Spend::default(), &world).await
+    {
+        return response;
+    }
+    parse_march_result(
+        "help_call",
+        retry_transient(|| march::call_for_help(req_json.clone(), &world)).await,
+    )
+}
+
+#[post("/march/reinforce")]
+async fn post_reinforce(
+    world: World,
+    req: HttpRequest,
+    item: web::Json<march::Reinforce>,
+) -> HttpResponse {
+    let req_json = item.into_inner();
+    if let Err(response) =
+        accept_signed_request(&req, &req_json, Action::Defend, Spend::default(), &world).await
+    {
+        return response;
+    }
+    parse_march_result(
+        "reinforce",
+        retry_transient(|| march::reinforce(req_json.clone(), &world)).await,
+    )
+}
+
+/// Marches of `pubkey`, with the reinforcements only the session may see.
+#[get("/march/list/{pubkey}")]
+async fn get_marches(
+    world: World,
+    key: web::Data<SessionKey>,
+    req: HttpRequest,
+    pubkey: web::Path<String>,
+) -> HttpResponse {
+    let viewer = verify_session(&req, &key).ok();
+    let db = world.db.clone();
+    match march::marches(pubkey.into_inner(), db).await {
+        Ok(marches) => HttpResponse::Ok().json(march::seen_by(marches, viewer.as_deref())),
+        Err(SearchError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(SearchError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(SearchError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[get("/march/help/list")]
+async fn get_help_calls(
+    world: World,
+    key: web::Data<SessionKey>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let viewer = verify_session(&req, &key).ok();
+    let db = world.db.clone();
+    match march::help_calls(db).await {
+        Ok(marches) => HttpResponse::Ok().json(march::seen_by(marches, viewer.as_deref())),
+        Err(SearchError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(SearchError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(SearchError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[post("/hive/fortify")]
+async fn post_fortify(
+    world: World,
+    req: HttpRequest,
+    item: web::Json<fortifications::Fortify>,
+) -> HttpResponse {
+    let req_json = item.into_inner();
+    let spend = Spend::eggs(
+        world
+            .config
+            .fortifications
+            .level_cost(req_json.building, req_json.level),
+    );
+    if let Err(response) =
+        accept_signed_request(&req, &req_json, Action::Fortify, spend, &world).await
+    {
+        return response;
+    }
+    let result = retry_transient(|| fortifications::fortify(req_json.clone(), &world)).await;
+    metrics::observe_transaction("fortify", &result);
+    match result {
+        Ok(fortifications) => HttpResponse::Ok().json(fortifications),
+        Err(fortifications::FortifyError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(fortifications::FortifyError::InvalidLevel) => HttpResponse::Conflict().body("{}"),
+        Err(fortifications::FortifyError::NotEnoughTokens) => HttpResponse::Forbidden().body("{}"),
+        Err(fortifications::FortifyError::DBError(e)) => {
+            HttpResponse::InternalServerError().body(e.to_string())
+        }
+    }
+}
+
+#[get("/hive/fortifications/{pubkey}")]
+async fn get_fortifications(world: World, pubkey: web::Path<String>) -> HttpResponse {
+    let db = world.db.clone();
+    match fortifications::db_search_fortifications(pubkey.into_inner(), db).await {
+        Ok(fortifications) => HttpResponse::Ok().json(fortifications),
+        Err(SearchError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(SearchError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(SearchError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[post("/bounty")]
+async fn post_bounty(
+    world: World,
+    req: HttpRequest,
+    item: web::Json<bounties::PlaceBounty>,
+) -> HttpResponse {
+    let req_json = item.into_inner();
+    let spend = Spend::eggs(req_json.eggs);
+    if let Err(response) =
+        accept_signed_request(&req, &req_json, Action::Bounty, spend, &world).await
+    {
+        return response;
+    }
+    let result = retry_transient(|| bounties::place(req_json.clone(), &world)).await;
+    metrics::observe_transaction("bounty", &result);
+    match result {
+        Ok(bounty) => HttpResponse::Ok().json(bounty),
+        Err(bounties::BountyError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(bounties::BountyError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(bounties::BountyError::NotEnoughTokens) => HttpResponse::Forbidden().body("{}"),
+        Err(bounties::BountyError::OwnHive) => HttpResponse::Conflict().body("{}"),
+        Err(bounties::BountyError::DBError(e)) => {
+            HttpResponse::InternalServerError().body(e.to_string())
+        }
+    }
+}
+
+#[get("/bounty/list/{sort}")]
+async fn get_bounties(world: World, sort: web::Path<bounties::BountySort>) -> HttpResponse {
+    let db = world.db.clone();
+    match bounties::open_bounties(sort.into_inner(), db).await {
+        Ok(bounties) => HttpResponse::Ok().json(bounties),
+        Err(SearchError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(SearchError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(SearchError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[post("/mercenaries/list")]
+async fn post_mercenaries(
+    world: World,
+    req: HttpRequest,
+    item: web::Json<mercenaries::ListMercenaries>,
+) -> HttpResponse {
+    let req_json = item.into_inner();
+    let spend = Spend::berserkers(req_json.berserkers);
+    if let Err(response) =
+        accept_signed_request(&req, &req_json, Action::Mercenaries, spend, &world).await
+    {
+        return response;
+    }
+    let result = retry_transient(|| mercenaries::list(req_json.clone(), &world)).await;
+    metrics::observe_transaction("enlist", &result);
+    match result {
+        Ok(offer) => HttpResponse::Ok().json(offer),
+        Err(mercenaries::MercenaryError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(mercenaries::MercenaryError::InvalidTerms) => HttpResponse::BadRequest().body("{}"),
+        Err(mercenaries::MercenaryError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(mercenaries::MercenaryError::NotEnoughTokens) => HttpResponse::Forbidden().body("{}"),
+        Err(mercenaries::MercenaryError::DBError(e)) => {
+            HttpResponse::InternalServerError().body(e.to_string())
+        }
+    }
+}
+
+#[post("/mercenaries/withdraw")]
+async fn post_withdraw_mercenaries(
+    world: World,
+    req: HttpRequest,
+    item: web::Json<mercenaries::WithdrawMercenaries>,
+) -> HttpResponse {
+    let req_json = item.into_inner();
+    if let Err(response) = accept_signed_request(
+        &req,
+        &req_json,
+        Action::Mercenaries,
+        Spend::default(),
+        &world,
+    )
+    .await
+    {
+        return response;
+    }
+    let result = retry_transient(|| mercenaries::withdraw(req_json.clone(), &world)).await;
+    metrics::observe_transaction("discharge", &result);
+    match result {
+        Ok(()) => HttpResponse::Ok().body("{}"),
+        Err(mercenaries::MercenaryError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(mercenaries::MercenaryError::InvalidTerms) => HttpResponse::BadRequest().body("{}"),
+        Err(mercenaries::MercenaryError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(mercenaries::MercenaryError::NotEnoughTokens) => HttpResponse::Forbidden().body("{}"),
+        Err(mercenaries::MercenaryError::DBError(e)) => {
+            HttpResponse::InternalServerError().body(e.to_string())
+        }
+    }
+}
+
+#[get("/mercenaries/offers")]
+async fn get_mercenary_offers(world: World) -> HttpResponse {
+    let db = world.db.clone();
+    match mercenaries::listed_offers(db).await {
+        Ok(offers) => HttpResponse::Ok().json(offers),
+        Err(SearchError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(SearchError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(SearchError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[post("/scout")]
+async fn post_scout(
+    world: World,
+    req: HttpRequest,
+    item: web::Json<scouting::Scout>,
+) -> HttpResponse {
+    let req_json = item.into_inner();
+    let spend = match req_json.pay_with {
+        scouting::ScoutPayment::Berserkers => Spend::berserkers(world.config.scout_berserkers),
+        scouting::ScoutPayment::Eggs => Spend::eggs(world.config.scout_eggs),
+    };
+    if let Err(response) =
+        accept_signed_request(&req, &req_json, Action::Scout, spend, &world).await
+    {
+        return response;
+    }
+    let result = retry_transient(|| scouting::scout(req_json.clone(), &world)).await;
+    metrics::observe_transaction("scout", &result);
+    match result {
+        Ok(intel) => HttpResponse::Ok().json(intel),
+        Err(scouting::ScoutError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(scouting::ScoutError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(scouting::ScoutError::NotEnoughTokens) => HttpResponse::Forbidden().body("{}"),
+        Err(scouting::ScoutError::DBError(e)) => {
+            HttpResponse::InternalServerError().body(e.to_string())
+        }
+    }
+}
+
+#[get("/scout/intel")]
+async fn get_intel(world: World, key: web::Data<SessionKey>, req: HttpRequest) -> HttpResponse {
+    let pubkey = match verify_session(&req, &key) {
+        Ok(pubkey) => pubkey,
+        Err(_) => return HttpResponse::Unauthorized().body("{}"),
+    };
+    let db = world.db.clone();
+    match scouting::live_intel(pubkey, db).await {
+        Ok(intel) => HttpResponse::Ok().json(intel),
+        Err(SearchError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(SearchError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(SearchError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+fn parse_delegation_result(r: Result<(), DelegationError>) -> HttpResponse {
+    match r {
+        Ok(()) => HttpResponse::Ok().body("{}"),
+        Err(DelegationError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(DelegationError::InvalidCertificate) => HttpResponse::BadRequest().body("{}"),
+        Err(DelegationError::AlreadyExists) => HttpResponse::Forbidden().body("{}"),
+        Err(DelegationError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(DelegationError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[post("/delegation/create")]
+async fn post_delegation(
+    world: World,
+    req: HttpRequest,
+    item: web::Json<Delegation>,
+) -> HttpResponse {
+    let req_json = item.into_inner();
+    if let Err(response) = accept_master_request(&req, &req_json, &world).await {
+        return response;
+    }
+    let db = world.db.clone();
+    parse_delegation_result(create_delegation(req_json, db).await)
+}
+
+#[post("/delegation/revoke")]
+async fn post_revoke_delegation(
+    world: World,
+    req: HttpRequest,
+    item: web::Json<RevokeDelegation>,
+) -> HttpResponse {
+    let req_json = item.into_inner();
+    if let Err(response) = accept_master_request(&req, &req_json, &world).await {
+        return response;
+    }
+    let db = world.db.clone();
+    parse_delegation_result(revoke_delegation(req_json, db).await)
+}
+
+#[get("/auth/challenge/{pubkey}")]
+async fn get_challenge(world: World, pubkey: web::Path<String>) -> HttpResponse {
+    let db = world.db.clone();
+    match create_challenge(pubkey.into_inner(), db).await {
+        Ok(challenge) => HttpResponse::Ok().json(challenge),
+        Err(SessionError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(SessionError::InvalidChallenge) => HttpResponse::Unauthorized().body("{}"),
+        Err(SessionError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[post("/auth/login")]
+async fn post_login(
+    world: World,
+    key: web::Data<SessionKey>,
+    req: HttpRequest,
+    item: web::Json<Login>,
+) -> HttpResponse {
+    let req_json = item.into_inner();
+    if verify_signer(&req, &req_json, &req_json.clone_pubkey()).is_err() {
+        return HttpResponse::Unauthorized().body("{}");
+    }
+    let db = world.db.clone();
+    match login(req_json, &key, db).await {
+        Ok(session) => HttpResponse::Ok().json(session),
+        Err(SessionError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(SessionError::InvalidChallenge) => HttpResponse::Unauthorized().body("{}"),
+        Err(SessionError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[get("/account")]
+async fn get_account(world: World, key: web::Data<SessionKey>, req: HttpRequest) -> HttpResponse {
+    let pubkey = match verify_session(&req, &key) {
+        Ok(pubkey) => pubkey,
+        Err(_) => return HttpResponse::Unauthorized().body("{}"),
+    };
+    settle_hive(&pubkey, &world).await;
+    let db = world.db.clone();
+    match db_search_account(pubkey, db).await {
+        Ok(account) => HttpResponse::Ok().json(account),
+        Err(SearchError::InvalidPubkey) => HttpResponse::BadRequest().body("{}"),
+        Err(SearchError::NotFound) => HttpResponse::NotFound().body("{}"),
+        Err(SearchError::DBError(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[get("/notifications")]
+async fn get_notifications(
+    world: World,
+    key: web::Data<SessionKey>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let pubkey = match verify_session(&req, &key) {
+        Ok(pubkey) => pubkey,
+        Err(_) => return HttpResponse::Unauthorized().body("{}"),
+    };
+    match notifications::recent(&pubkey, &world.db).await {
+        Ok(notifications) => HttpResponse::Ok().json(notifications),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[get("/transparency/head")]
+async fn get_transparency_head(world: World) -> HttpResponse {
+    let db = world.db.clone();
+    match transparency::head(&db).await {
+        Ok(head) => HttpResponse::Ok().json(head),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[get("/transparency/log/{from}")]
+async fn get_transparency_log(world: World, from: web::Path<i64>) -> HttpResponse {
+    let db = world.db.clone();
+    match transparency::page(from.into_inner(), &db).await {
+        Ok(records) => HttpResponse::Ok().json(records),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[get("/economy/stats")]
+async fn get_economy_stats(world: World) -> HttpResponse {
+    let db = world.db.clone();
+    match economy::stats(&db).await {
+        Ok(stats) => HttpResponse::Ok().json(stats),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[get("/economy/snapshots/{from}/{to}")]
+async fn get_economy_snapshots(world: World, range: web::Path<(i64, i64)>) -> HttpResponse {
+    let db = world.db.clone();
+    let (from, to) = range.into_inner();
+    match economy::snapshots(from, to, &db).await {
+        Ok(snapshots) => HttpResponse::Ok().json(snapshots),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[get("/metrics")]
+async fn get_metrics() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::render())
+}
+
+#[get("/healthz")]
+async fn get_healthz(world: World) -> HttpResponse {
+    let db = world.db.clone();
+    let health = metrics::check_health(&db).await;
+    match health.mongo {
+        true => HttpResponse::Ok().json(health),
+        false => HttpResponse::ServiceUnavailable().json(health),
+    }
+}
+
+/// Ready when the database of every world is reachable and supports
+/// transactions.
+#[get("/readyz")]
+async fn get_readyz(worlds: web::Data<Worlds>) -> HttpResponse {
+    let health = metrics::check_worlds(&worlds).await;
+    match health
+        .values()
+        .all(|world| world.mongo && world.transactions)
+    {
+        true => HttpResponse::Ok().json(health),
+        false => HttpResponse::ServiceUnavailable().json(health),
+    }
+}
+
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_airdrop)
+        .service(get_swarm)
+        .service(get_hive)
+        .service(get_hive_top)
+        .service(get_hive_neigh)
+        .service(get_hive_targets)
+        .service(get_leaderboard)
+        .service(get_sacred_hive)
+        .service(stake_sacred_hive)
+        .service(stake_hive)
+        .service(unstake_sacred_hive)
+        .service(lock_sacred_hive)
+        .service(unlock_sacred_hive)
+        .service(get_sacred_hive_locks)
+        .service(unstake_hive)
+        .service(post_attack)
+        .service(post_recall)
+        .service(post_help_call)
+        .service(post_reinforce)
+        .service(get_marches)
+        .service(get_help_calls)
+        .service(post_scout)
+        .service(post_fortify)
+        .service(get_fortifications)
+        .service(post_bounty)
+        .service(get_bounties)
+        .service(post_mercenaries)
+        .service(post_withdraw_mercenaries)
+        .service(get_mercenary_offers)
+        .service(get_intel)
+        .service(post_hatchery)
+        .service(get_hatch_jobs)
+        .service(post_hatch_claim)
+        .service(trigger_sacred_hive)
+        .service(post_delegation)
+        .service(post_revoke_delegation)
+        .service(get_challenge)
+        .service(post_login)
+        .service(get_account)
+        .service(get_notifications)
+        .service(get_transparency_head)
+        .service(get_transparency_log)
+        .service(get_economy_stats)
+        .service(get_economy_snapshots);
+}
+
+/// Maintenance commands that run on the default world instead of the server:
+/// `ledger-rebuild [target_db] [--force]`, `check-economy`,
+/// `seed <seed> <players> [preset] [target_db]`, `export <file>` and
+/// `restore <file> [target_db]`. `verify-log` does not need a database and
+/// is handled before connecting.
+async fn run_command(command: &str, world: &World) -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(2).collect();
+    let db = &world.db;
+    let database =
+        |name: Option<&String>| name.map_or_else(|| db.clone(), |name| world.client.database(name));
+    let ok = match command {
+        "ledger-rebuild" => {
+            let force = args.iter().any(|arg| arg == "--force");
+            let target = args
+                .iter()
+                .find(|arg| *arg != "--force")
+                .map(|name| world.client.database(name));
+            ledger::run_rebuild(db, target, force)
+                .await
+                .map_err(|e| std::io::Error::other(e.to_string()))?
+        }
+        "seed" => seed::run_seed(&args, database(args.get(3)))
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))?,
+        "export" => archive::run_export(&args, world)
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))?,
+        "restore" => archive::run_restore(&args, database(args.get(1)))
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))?,
+        "check-economy" => economy::run_check(world)
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))?,
+        _ => {
+            eprintln!("unknown command {}", command);
+            false
+        }
+    };
+    if !ok {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Brings the database of a world to the current schema.
+async fn prepare_world(world: &World) -> std::io::Result<()> {
+    let db = &world.db;
+    if let Err(e) = migrations::migrate(db).await {
+        return Err(std::io::Error::other(format!(
+            "world {}: {}",
+            world.name, e
+        )));
+    }
+    create_db_indexes(db).await;
+    create_delegation_indexes(db).await;
+    create_challenge_indexes(db).await;
+    ledger::create_ledger_indexes(db).await;
+    transparency::create_transparency_indexes(db).await;
+    production::create_production_indexes(db).await;
+    incubation::create_incubation_indexes(db).await;
+    march::create_march_indexes(db).await;
+    notifications::create_notification_indexes(db).await;
+    scouting::create_intel_indexes(db).await;
+    fortifications::create_fortification_indexes(db).await;
+    ratings::create_rating_indexes(db).await;
+    matchmaking::create_power_indexes(db).await;
+    bounties::create_bounty_indexes(db).await;
+    mercenaries::create_mercenary_indexes(db).await;
+    locks::create_lock_indexes(db).await;
+    economy::create_snapshot_collection(db).await;
+    Ok(())
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    if std::env::args().nth(1).as_deref() == Some("verify-log") {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        std::process::exit(if transparency::run_verify(&args) {
+            0
+        } else {
+            1
+        });
+    }
+    logging::init();
+    let config = web::Data::new(ServerConfig::from_env());
     let uri = std::env::var("MONGODB_URI").unwrap_or_else(|_| "mongodb://localhost:27017".into());
     let mc = Client::with_uri_str(uri).await.expect("failed to connect");
-    let db = &mc.default_database().expect("default db not specified");
-    create_db_indexes(db).await;
-    init_mockup_db(db).await;
-
-    HttpServer::new(move || {
-        App::new()
-            .app_data(web::Data::new(mc.clone()))
-            .service(get_airdrop)
-            .service(get_swarm)
-            .service(get_hive)
-            .service(get_hive_top)
-            .service(get_hive_neigh)
-            .service(get_sacred_hive)
-            .service(stake_sacred_hive)
-            .service(stake_hive)
-            .service(unstake_sacred_hive)
-            .service(unstake_hive)
-            .service(post_attack)
-            .service(post_hatchery)
-            .service(trigger_sacred_hive)
-    })
-    .bind(("127.0.0.1", 9000))?
-    .run()
-    .await
+    let worlds = web::Data::new(Worlds::load(mc, config.worlds_file.as_deref())?);
+    for world in worlds.iter() {
+        prepare_world(world).await?;
+    }
+    if let Some(command) = std::env::args().nth(1) {
+        return run_command(&command, worlds.default_world()).await;
+    }
+    for world in worlds.iter() {
+        seed::seed_on_startup(&world.db).await;
+        matchmaking::store_powers(world, None)
+            .await
+            .expect("storing powers should succeed");
+        actix_web::rt::spawn(economy::snapshot_task(world.clone()));
+        actix_web::rt::spawn(march::march_task(world.clone()));
+        actix_web::rt::spawn(bounties::bounty_task(world.clone()));
+    }
+    let session_key = web::Data::new(SessionKey::from_env());
+    let tls = config.tls()?;
+    let bind_address = (config.host.clone(), config.port);
+
+    let server = HttpServer::new(move || {
+        let mut app = App::new()
+            .app_data(worlds.clone())
+            .app_data(session_key.clone())
+            .app_data(web::JsonConfig::default().limit(config.body_limit))
+            .app_data(web::PayloadConfig::default().limit(config.body_limit))
+            .wrap_fn(|req, srv| {
+                let response = srv.call(req);
+                async move {
+                    let response = response.await?;
+                    finish_signed_request(&response).await;
+                    Ok(response)
+                }
+            })
+            .wrap(config.cors())
+            .wrap_fn(|req, srv| {
+                let start = std::time::Instant::now();
+                let response = srv.call(req);
+                async move {
+                    let response = response.await?;
+                    metrics::observe_request(&response, start);
+                    Ok(response)
+                }
+            })
+            .wrap_fn(logging::trace_request)
+            .service(get_metrics)
+            .service(get_healthz)
+            .service(get_readyz)
+            .service(
+                web::scope(&format!("{}/worlds/{{world}}", config.api_prefix())).configure(routes),
+            )
+            .service(web::scope(config.api_prefix()).configure(routes));
+        if let Some(client_dir) = &config.client_dir {
+            app = app.service(Files::new("/", client_dir).index_file("index.html"));
+        }
+        app
+    });
+    let server = match tls {
+        Some(tls) => server.bind_rustls(bind_address, tls)?,
+        None => server.bind(bind_address)?,
+    };
+    server.run().await
 }
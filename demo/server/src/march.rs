@@ -0,0 +1,930 @@
+use {
+    super::{
+        bounties, fortifications, incubation,
+        ledger::{self, Change, Operation},
+        matchmaking,
+        mercenaries::{self, Hire},
+        metrics,
+        model::*,
+        notifications::{self, Event},
+        production, ratings,
+        units::{self, BERSERKERS, EGGS, GUARDIANS},
+        world::World,
+    },
+    futures::stream::TryStreamExt,
+    mongodb::{
+        bson::{doc, oid::ObjectId},
+        error::Error as MongoError,
+        options::{FindOptions, IndexOptions},
+        ClientSession, Database, IndexModel,
+    },
+    serde::{Deserialize, Serialize},
+    std::{collections::BTreeMap, time::Duration},
+};
+
+pub const MARCHES_COLL_NAME: &str = "marches";
+pub const MARCH_SECS: i64 = 600;
+pub const MARCH_TICK_SECS: u64 = 5;
+pub const MARCH_PAGE_SIZE: i64 = 100;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MarchStatus {
+    Marching,
+    Recalled,
+    Resolved,
+}
+
+/// Guardians a helper sent to defend the target of a march.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Reinforcement {
+    pub pubkey: String,
+    pub guardians: i64,
+}
+
+/// Berserkers on their way to a hive. They fight the hive as it is at
+/// `arrives_at` (unix seconds), together with the reinforcements sent
+/// before then. The tokens of a march only count while it is marching.
+/// `berserkers` are the recruits, `veterans` the tiers as in `Swarm`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct March {
+    pub id: String,
+    pub swarm_pubkey: String,
+    pub hive_pubkey: String,
+    pub berserkers: i64,
+    #[serde(default)]
+    pub veterans: Vec<i64>,
+    pub launched_at: i64,
+    pub arrives_at: i64,
+    pub status: MarchStatus,
+    pub help_called: bool,
+    pub reinforcements: Vec<Reinforcement>,
+    pub won: Option<bool>,
+    pub loot: i64,
+    /// Berserkers that came home promoted from a won battle.
+    #[serde(default)]
+    pub survivors: i64,
+    /// Share of the hive eggs the march loots, lowered for targets below
+    /// the power band of the attacker.
+    #[serde(default = "full_loot")]
+    pub loot_percent: i64,
+    /// Attack rating points the attacker won, negative for a lost battle.
+    #[serde(default)]
+    pub rating_change: i64,
+    /// Eggs of the bounties on the hive paid to the attacker on top of the
+    /// loot.
+    #[serde(default)]
+    pub bounty: i64,
+    /// Berserkers hired from another swarm for this march.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mercenaries: Option<Hire>,
+}
+
+fn full_loot() -> i64 {
+    100
+}
+
+/// Body of `/march/recall`, signed by the attacker.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Recall {
+    pub pubkey: String,
+    pub march: String,
+}
+
+/// Body of `/march/help`, signed by the owner of the target hive.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct HelpCall {
+    pub pubkey: String,
+    pub march: String,
+}
+
+/// Body of `/march/reinforce`, signed by the helper.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Reinforce {
+    pub pubkey: String,
+    pub march: String,
+    pub guardians: i64,
+}
+
+impl KeyCloner for Recall {
+    fn clone_pubkey(&self) -> String {
+        self.pubkey.clone()
+    }
+}
+
+impl KeyCloner for HelpCall {
+    fn clone_pubkey(&self) -> String {
+        self.pubkey.clone()
+    }
+}
+
+impl KeyCloner for Reinforce {
+    fn clone_pubkey(&self) -> String {
+        self.pubkey.clone()
+    }
+}
+
+pub enum MarchError {
+    InvalidPubkey,
+    NotFound,
+    NotEnoughTokens,
+    /// The march arrived and waits for the scheduler.
+    Arrived,
+    DBError(MongoError),
+}
+
+impl From<SearchError> for MarchError {
+    fn from(e: SearchError) -> MarchError {
+        match e {
+            SearchError::NotFound => MarchError::NotEnoughTokens,
+            SearchError::InvalidPubkey => MarchError::InvalidPubkey,
+            SearchError::DBError(e) => MarchError::DBError(e),
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for MarchError {
+    fn from(e: mongodb::error::Error) -> MarchError {
+        MarchError::DBError(e)
+    }
+}
+
+impl TransactionError for MarchError {
+    fn db_error(&self) -> Option<&MongoError> {
+        match self {
+            MarchError::DBError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Ledger change of the tokens a player has on the road. The ledger tracks
+/// all marches of a player as one balance.
+fn marching_change(before: Swarm, after: Swarm) -> Change {
+    Change {
+        collection: MARCHES_COLL_NAME.to_string(),
+        before,
+        after,
+    }
+}
+
+/// The berserkers of `march`.
+fn army(march: &March) -> Swarm {
+    Swarm {
+        veterans: march.veterans.clone(),
+        ..Swarm::empty(march.swarm_pubkey.clone()).with(BERSERKERS, march.berserkers)
+    }
+}
+
+/// The berserkers of `march` and the eggs it holds to pay its mercenaries.
+fn marching(march: &March) -> Swarm {
+    army(march).with(
+        EGGS,
+        march.mercenaries.as_ref().map_or(0, |hire| hire.price),
+    )
+}
+
+/// The mercenaries of `hire`, owned by the swarm that listed them.
+fn hired(hire: &Hire) -> Swarm {
+    Swarm::empty(hire.pubkey.clone()).with(BERSERKERS, hire.berserkers)
+}
+
+/// Reinforcements of `pubkey` on the road.
+fn guarding(pubkey: &str, guardians: i64) -> Swarm {
+    Swarm::empty(pubkey.to_string()).with(GUARDIANS, guardians)
+}
+
+async fn find_march(
+    filter: mongodb::bson::Document,
+    db: &Database,
+    session: &mut ClientSession,
+) -> Result<March, MarchError> {
+    db.collection::<March>(MARCHES_COLL_NAME)
+        .find_one_with_session(filter, None, session)
+        .await?
+        .ok_or(MarchError::NotFound)
+}
+
+async fn write_march(
+    march: &March,
+    db: &Database,
+    session: &mut ClientSession,
+) -> Result<(), MongoError> {
+    db.collection::<March>(MARCHES_COLL_NAME)
+        .replace_one_with_session(doc! { "id": &march.id }, march, None, session)
+        .await?;
+    Ok(())
+}
+
+async fn write_swarm(
+    swarm: &Swarm,
+    db: &Database,
+    session: &mut ClientSession,
+) -> Result<(), MongoError> {
+    db.collection::<Swarm>(Swarm::get_collection())
+        .replace_one_with_session(doc! { "pubkey": &swarm.pubkey }, swarm, None, session)
+        .await?;
+    Ok(())
+}
+
+/// Guardians sent to `march`, summed per helper.
+fn reinforcements(march: &March) -> BTreeMap<String, i64> {
+    let mut guardians = BTreeMap::new();
+    for reinforcement in &march.reinforcements {
+        *guardians.entry(reinforcement.pubkey.clone()).or_default() += reinforcement.guardians;
+    }
+    guardians
+}
+
+/// Sends the reinforcements of `march` back to the swarms of their helpers.
+async fn return_reinforcements(
+    march: &March,
+    db: &Database,
+    session: &mut ClientSession,
+    changes: &mut Vec<Change>,
+) -> Result<(), MarchError> {
+    for (helper, guardians) in reinforcements(march) {
+        let mut swarm =
+            db_search_with_session::<Swarm>(helper.clone(), db.clone(), session).await?;
+        let before = swarm.clone();
+        swarm.change(GUARDIANS, guardians);
+        write_swarm(&swarm, db, session).await?;
+        changes.push(Change::new(&before, &swarm));
+    }
+    Ok(())
+}
+
+/// Sends the surviving mercenaries of `march` home with the eggs they were
+/// paid and notifies their owner.
+async fn pay_mercenaries(
+    march: &March,
+    db: &Database,
+    session: &mut ClientSession,
+    changes: &mut Vec<Change>,
+) -> Result<(), MarchError> {
+    let hire = match &march.mercenaries {
+        Some(hire) => hire,
+        None => return Ok(()),
+    };
+    let mut swarm =
+        db_search_with_session::<Swarm>(hire.pubkey.clone(), db.clone(), session).await?;
+    let before = swarm.clone();
+    swarm.change(BERSERKERS, hire.survivors);
+    swarm.change(EGGS, hire.paid);
+    write_swarm(&swarm, db, session).await?;
+    changes.push(Change::new(&before, &swarm));
+    changes.push(marching_change(
+        hired(hire),
+        Swarm::empty(hire.pubkey.clone()),
+    ));
+    let event = Event::MercenariesReturned {
+        march: march.id.clone(),
+        survivors: hire.survivors,
+        eggs: hire.paid,
+    };
+    notifications::notify_with_session(db, session, &hire.pubkey, event).await?;
+    Ok(())
+}
+
+/// Sends the berserkers of `request` on a march that arrives at the target
+/// hive after `march_secs`, together with the mercenaries it hires. The
+/// march holds their price until the battle. The owner of the hive is
+/// notified. Targets below the power band of the attacker are refused or
+/// looted less, depending on the matchmaking config. Swarms can not march
+/// on their own hive.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        world = %world.name,
+        swarm_pubkey = %request.swarm_pubkey,
+        hive_pubkey = %request.hive_pubkey,
+        berserkers = request.berserkers,
+    )
+)]
+pub async fn launch(request: Attack, world: &World) -> Result<March, AttackError> {
+    if request.berserkers <= 0 {
+        return Err(AttackError::NotEnoughTokens);
+    }
+    if request.swarm_pubkey == request.hive_pubkey {
+        return Err(AttackError::OwnHive);
+    }
+    let mut session = world.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    let db = world.db.clone();
+    let mut swarm =
+        db_search_with_session::<Swarm>(request.swarm_pubkey.clone(), db.clone(), &mut session)
+            .await?;
+    let hive =
+        match db_search_with_session::<Hive>(request.hive_pubkey.clone(), db.clone(), &mut session)
+            .await
+        {
+            Err(SearchError::NotFound) => return Err(AttackError::NotFound),
+            result => result?,
+        };
+    // hives without eggs have nothing to raid
+    if hive.get(EGGS) == 0 {
+        return Err(AttackError::NotFound);
+    }
+    let attacker_power =
+        matchmaking::power_with_session(&request.swarm_pubkey, world, &mut session).await?;
+    let target_power =
+        matchmaking::power_with_session(&request.hive_pubkey, world, &mut session).await?;
+    let matchmaking = &world.config.matchmaking;
+    let loot_percent = match matchmaking.below_band(attacker_power, target_power) {
+        false => full_loot(),
+        true if matchmaking.enforce_band => {
+            tracing::info!(attacker_power, target_power, "target below the power band");
+            return Err(AttackError::OutOfBand);
+        }
+        true => matchmaking.out_of_band_loot_percent,
+    };
+    let id = ObjectId::new().to_hex();
+    let hire = match &request.mercenaries {
+        Some(offer) => Some(
+            mercenaries::hire_with_session(offer, &request.swarm_pubkey, &id, &db, &mut session)
+                .await?
+                .ok_or(AttackError::NotFound)?,
+        ),
+        None => None,
+    };
+    let price = hire.as_ref().map_or(0, |hire| hire.price);
+    if swarm.get(EGGS) < price {
+        tracing::info!(
+            available = swarm.get(EGGS),
+            price,
+            "not enough eggs to hire"
+        );
+        return Err(AttackError::NotEnoughTokens);
+    }
+    let before = swarm.clone();
+    let army = match swarm.take_berserkers(request.berserkers) {
+        Some(army) => army,
+        None => {
+            tracing::info!(available = swarm.all_berserkers(), "not enough berserkers");
+            return Err(AttackError::NotEnoughTokens);
+        }
+    };
+    swarm.change(EGGS, -price);
+    let now = chrono::Utc::now().timestamp();
+    let march = March {
+        id,
+        swarm_pubkey: request.swarm_pubkey.clone(),
+        hive_pubkey: request.hive_pubkey.clone(),
+        berserkers: army.get(BERSERKERS),
+        veterans: army.veterans.clone(),
+        launched_at: now,
+        arrives_at: now + world.config.march_secs,
+        status: MarchStatus::Marching,
+        help_called: false,
+        reinforcements: vec![],
+        won: None,
+        loot: 0,
+        survivors: 0,
+        loot_percent,
+        rating_change: 0,
+        bounty: 0,
+        mercenaries: hire,
+    };
+    write_swarm(&swarm, &db, &mut session).await?;
+    db.collection::<March>(MARCHES_COLL_NAME)
+        .insert_one_with_session(&march, None, &mut session)
+        .await?;
+    let mut changes = vec![
+        Change::new(&before, &swarm),
+        marching_change(Swarm::empty(swarm.pubkey.clone()), marching(&march)),
+    ];
+    if let Some(hire) = &march.mercenaries {
+        changes.push(mercenaries::listed_change(&hire.pubkey, hire.berserkers, 0));
+        changes.push(marching_change(
+            Swarm::empty(hire.pubkey.clone()),
+            hired(hire),
+        ));
+    }
+    matchmaking::store_powers_with_session(&changes, world, &mut session).await?;
+    ledger::append(&db, &mut session, Operation::March, &swarm.pubkey, changes).await?;
+    notifications::notify_with_session(
+        &db,
+        &mut session,
+        &march.hive_pubkey,
+        Event::MarchLaunched {
+            march: march.id.clone(),
+            attacker: march.swarm_pubkey.clone(),
+            arrives_at: march.arrives_at,
+        },
+    )
+    .await?;
+    commit_with_retry(&mut session).await?;
+    tracing::info!(march = %march.id, arrives_at = march.arrives_at, "march launched");
+    Ok(march)
+}
+
+/// Brings the berserkers, mercenaries and reinforcements of a march that
+/// did not arrive yet back home. The mercenaries are not paid.
+#[tracing::instrument(
+    skip_all,
+    fields(world = %world.name, pubkey = %request.pubkey, march = %request.march)
+)]
+pub async fn recall(request: Recall, world: &World) -> Result<(), MarchError> {
+    if !pubkey_is_valid(&request.pubkey) {
+        return Err(MarchError::InvalidPubkey);
+    }
+    let mut session = world.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    let db = world.db.clone();
+    let filter = doc! {
+        "id": &request.march,
+        "swarm_pubkey": &request.pubkey,
+        "status": "marching",
+    };
+    let mut march = find_march(filter, &db, &mut session).await?;
+    if march.arrives_at <= chrono::Utc::now().timestamp() {
+        return Err(MarchError::Arrived);
+    }
+    let mut swarm =
+        db_search_with_session::<Swarm>(request.pubkey.clone(), db.clone(), &mut session).await?;
+    let before = swarm.clone();
+    swarm.add(&marching(&march));
+    write_swarm(&swarm, &db, &mut session).await?;
+    let mut changes = vec![
+        Change::new(&before, &swarm),
+        marching_change(marching(&march), Swarm::empty(swarm.pubkey.clone())),
+    ];
+    for (helper, guardians) in reinforcements(&march) {
+        changes.push(marching_change(
+            guarding(&helper, guardians),
+            Swarm::empty(helper.clone()),
+        ));
+    }
+    return_reinforcements(&march, &db, &mut session, &mut changes).await?;
+    if let Some(hire) = march.mercenaries.as_mut() {
+        hire.survivors = hire.berserkers;
+    }
+    pay_mercenaries(&march, &db, &mut session, &mut changes).await?;
+    march.status = MarchStatus::Recalled;
+    write_march(&march, &db, &mut session).await?;
+    matchmaking::store_powers_with_session(&changes, world, &mut session).await?;
+    ledger::append(&db, &mut session, Operation::Recall, &swarm.pubkey, changes).await?;
+    let event = Event::MarchRecalled {
+        march: march.id.clone(),
+    };
+    for pubkey in
+        std::iter::once(march.hive_pubkey.clone()).chain(reinforcements(&march).into_keys())
+    {
+        notifications::notify_with_session(&db, &mut session, &pubkey, event.clone()).await?;
+    }
+    commit_with_retry(&mut session).await?;
+    tracing::info!("march recalled");
+    Ok(())
+}
+
+/// Opens a march against the hive of the caller to reinforcements.
+#[tracing::instrument(
+    skip_all,
+    fields(world = %world.name, pubkey = %request.pubkey, march = %request.march)
+)]
+pub async fn call_for_help(request: HelpCall, world: &World) -> Result<(), MarchError> {
+    if !pubkey_is_valid(&request.pubkey) {
+        return Err(MarchError::InvalidPubkey);
+    }
+    let filter = doc! {
+        "id": &request.march,
+        "hive_pubkey": &request.pubkey,
+        "status": "marching",
+        "arrives_at": { "$gt": chrono::Utc::now().timestamp() },
+    };
+    let result = world
+        .db
+        .collection::<March>(MARCHES_COLL_NAME)
+        .update_one(filter, doc! { "$set": { "help_called": true } }, None)
+        .await?;
+    match result.matched_count {
+        0 => Err(MarchError::NotFound),
+        _ => {
+            tracing::info!("help called");
+            Ok(())
+        }
+    }
+}
+
+/// Sends guardians of the caller to defend the target of a march that
+/// called for help. They fall with the hive or come back after the battle.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        world = %world.name,
+        pubkey = %request.pubkey,
+        march = %request.march,
+        guardians = request.guardians,
+    )
+)]
+pub async fn reinforce(request: Reinforce, world: &World) -> Result<(), MarchError> {
+    if request.guardians <= 0 {
+        return Err(MarchError::NotEnoughTokens);
+    }
+    let mut session = world.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    let db = world.db.clone();
+    let mut swarm =
+        db_search_with_session::<Swarm>(request.pubkey.clone(), db.clone(), &mut session).await?;
+    let filter = doc! {
+        "id": &request.march,
+        "swarm_pubkey": { "$ne": &request.pubkey },
+        "status": "marching",
+        "help_called": true,
+        "arrives_at": { "$gt": chrono::Utc::now().timestamp() },
+    };
+    let mut march = find_march(filter, &db, &mut session).await?;
+    if swarm.get(GUARDIANS) < request.guardians {
+        tracing::info!(available = swarm.get(GUARDIANS), "not enough guardians");
+        return Err(MarchError::NotEnoughTokens);
+    }
+    let before = swarm.clone();
+    swarm.change(GUARDIANS, -request.guardians);
+    let sent = reinforcements(&march)
+        .get(&request.pubkey)
+        .copied()
+        .unwrap_or(0);
+    march.reinforcements.push(Reinforcement {
+        pubkey: request.pubkey.clone(),
+        guardians: request.guardians,
+    });
+    write_swarm(&swarm, &db, &mut session).await?;
+    write_march(&march, &db, &mut session).await?;
+    let changes = vec![
+        Change::new(&before, &swarm),
+        marching_change(
+            guarding(&swarm.pubkey, sent),
+            guarding(&swarm.pubkey, sent + request.guardians),
+        ),
+    ];
+    matchmaking::store_powers_with_session(&changes, world, &mut session).await?;
+    ledger::append(
+        &db,
+        &mut session,
+        Operation::Reinforce,
+        &swarm.pubkey,
+        changes,
+    )
+    .await?;
+    notifications::notify_with_session(
+        &db,
+        &mut session,
+        &march.hive_pubkey,
+        Event::Reinforced {
+            march: march.id.clone(),
+            helper: request.pubkey.clone(),
+            guardians: request.guardians,
+        },
+    )
+    .await?;
+    commit_with_retry(&mut session).await?;
+    tracing::info!("hive reinforced");
+    Ok(())
+}
+
+/// Fights an arrived march against the hive as it is now, behind its walls.
+/// A won battle takes the eggs of the hive that are not in its vault and
+/// kills its defenders and reinforcements, and the open bounties on the hive
+/// go to the attacker. The berserkers whose power was not needed survive
+/// and come home one tier up, surviving mercenaries go back to their owner
+/// with their loot share. A lost battle kills all berserkers and sends the
+/// reinforcements home. Mercenaries are paid their price either way.
+#[tracing::instrument(skip(world), fields(world = %world.name))]
+pub async fn resolve(id: &str, world: &World) -> Result<March, MarchError> {
+    let mut session = world.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    let db = world.db.clone();
+    let filter = doc! {
+        "id": id,
+        "status": "marching",
+        "arrives_at": { "$lte": chrono::Utc::now().timestamp() },
+    };
+    let mut march = find_march(filter, &db, &mut session).await?;
+    let mut swarm =
+        db_search_with_session::<Swarm>(march.swarm_pubkey.clone(), db.clone(), &mut session)
+            .await?;
+    production::settle_with_session(&march.hive_pubkey, world, &mut session).await?;
+    let mut hive =
+        db_search_with_session::<Hive>(march.hive_pubkey.clone(), db.clone(), &mut session).await?;
+    // marches below the power band leave the hatchery alone
+    let incubating = match world.config.incubation_raidable && march.loot_percent == full_loot() {
+        true => incubation::incubating_eggs(&march.hive_pubkey, &db, &mut session).await?,
+        false => 0,
+    };
+    let fortifications =
+        fortifications::load_with_session(&march.hive_pubkey, &db, &mut session).await?;
+    let reinforcing: i64 = reinforcements(&march).values().sum();
+    let swarm_before = swarm.clone();
+    let hive_before = hive.clone();
+    let power = |swarm: &Swarm| {
+        swarm.attack_power(&world.config.units, &world.config.veteran_attack_percent)
+    };
+    let mercenaries = march.mercenaries.as_ref().map_or(0, |hire| hire.berserkers);
+    let attack_power = power(&army(&march))
+        + march
+            .mercenaries
+            .as_ref()
+            .map_or(0, |hire| power(&hired(hire)));
+    let mut defenders = hive.clone();
+    defenders.change(GUARDIANS, reinforcing);
+    let defense_power =
+        units::defense_power(&world.config.units, &defenders.balances(), |spread| {
+            i64::from(rand::random::<u8>()) % (spread.max(0) + 1)
+        }) * fortifications.defense_percent(&world.config)
+            / 100;
+    let won = attack_power > defense_power;
+    let vaulted = hive.get(EGGS) * fortifications.vault_percent(&world.config) / 100;
+    let looted = (hive.get(EGGS) - vaulted) * march.loot_percent / 100;
+    let loot = if won { looted + incubating } else { 0 };
+    let mut bounty_changes = vec![];
+    if won {
+        let (paid, changes) = bounties::claim_with_session(
+            &march.hive_pubkey,
+            &march.swarm_pubkey,
+            &db,
+            &mut session,
+        )
+        .await?;
+        march.bounty = paid;
+        bounty_changes = changes;
+        if incubating > 0 {
+            incubation::raid_with_session(&march.hive_pubkey, &db, &mut session).await?;
+        }
+        let fighters = army(&march).all_berserkers() + mercenaries;
+        let survivors = fighters * (attack_power - defense_power) / attack_power;
+        let promoted = army(&march).promote_survivors(
+            survivors,
+            fighters,
+            world.config.veteran_attack_percent.len(),
+        );
+        let hired_survivors = survivors * mercenaries / fighters;
+        march.survivors = promoted.all_berserkers();
+        swarm.add(&promoted);
+        if let Some(hire) = march.mercenaries.as_mut() {
+            hire.survivors = hired_survivors;
+            hire.paid = loot * hire.loot_percent / 100;
+        }
+        let share = march.mercenaries.as_ref().map_or(0, |hire| hire.paid);
+        swarm.change(EGGS, loot - share + march.bounty);
+        hive.change(EGGS, -looted);
+        // the defenders died, only the eggs that were not looted are left
+        hive.balances.retain(|id, _| id == EGGS);
+    }
+    db.collection::<Hive>(Hive::get_collection())
+        .replace_one_with_session(
+            doc! { "pubkey": hive.clone_pubkey() },
+            &hive,
+            None,
+            &mut session,
+        )
+        .await?;
+    write_swarm(&swarm, &db, &mut session).await?;
+    let mut changes = vec![
+        Change::new(&swarm_before, &swarm),
+        Change::new(&hive_before, &hive),
+        marching_change(marching(&march), Swarm::empty(swarm.pubkey.clone())),
+    ];
+    if won && incubating > 0 {
+        changes.push(incubation::incubating_change(
+            &march.hive_pubkey,
+            incubating,
+            0,
+        ));
+    }
+    for (helper, guardians) in reinforcements(&march) {
+        changes.push(marching_change(
+            guarding(&helper, guardians),
+            Swarm::empty(helper.clone()),
+        ));
+    }
+    changes.extend(bounty_changes);
+    if !won {
+        return_reinforcements(&march, &db, &mut session, &mut changes).await?;
+    }
+    if let Some(hire) = march.mercenaries.as_mut() {
+        hire.paid += hire.price;
+    }
+    pay_mercenaries(&march, &db, &mut session, &mut changes).await?;
+    march.rating_change = ratings::rate_with_session(
+        &march.swarm_pubkey,
+        &march.hive_pubkey,
+        won,
+        world.config.rating_k_factor,
+        &db,
+        &mut session,
+    )
+    .await?;
+    march.status = MarchStatus::Resolved;
+    march.won = Some(won);
+    march.loot = loot;
+    write_march(&march, &db, &mut session).await?;
+    matchmaking::store_powers_with_session(&changes, world, &mut session).await?;
+    ledger::append(&db, &mut session, Operation::Attack, &swarm.pubkey, changes).await?;
+    let event = Event::MarchResolved {
+        march: march.id.clone(),
+        hive: march.hive_pubkey.clone(),
+        won,
+        loot,
+    };
+    let mut involved: Vec<String> = reinforcements(&march).into_keys().collect();
+    involved.extend([march.swarm_pubkey.clone(), march.hive_pubkey.clone()]);
+    involved.sort();
+    involved.dedup();
+    for pubkey in involved {
+        notifications::notify_with_session(&db, &mut session, &pubkey, event.clone()).await?;
+    }
+    commit_with_retry(&mut session).await?;
+    metrics::ATTACKS
+        .with_label_values(&[if won { "won" } else { "lost" }])
+        .inc();
+    tracing::info!(
+        attack_power,
+        defense_power,
+        won,
+        loot,
+        bounty = march.bounty,
+        "attack resolved"
+    );
+    Ok(march)
+}
+
+/// Resolves the marches that arrived and returns how many were fought.
+pub async fn resolve_arrived(world: &World) -> Result<usize, MongoError> {
+    let filter = doc! {
+        "status": "marching",
+        "arrives_at": { "$lte": chrono::Utc::now().timestamp() },
+    };
+    let options = FindOptions::builder()
+        .sort(doc! { "arrives_at": 1 })
+        .limit(MARCH_PAGE_SIZE)
+        .build();
+    let arrived: Vec<March> = world
+        .db
+        .collection::<March>(MARCHES_COLL_NAME)
+        .find(filter, options)
+        .await?
+        .try_collect()
+        .await?;
+    let mut resolved = 0;
+    for march in arrived {
+        match resolve(&march.id, world).await {
+            Ok(_) => resolved += 1,
+            // resolved by another server in the meantime
+            Err(MarchError::NotFound) => (),
+            Err(MarchError::DBError(e)) => return Err(e),
+            Err(_) => tracing::warn!(march = %march.id, "march can not be resolved"),
+        }
+    }
+    Ok(resolved)
+}
+
+/// Resolves arrived marches every `MARCH_TICK_SECS`.
+pub async fn march_task(world: World) {
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(MARCH_TICK_SECS));
+    loop {
+        interval.tick().await;
+        match resolve_arrived(&world).await {
+            Ok(0) => (),
+            Ok(resolved) => tracing::info!(world = %world.name, resolved, "marches resolved"),
+            Err(e) => tracing::warn!(world = %world.name, error = %e, "resolving marches failed"),
+        }
+    }
+}
+
+/// Marches as `viewer` (the pubkey of a session, if any) may see them: the
+/// guardians reinforcing a hive are listed for its owner, every other
+/// swarm only sees its own reinforcement.
+pub fn seen_by(marches: Vec<March>, viewer: Option<&str>) -> Vec<March> {
+    marches
+        .into_iter()
+        .map(|mut march| {
+            if viewer != Some(march.hive_pubkey.as_str()) {
+                march
+                    .reinforcements
+                    .retain(|r| viewer == Some(r.pubkey.as_str()));
+            }
+            march
+        })
+        .collect()
+}
+
+/// Marches on the road that were launched by or against `pubkey`, or that
+/// it reinforces or fights in as mercenaries, soonest arrival first.
+#[tracing::instrument(skip(db))]
+pub async fn marches(pubkey: String, db: Database) -> Result<Vec<March>, SearchError> {
+    if !pubkey_is_valid(&pubkey) {
+        return Err(SearchError::InvalidPubkey);
+    }
+    let filter = doc! {
+        "status": "marching",
+        "$or": [
+            { "swarm_pubkey": &pubkey },
+            { "hive_pubkey": &pubkey },
+            { "reinforcements.pubkey": &pubkey },
+            { "mercenaries.pubkey": &pubkey },
+        ],
+    };
+    let options = FindOptions::builder()
+        .sort(doc! { "arrives_at": 1 })
+        .limit(MARCH_PAGE_SIZE)
+        .build();
+    Ok(db
+        .collection::<March>(MARCHES_COLL_NAME)
+        .find(filter, options)
+        .await?
+        .try_collect()
+        .await?)
+}
+
+/// Marches on the road whose targets called for help.
+#[tracing::instrument(skip(db))]
+pub async fn help_calls(db: Database) -> Result<Vec<March>, SearchError> {
+    let filter = doc! {
+        "status": "marching",
+        "help_called": true,
+        "arrives_at": { "$gt": chrono::Utc::now().timestamp() },
+    };
+    let options = FindOptions::builder()
+        .sort(doc! { "arrives_at": 1 })
+        .limit(MARCH_PAGE_SIZE)
+        .build();
+    Ok(db
+        .collection::<March>(MARCHES_COLL_NAME)
+        .find(filter, options)
+        .await?
+        .try_collect()
+        .await?)
+}
+
+/// Adds the berserkers, mercenaries, reinforcements and mercenary prices
+/// each player has on the road to `state`.
+pub async fn load_marching(
+    db: &Database,
+    pubkey: Option<&str>,
+    state: &mut ledger::State,
+) -> Result<(), MongoError> {
+    let filter = pubkey.map(|pubkey| {
+        doc! {
+            "$or": [
+                { "swarm_pubkey": pubkey },
+                { "reinforcements.pubkey": pubkey },
+                { "mercenaries.pubkey": pubkey },
+            ]
+        }
+    });
+    let mut cursor = db
+        .collection::<March>(MARCHES_COLL_NAME)
+        .find(filter, None)
+        .await?;
+    let mut add = |units: Swarm| {
+        if pubkey.is_some_and(|pubkey| pubkey != units.pubkey) {
+            return;
+        }
+        state
+            .entry((MARCHES_COLL_NAME.to_string(), units.pubkey.clone()))
+            .or_insert_with(|| Swarm::empty(units.pubkey.clone()))
+            .add(&units);
+    };
+    while let Some(march) = cursor.try_next().await? {
+        let on_road = march.status == MarchStatus::Marching;
+        add(match on_road {
+            true => marching(&march),
+            false => Swarm::empty(march.swarm_pubkey.clone()),
+        });
+        if let Some(hire) = &march.mercenaries {
+            add(match on_road {
+                true => hired(hire),
+                false => Swarm::empty(hire.pubkey.clone()),
+            });
+        }
+        for (helper, guardians) in reinforcements(&march) {
+            add(guarding(&helper, on_road as i64 * guardians));
+        }
+    }
+    Ok(())
+}
+
+pub async fn create_march_indexes(db: &Database) {
+    let marches = db.collection::<March>(MARCHES_COLL_NAME);
+    let options = IndexOptions::builder().unique(true).build();
+    let model = IndexModel::builder()
+        .keys(doc! { "id": 1 })
+        .options(options)
+        .build();
+    marches
+        .create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+    for keys in [
+        doc! { "status": 1, "arrives_at": 1 },
+        doc! { "swarm_pubkey": 1 },
+        doc! { "hive_pubkey": 1 },
+        doc! { "reinforcements.pubkey": 1 },
+        doc! { "mercenaries.pubkey": 1 },
+    ] {
+        marches
+            .create_index(IndexModel::builder().keys(keys).build(), None)
+            .await
+            .expect("creating an index should succeed");
+    }
+}
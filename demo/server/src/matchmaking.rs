@@ -0,0 +1,280 @@
+use {
+    super::{
+        ledger::Change,
+        model::*,
+        ratings,
+        units::{self, Balances, EGGS},
+        world::{GameConfig, World},
+    },
+    futures::stream::TryStreamExt,
+    mongodb::{
+        bson::{doc, from_document},
+        error::Error as MongoError,
+        options::{IndexOptions, ReplaceOptions},
+        ClientSession, Database, IndexModel,
+    },
+    serde::{Deserialize, Serialize},
+    std::collections::BTreeSet,
+};
+
+pub const POWERS_COLL_NAME: &str = "powers";
+pub const BAND_PERCENT: i64 = 25;
+pub const OUT_OF_BAND_LOOT_PERCENT: i64 = 25;
+pub const TARGETS_PAGE_SIZE: usize = 10;
+pub const CONTENDERS_LIMIT: i64 = 100;
+
+/// Which targets an attacker is matched with.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct MatchmakingConfig {
+    /// Power of the weakest suggested target in percent of the power of the
+    /// attacker. The strongest suggested target is as much stronger.
+    pub band_percent: i64,
+    /// Whether attacks on targets below the band are refused.
+    pub enforce_band: bool,
+    /// Share of the loot taken from targets below the band when the band is
+    /// not enforced, in percent.
+    pub out_of_band_loot_percent: i64,
+}
+
+impl Default for MatchmakingConfig {
+    fn default() -> Self {
+        MatchmakingConfig {
+            band_percent: BAND_PERCENT,
+            enforce_band: false,
+            out_of_band_loot_percent: OUT_OF_BAND_LOOT_PERCENT,
+        }
+    }
+}
+
+impl MatchmakingConfig {
+    /// Whether a target with `target` power is too weak for an attacker with
+    /// `attacker` power.
+    pub fn below_band(&self, attacker: i64, target: i64) -> bool {
+        target * 100 < attacker * self.band_percent
+    }
+
+    /// Lowest and highest power of the targets suggested to an attacker
+    /// with `attacker` power. The lowest is the weakest not `below_band`, a
+    /// band of 0 has no highest.
+    pub fn band(&self, attacker: i64) -> (i64, Option<i64>) {
+        let lowest = (attacker * self.band_percent + 99).div_euclid(100);
+        let highest = (self.band_percent > 0).then(|| (attacker * 100) / self.band_percent);
+        (lowest, highest)
+    }
+}
+
+/// Stored power of a player, kept up to date by every transaction that
+/// changes the strength of the swarm or the hive so that targets can be
+/// found by an indexed range query.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Power {
+    pub pubkey: String,
+    pub power: i64,
+}
+
+/// Combined strength of a player: the attack power of the swarm and the
+/// average defense of the hive.
+pub fn power(swarm: &Swarm, hive: &Hive, config: &GameConfig) -> i64 {
+    swarm.attack_power(&config.units, &config.veteran_attack_percent)
+        + units::defense_power(&config.units, &hive.balances(), |spread| spread / 2)
+}
+
+/// Power of `pubkey` read inside the transaction of `session`. Missing
+/// documents count as empty.
+pub async fn power_with_session(
+    pubkey: &str,
+    world: &World,
+    session: &mut ClientSession,
+) -> Result<i64, MongoError> {
+    let swarm = world
+        .db
+        .collection::<Swarm>(SWARMS_COLL_NAME)
+        .find_one_with_session(doc! { "pubkey": pubkey }, None, session)
+        .await?
+        .unwrap_or_else(|| Swarm::empty(pubkey.to_string()));
+    let hive = world
+        .db
+        .collection::<Hive>(HIVE_COLL_NAME)
+        .find_one_with_session(doc! { "pubkey": pubkey }, None, session)
+        .await?
+        .unwrap_or_else(|| Hive::from_balances(pubkey.to_string(), &Balances::new()));
+    Ok(power(&swarm, &hive, &world.config))
+}
+
+async fn store_power_with_session(
+    pubkey: &str,
+    world: &World,
+    session: &mut ClientSession,
+) -> Result<(), MongoError> {
+    let power = Power {
+        pubkey: pubkey.to_string(),
+        power: power_with_session(pubkey, world, session).await?,
+    };
+    world
+        .db
+        .collection::<Power>(POWERS_COLL_NAME)
+        .replace_one_with_session(
+            doc! { "pubkey": pubkey },
+            &power,
+            ReplaceOptions::builder().upsert(true).build(),
+            session,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Whether `change` moves the attack power of a swarm or the defense of a
+/// hive. Tokens without unit stats, like eggs, leave the power as it is.
+fn changes_strength(change: &Change, config: &GameConfig) -> bool {
+    match change.collection.as_str() {
+        SWARMS_COLL_NAME => {
+            let attack =
+                |swarm: &Swarm| swarm.attack_power(&config.units, &config.veteran_attack_percent);
+            attack(&change.before) != attack(&change.after)
+        }
+        HIVE_COLL_NAME => {
+            let defense = |hive: &Swarm| {
+                units::defense_power(&config.units, &hive.balances, |spread| spread / 2)
+            };
+            defense(&change.before) != defense(&change.after)
+        }
+        _ => false,
+    }
+}
+
+/// Stores the power of every player whose swarm or hive changed strength
+/// in `changes`, read inside the transaction of `session` after the
+/// changes were written.
+pub async fn store_powers_with_session(
+    changes: &[Change],
+    world: &World,
+    session: &mut ClientSession,
+) -> Result<(), MongoError> {
+    let pubkeys: BTreeSet<&str> = changes
+        .iter()
+        .filter(|change| changes_strength(change, &world.config))
+        .map(|change| change.after.pubkey.as_str())
+        .collect();
+    for pubkey in pubkeys {
+        store_power_with_session(pubkey, world, session).await?;
+    }
+    Ok(())
+}
+
+/// Recomputes the stored power of every hive owner, for hives written
+/// before powers were stored and for changed unit stats. Optionally only
+/// for `pubkey`.
+pub async fn store_powers(world: &World, pubkey: Option<&str>) -> Result<(), MongoError> {
+    let filter = pubkey.map(|pubkey| doc! { "pubkey": pubkey });
+    let mut cursor = world
+        .db
+        .collection::<Hive>(HIVE_COLL_NAME)
+        .find(filter, None)
+        .await?;
+    let powers = world.db.collection::<Power>(POWERS_COLL_NAME);
+    while let Some(hive) = cursor.try_next().await? {
+        let swarm = world
+            .db
+            .collection::<Swarm>(SWARMS_COLL_NAME)
+            .find_one(doc! { "pubkey": &hive.pubkey }, None)
+            .await?
+            .unwrap_or_else(|| Swarm::empty(hive.pubkey.clone()));
+        let power = Power {
+            pubkey: hive.pubkey.clone(),
+            power: power(&swarm, &hive, &world.config),
+        };
+        powers
+            .replace_one(
+                doc! { "pubkey": &hive.pubkey },
+                &power,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Hives with eggs of the players other than `pubkey` whose stored power
+/// is within `band`, at most `CONTENDERS_LIMIT` of them and the weakest
+/// first when the band holds more.
+async fn contenders(
+    pubkey: &str,
+    band: (i64, Option<i64>),
+    db: &Database,
+) -> Result<Vec<Hive>, MongoError> {
+    let (lowest, highest) = band;
+    let mut range = doc! { "$gte": lowest };
+    if let Some(highest) = highest {
+        range.insert("$lte", highest);
+    }
+    let pipeline = vec![
+        doc! { "$match": { "power": range, "pubkey": { "$ne": pubkey } } },
+        doc! { "$sort": { "power": 1 } },
+        doc! {
+            "$lookup": {
+                "from": HIVE_COLL_NAME,
+                "localField": "pubkey",
+                "foreignField": "pubkey",
+                "as": "hive",
+            }
+        },
+        doc! { "$unwind": "$hive" },
+        doc! { "$match": { "hive.eggs": { "$gt": 0 } } },
+        doc! { "$limit": CONTENDERS_LIMIT },
+        doc! { "$replaceRoot": { "newRoot": "$hive" } },
+    ];
+    let mut cursor = db
+        .collection::<Power>(POWERS_COLL_NAME)
+        .aggregate(pipeline, None)
+        .await?;
+    let mut hives = vec![];
+    while let Some(hive) = cursor.try_next().await? {
+        hives.push(from_document::<Hive>(hive)?);
+    }
+    Ok(hives)
+}
+
+/// Hives whose owners are within the power band of `pubkey`, the defense
+/// ratings closest to the attack rating of `pubkey` first and then the
+/// hives with most eggs.
+#[tracing::instrument(skip(world), fields(world = %world.name))]
+pub async fn suggested_targets(pubkey: String, world: &World) -> Result<Vec<Hive>, SearchError> {
+    let db = world.db.clone();
+    let attacker = db_search_account(pubkey.clone(), db.clone()).await?;
+    let config = &world.config;
+    let attacker_power = power(&attacker.swarm, &attacker.hive, config);
+    let band = config.matchmaking.band(attacker_power);
+    let targets = contenders(&pubkey, band, &db).await?;
+    let pubkeys: Vec<String> = targets.iter().map(|hive| hive.pubkey.clone()).collect();
+    let ratings = ratings::db_search_ratings(&pubkeys, &db).await?;
+    let mut rated: Vec<(i64, Hive)> = ratings
+        .iter()
+        .map(|rating| (rating.defense - attacker.rating.attack).abs())
+        .zip(targets)
+        .collect();
+    rated.sort_by_key(|(distance, hive)| (*distance, std::cmp::Reverse(hive.get(EGGS))));
+    Ok(rated
+        .into_iter()
+        .map(|(_, hive)| hive)
+        .take(TARGETS_PAGE_SIZE)
+        .collect())
+}
+
+pub async fn create_power_indexes(db: &Database) {
+    let powers = db.collection::<Power>(POWERS_COLL_NAME);
+    let options = IndexOptions::builder().unique(true).build();
+    let model = IndexModel::builder()
+        .keys(doc! { "pubkey": 1 })
+        .options(options)
+        .build();
+    powers
+        .create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+    let model = IndexModel::builder().keys(doc! { "power": 1 }).build();
+    powers
+        .create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+}
@@ -0,0 +1,361 @@
+use {
+    super::{
+        ledger::{self, Change, Operation},
+        matchmaking,
+        model::*,
+        notifications::{self, Event},
+        units::BERSERKERS,
+        world::World,
+    },
+    futures::stream::TryStreamExt,
+    mongodb::{
+        bson::{doc, oid::ObjectId},
+        error::Error as MongoError,
+        options::{FindOptions, IndexOptions},
+        ClientSession, Database, IndexModel,
+    },
+    serde::{Deserialize, Serialize},
+};
+
+pub const MERCENARIES_COLL_NAME: &str = "mercenaries";
+pub const OFFERS_PAGE_SIZE: i64 = 100;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OfferStatus {
+    Listed,
+    Hired,
+    Withdrawn,
+}
+
+/// Berserkers `owner` lists for hire. An attacker hires all of them for a
+/// single march and pays `price` eggs plus `loot_percent` of the loot of a
+/// won battle. The berserkers only count while the offer is listed.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Offer {
+    pub id: String,
+    pub owner: String,
+    pub berserkers: i64,
+    pub price: i64,
+    pub loot_percent: i64,
+    pub listed_at: i64,
+    pub status: OfferStatus,
+    pub march: Option<String>,
+}
+
+/// Mercenaries that joined a march. The price is held by the march until
+/// the battle is fought.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Hire {
+    pub offer: String,
+    /// The owner of the mercenaries.
+    pub pubkey: String,
+    pub berserkers: i64,
+    pub price: i64,
+    pub loot_percent: i64,
+    /// Mercenaries that came home from a won battle.
+    pub survivors: i64,
+    /// Eggs the owner received, the price and the loot share.
+    pub paid: i64,
+}
+
+/// Body of `/mercenaries/list`, signed by the owner.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ListMercenaries {
+    pub pubkey: String,
+    pub berserkers: i64,
+    pub price: i64,
+    pub loot_percent: i64,
+}
+
+/// Body of `/mercenaries/withdraw`, signed by the owner.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct WithdrawMercenaries {
+    pub pubkey: String,
+    pub offer: String,
+}
+
+impl KeyCloner for ListMercenaries {
+    fn clone_pubkey(&self) -> String {
+        self.pubkey.clone()
+    }
+}
+
+impl KeyCloner for WithdrawMercenaries {
+    fn clone_pubkey(&self) -> String {
+        self.pubkey.clone()
+    }
+}
+
+pub enum MercenaryError {
+    InvalidPubkey,
+    /// A negative price or a loot share outside of 0 to 100 percent.
+    InvalidTerms,
+    NotFound,
+    NotEnoughTokens,
+    DBError(MongoError),
+}
+
+impl From<SearchError> for MercenaryError {
+    fn from(e: SearchError) -> MercenaryError {
+        match e {
+            SearchError::NotFound => MercenaryError::NotEnoughTokens,
+            SearchError::InvalidPubkey => MercenaryError::InvalidPubkey,
+            SearchError::DBError(e) => MercenaryError::DBError(e),
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for MercenaryError {
+    fn from(e: mongodb::error::Error) -> MercenaryError {
+        MercenaryError::DBError(e)
+    }
+}
+
+impl TransactionError for MercenaryError {
+    fn db_error(&self) -> Option<&MongoError> {
+        match self {
+            MercenaryError::DBError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Ledger change of the berserkers an owner has listed. The ledger tracks
+/// all offers of an owner as one balance.
+pub fn listed_change(owner: &str, before: i64, after: i64) -> Change {
+    let listed = |berserkers| Swarm::empty(owner.to_string()).with(BERSERKERS, berserkers);
+    Change {
+        collection: MERCENARIES_COLL_NAME.to_string(),
+        before: listed(before),
+        after: listed(after),
+    }
+}
+
+async fn write_swarm(
+    swarm: &Swarm,
+    db: &Database,
+    session: &mut ClientSession,
+) -> Result<(), MongoError> {
+    db.collection::<Swarm>(Swarm::get_collection())
+        .replace_one_with_session(doc! { "pubkey": &swarm.pubkey }, swarm, None, session)
+        .await?;
+    Ok(())
+}
+
+/// Moves recruits of the swarm of the owner into a new offer. Veterans are
+/// not for hire.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        world = %world.name,
+        pubkey = %request.pubkey,
+        berserkers = request.berserkers,
+        price = request.price,
+        loot_percent = request.loot_percent,
+    )
+)]
+pub async fn list(request: ListMercenaries, world: &World) -> Result<Offer, MercenaryError> {
+    if request.price < 0 || !(0..=100).contains(&request.loot_percent) {
+        return Err(MercenaryError::InvalidTerms);
+    }
+    if request.berserkers <= 0 {
+        return Err(MercenaryError::NotEnoughTokens);
+    }
+    let mut session = world.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    let db = world.db.clone();
+    let mut swarm =
+        db_search_with_session::<Swarm>(request.pubkey.clone(), db.clone(), &mut session).await?;
+    if swarm.get(BERSERKERS) < request.berserkers {
+        tracing::info!(available = swarm.get(BERSERKERS), "not enough berserkers");
+        return Err(MercenaryError::NotEnoughTokens);
+    }
+    let before = swarm.clone();
+    swarm.change(BERSERKERS, -request.berserkers);
+    let offer = Offer {
+        id: ObjectId::new().to_hex(),
+        owner: request.pubkey.clone(),
+        berserkers: request.berserkers,
+        price: request.price,
+        loot_percent: request.loot_percent,
+        listed_at: chrono::Utc::now().timestamp(),
+        status: OfferStatus::Listed,
+        march: None,
+    };
+    write_swarm(&swarm, &db, &mut session).await?;
+    db.collection::<Offer>(MERCENARIES_COLL_NAME)
+        .insert_one_with_session(&offer, None, &mut session)
+        .await?;
+    let changes = vec![
+        Change::new(&before, &swarm),
+        listed_change(&swarm.pubkey, 0, offer.berserkers),
+    ];
+    matchmaking::store_powers_with_session(&changes, world, &mut session).await?;
+    ledger::append(&db, &mut session, Operation::Enlist, &swarm.pubkey, changes).await?;
+    commit_with_retry(&mut session).await?;
+    tracing::info!(offer = %offer.id, "mercenaries listed");
+    Ok(offer)
+}
+
+/// Brings the berserkers of an offer that was not hired back to the swarm
+/// of the owner.
+#[tracing::instrument(
+    skip_all,
+    fields(world = %world.name, pubkey = %request.pubkey, offer = %request.offer)
+)]
+pub async fn withdraw(request: WithdrawMercenaries, world: &World) -> Result<(), MercenaryError> {
+    if !pubkey_is_valid(&request.pubkey) {
+        return Err(MercenaryError::InvalidPubkey);
+    }
+    let mut session = world.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    let db = world.db.clone();
+    let filter = doc! { "id": &request.offer, "owner": &request.pubkey, "status": "listed" };
+    let offer = db
+        .collection::<Offer>(MERCENARIES_COLL_NAME)
+        .find_one_with_session(filter.clone(), None, &mut session)
+        .await?
+        .ok_or(MercenaryError::NotFound)?;
+    let mut swarm =
+        db_search_with_session::<Swarm>(request.pubkey.clone(), db.clone(), &mut session).await?;
+    let before = swarm.clone();
+    swarm.change(BERSERKERS, offer.berserkers);
+    write_swarm(&swarm, &db, &mut session).await?;
+    db.collection::<Offer>(MERCENARIES_COLL_NAME)
+        .update_one_with_session(
+            filter,
+            doc! { "$set": { "status": "withdrawn" } },
+            None,
+            &mut session,
+        )
+        .await?;
+    let changes = vec![
+        Change::new(&before, &swarm),
+        listed_change(&swarm.pubkey, offer.berserkers, 0),
+    ];
+    matchmaking::store_powers_with_session(&changes, world, &mut session).await?;
+    ledger::append(
+        &db,
+        &mut session,
+        Operation::Discharge,
+        &swarm.pubkey,
+        changes,
+    )
+    .await?;
+    commit_with_retry(&mut session).await?;
+    tracing::info!("mercenaries withdrawn");
+    Ok(())
+}
+
+/// Hires the listed offer `id` for `march` of `attacker` inside the
+/// transaction of `session`. The owner is notified. Returns None if the
+/// offer is not listed or belongs to the attacker. The price is left to
+/// the caller.
+pub async fn hire_with_session(
+    id: &str,
+    attacker: &str,
+    march: &str,
+    db: &Database,
+    session: &mut ClientSession,
+) -> Result<Option<Hire>, MongoError> {
+    let filter = doc! { "id": id, "owner": { "$ne": attacker }, "status": "listed" };
+    let offer = match db
+        .collection::<Offer>(MERCENARIES_COLL_NAME)
+        .find_one_with_session(filter.clone(), None, session)
+        .await?
+    {
+        Some(offer) => offer,
+        None => return Ok(None),
+    };
+    db.collection::<Offer>(MERCENARIES_COLL_NAME)
+        .update_one_with_session(
+            filter,
+            doc! { "$set": { "status": "hired", "march": march } },
+            None,
+            session,
+        )
+        .await?;
+    let event = Event::MercenariesHired {
+        offer: offer.id.clone(),
+        attacker: attacker.to_string(),
+        march: march.to_string(),
+    };
+    notifications::notify_with_session(db, session, &offer.owner, event).await?;
+    Ok(Some(Hire {
+        offer: offer.id,
+        pubkey: offer.owner,
+        berserkers: offer.berserkers,
+        price: offer.price,
+        loot_percent: offer.loot_percent,
+        survivors: 0,
+        paid: 0,
+    }))
+}
+
+/// Price of the listed offer `id`, None if it is not listed.
+pub async fn listed_price(id: &str, db: &Database) -> Result<Option<i64>, MongoError> {
+    Ok(db
+        .collection::<Offer>(MERCENARIES_COLL_NAME)
+        .find_one(doc! { "id": id, "status": "listed" }, None)
+        .await?
+        .map(|offer| offer.price))
+}
+
+/// Listed offers, the smallest loot share and then the lowest price first.
+#[tracing::instrument(skip(db))]
+pub async fn listed_offers(db: Database) -> Result<Vec<Offer>, SearchError> {
+    let options = FindOptions::builder()
+        .sort(doc! { "loot_percent": 1, "price": 1 })
+        .limit(OFFERS_PAGE_SIZE)
+        .build();
+    Ok(db
+        .collection::<Offer>(MERCENARIES_COLL_NAME)
+        .find(doc! { "status": "listed" }, options)
+        .await?
+        .try_collect()
+        .await?)
+}
+
+/// Adds the berserkers each owner has listed to `state`.
+pub async fn load_listed(
+    db: &Database,
+    pubkey: Option<&str>,
+    state: &mut ledger::State,
+) -> Result<(), MongoError> {
+    let filter = pubkey.map(|pubkey| doc! { "owner": pubkey });
+    let mut cursor = db
+        .collection::<Offer>(MERCENARIES_COLL_NAME)
+        .find(filter, None)
+        .await?;
+    while let Some(offer) = cursor.try_next().await? {
+        let listed = offer.status == OfferStatus::Listed;
+        state
+            .entry((MERCENARIES_COLL_NAME.to_string(), offer.owner.clone()))
+            .or_insert_with(|| Swarm::empty(offer.owner.clone()))
+            .change(BERSERKERS, listed as i64 * offer.berserkers);
+    }
+    Ok(())
+}
+
+pub async fn create_mercenary_indexes(db: &Database) {
+    let offers = db.collection::<Offer>(MERCENARIES_COLL_NAME);
+    let options = IndexOptions::builder().unique(true).build();
+    let model = IndexModel::builder()
+        .keys(doc! { "id": 1 })
+        .options(options)
+        .build();
+    offers
+        .create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+    for keys in [
+        doc! { "status": 1, "loot_percent": 1, "price": 1 },
+        doc! { "owner": 1 },
+    ] {
+        offers
+            .create_index(IndexModel::builder().keys(keys).build(), None)
+            .await
+            .expect("creating an index should succeed");
+    }
+}
@@ -0,0 +1,181 @@
+use {
+    super::{
+        bounties::BOUNTIES_COLL_NAME,
+        economy::EconomyStats,
+        incubation::HATCH_JOBS_COLL_NAME,
+        locks::STAKE_LOCKS_COLL_NAME,
+        march::MARCHES_COLL_NAME,
+        mercenaries::MERCENARIES_COLL_NAME,
+        model::{TransactionError, HIVE_COLL_NAME, SACRED_HIVE_COLL_NAME, SWARMS_COLL_NAME},
+        units,
+        world::Worlds,
+    },
+    actix_web::dev::ServiceResponse,
+    mongodb::{bson::doc, Database},
+    once_cell::sync::Lazy,
+    prometheus::{
+        register_histogram_vec, register_int_counter, register_int_counter_vec,
+        register_int_gauge_vec, Encoder, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec,
+        TextEncoder,
+    },
+    serde::Serialize,
+    std::{collections::BTreeMap, time::Instant},
+};
+
+pub static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "http_requests_total",
+        "Number of HTTP requests per route.",
+        &["route", "method", "status"]
+    )
+    .unwrap()
+});
+
+pub static HTTP_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "http_request_duration_seconds",
+        "HTTP request latencies per route.",
+        &["route", "method"]
+    )
+    .unwrap()
+});
+
+pub static TRANSACTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "mongo_transactions_total",
+        "Mongo transactions per operation and outcome (committed, rejected or aborted).",
+        &["operation", "outcome"]
+    )
+    .unwrap()
+});
+
+pub static TRANSACTION_RETRIES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "mongo_transaction_retries_total",
+        "Mongo transactions and commits retried after a transient error or an unknown commit result."
+    )
+    .unwrap()
+});
+
+pub static ATTACKS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "attacks_total",
+        "Resolved attacks per outcome (won or lost).",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+pub static HATCHED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "hatched_total",
+        "Eggs sent to the hatchery and units hatched from them.",
+        &["token"]
+    )
+    .unwrap()
+});
+
+pub static AIRDROPS: Lazy<IntCounter> =
+    Lazy::new(|| register_int_counter!("airdrops_total", "Airdrops issued.").unwrap());
+
+pub static TOKEN_SUPPLY: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "token_supply",
+        "Current supply of each token per world and collection.",
+        &["world", "token", "collection"]
+    )
+    .unwrap()
+});
+
+pub fn observe_request<B>(res: &ServiceResponse<B>, start: Instant) {
+    let route = res
+        .request()
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = res.request().method().to_string();
+    HTTP_REQUESTS
+        .with_label_values(&[&route, &method, res.status().as_str()])
+        .inc();
+    HTTP_LATENCY
+        .with_label_values(&[&route, &method])
+        .observe(start.elapsed().as_secs_f64());
+}
+
+/// Counts a transactional model call. Mongo errors abort the transaction,
+/// requests the game rules refuse are counted as rejected.
+pub fn observe_transaction<T, E: TransactionError>(operation: &str, result: &Result<T, E>) {
+    let outcome = match result {
+        Ok(_) => "committed",
+        Err(e) if e.db_error().is_some() => "aborted",
+        Err(_) => "rejected",
+    };
+    TRANSACTIONS.with_label_values(&[operation, outcome]).inc();
+}
+
+/// Sets the token supply gauges of a world from its economy stats, which
+/// cover everything the ledger is checked against: one gauge per unit id
+/// held in each collection, with the veterans of all tiers as `veterans`.
+/// Tokens nobody holds are reported as 0. The stats are computed by the
+/// snapshot task of the world, so scrapes never read the balances.
+pub fn set_token_supply(world: &str, stats: &EconomyStats) {
+    let supplies = [
+        (SWARMS_COLL_NAME, &stats.swarms),
+        (HIVE_COLL_NAME, &stats.hives),
+        (SACRED_HIVE_COLL_NAME, &stats.sacred_hives),
+        (HATCH_JOBS_COLL_NAME, &stats.incubating),
+        (MARCHES_COLL_NAME, &stats.marching),
+        (BOUNTIES_COLL_NAME, &stats.bounties),
+        (MERCENARIES_COLL_NAME, &stats.mercenaries),
+        (STAKE_LOCKS_COLL_NAME, &stats.locked),
+    ];
+    for (collection, supply) in supplies {
+        let mut totals = supply.balances.clone();
+        totals.insert("veterans".to_string(), supply.veterans);
+        for token in units::TOKENS.iter().chain(&["veterans"]) {
+            totals.entry(token.to_string()).or_insert(0);
+        }
+        for (token, total) in totals {
+            TOKEN_SUPPLY
+                .with_label_values(&[world, &token, collection])
+                .set(total);
+        }
+    }
+}
+
+/// Renders all registered metrics in the Prometheus text format.
+pub fn render() -> String {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("encoding metrics should succeed");
+    String::from_utf8(buffer).expect("metrics are valid utf8")
+}
+
+#[derive(Serialize)]
+pub struct Health {
+    pub mongo: bool,
+    pub transactions: bool,
+}
+
+/// Pings Mongo and asks the `hello` command whether the deployment is a
+/// replica set (or a sharded cluster), which is required for transactions.
+pub async fn check_health(db: &Database) -> Health {
+    let mongo = db.run_command(doc! { "ping": 1 }, None).await.is_ok();
+    let transactions = match db.run_command(doc! { "hello": 1 }, None).await {
+        Ok(hello) => hello.contains_key("setName") || hello.get_str("msg") == Ok("isdbgrid"),
+        Err(_) => false,
+    };
+    Health {
+        mongo,
+        transactions,
+    }
+}
+
+/// Health of the database of every world, keyed by world name.
+pub async fn check_worlds(worlds: &Worlds) -> BTreeMap<String, Health> {
+    let mut health = BTreeMap::new();
+    for world in worlds.iter() {
+        health.insert(world.name.clone(), check_health(&world.db).await);
+    }
+    health
+}
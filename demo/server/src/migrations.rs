@@ -0,0 +1,142 @@
+use {
+    super::{ledger, model, production},
+    futures::future::BoxFuture,
+    mongodb::{
+        bson::doc, error::Error as MongoError, options::FindOneOptions, options::IndexOptions,
+        Database, IndexModel,
+    },
+    serde::{Deserialize, Serialize},
+    std::fmt,
+};
+
+pub const SCHEMA_VERSION_COLL_NAME: &str = "schema_version";
+
+/// A step that brings the documents of version `version - 1` to `version`.
+/// Steps must be idempotent, two servers starting at the same time may both
+/// run a step before one of them records it.
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub run: for<'a> fn(&'a Database) -> BoxFuture<'a, Result<(), MongoError>>,
+}
+
+/// All migrations, ordered by version. Append new steps at the end and never
+/// change a step that was released.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "baseline: swarms, hives, sacred hives, ledger and transparency log",
+        run: |_| Box::pin(async { Ok(()) }),
+    },
+    Migration {
+        version: 2,
+        description: "start the egg production clocks of existing hives",
+        run: |db| Box::pin(production::start_clocks(db, chrono::Utc::now().timestamp())),
+    },
+    Migration {
+        version: 3,
+        description: "write genesis ledger entries for balances without ledger history",
+        run: |db| Box::pin(ledger::backfill_genesis(db, None)),
+    },
+    Migration {
+        version: 4,
+        description: "move the units of swarms and hives out of their units sub-document",
+        run: |db| Box::pin(model::flatten_units(db)),
+    },
+];
+
+/// Version of the documents this build reads and writes.
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+/// Record of an applied migration.
+#[derive(Deserialize, Serialize)]
+pub struct SchemaVersion {
+    pub version: i64,
+    pub description: String,
+    pub applied_at: i64,
+}
+
+pub enum MigrationError {
+    /// The database was migrated by a newer build.
+    DatabaseAhead {
+        database: i64,
+        supported: i64,
+    },
+    DBError(MongoError),
+}
+
+impl From<mongodb::error::Error> for MigrationError {
+    fn from(e: mongodb::error::Error) -> MigrationError {
+        MigrationError::DBError(e)
+    }
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MigrationError::DatabaseAhead {
+                database,
+                supported,
+            } => write!(
+                f,
+                "database schema version {} is newer than the supported version {}",
+                database, supported
+            ),
+            MigrationError::DBError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Latest version recorded in the database, 0 for a database that was never
+/// migrated.
+pub async fn current_version(db: &Database) -> Result<i64, MongoError> {
+    let options = FindOneOptions::builder()
+        .sort(doc! { "version": -1 })
+        .build();
+    Ok(db
+        .collection::<SchemaVersion>(SCHEMA_VERSION_COLL_NAME)
+        .find_one(None, options)
+        .await?
+        .map_or(0, |record| record.version))
+}
+
+/// Applies the pending migrations in order and returns the new version.
+/// Refuses to touch a database that is ahead of this build.
+pub async fn migrate(db: &Database) -> Result<i64, MigrationError> {
+    let collection = db.collection::<SchemaVersion>(SCHEMA_VERSION_COLL_NAME);
+    let options = IndexOptions::builder().unique(true).build();
+    let model = IndexModel::builder()
+        .keys(doc! { "version": 1 })
+        .options(options)
+        .build();
+    collection.create_index(model, None).await?;
+
+    let current = current_version(db).await?;
+    if current > latest_version() {
+        return Err(MigrationError::DatabaseAhead {
+            database: current,
+            supported: latest_version(),
+        });
+    }
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        tracing::info!(
+            version = migration.version,
+            description = migration.description,
+            "applying migration"
+        );
+        (migration.run)(db).await?;
+        collection
+            .insert_one(
+                SchemaVersion {
+                    version: migration.version,
+                    description: migration.description.to_string(),
+                    applied_at: chrono::Utc::now().timestamp(),
+                },
+                None,
+            )
+            .await?;
+    }
+    Ok(latest_version())
+}
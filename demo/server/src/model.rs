@@ -1,52 +1,280 @@
 use {
+    super::{
+        ledger::{self, Change, Operation},
+        locks, matchmaking, metrics, production,
+        ratings::{self, Rating},
+        units::{self, Balances, UnitConfig, BERSERKERS, EGGS, GUARDIANS, QUEENS, SACRED_QUEENS},
+        world::World,
+    },
     ed25519_dalek::*,
     futures::stream::TryStreamExt,
     mongodb::{
-        bson::doc, error::Error as MongoError, options::FindOptions, options::IndexOptions, Client,
+        bson::{doc, Document},
+        error::{
+            Error as MongoError, TRANSIENT_TRANSACTION_ERROR, UNKNOWN_TRANSACTION_COMMIT_RESULT,
+        },
+        options::FindOptions,
+        options::IndexOptions,
         ClientSession, Collection, Database, IndexModel,
     },
     rand::rngs::OsRng,
     serde::{de::DeserializeOwned, Deserialize, Serialize},
-    std::sync::Arc,
+    std::{future::Future, time::Duration},
 };
 
 pub const SWARMS_COLL_NAME: &str = "swarms";
 pub const SACRED_HIVE_COLL_NAME: &str = "sacredHives";
 pub const HIVE_COLL_NAME: &str = "hives";
+pub const AIRDROP_SACRED_QUEENS: i64 = 10;
+pub const EGGS_PER_SACRED_QUEEN: i64 = 100;
+pub const VETERAN_ATTACK_PERCENT: [i64; 3] = [150, 200, 300];
+pub const MAX_TRANSACTION_ATTEMPTS: u32 = 5;
+pub const TRANSACTION_BACKOFF_MILLIS: u64 = 20;
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct Swarm {
     pub pubkey: String,
-    pub sacred_queens: i64,
-    pub queens: i64,
-    pub guardians: i64,
-    pub berserkers: i64,
-    pub eggs: i64,
+    /// Tokens and units of the world registry keyed by id.
+    #[serde(flatten, with = "units::flat")]
+    pub balances: Balances,
+    /// Berserkers that survived attacks, `veterans[0]` is the first veteran
+    /// tier. The `berserkers` balance holds the recruits. Never ends with an
+    /// empty tier.
+    #[serde(default)]
+    pub veterans: Vec<i64>,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct SacredHive {
     pub pubkey: String,
     pub sacred_queens: i64,
     pub eggs: i64,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct Hive {
+    pub pubkey: String,
+    /// Eggs and staked units of the world registry keyed by id.
+    #[serde(flatten, with = "units::flat_hive")]
+    pub balances: Balances,
+}
+
+/// Body of `/hive/stake` and `/hive/unstake` in the shape clients sign it.
+/// Signatures are checked over its serialization, so it keeps the tokens in
+/// fields of their own whatever shape the stored `Hive` has.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct HiveRequest {
     pub pubkey: String,
     pub guardians: i64,
     pub queens: i64,
     pub eggs: i64,
+    /// Units of the world registry that have no field of their own.
+    #[serde(default, skip_serializing_if = "Balances::is_empty")]
+    pub units: Balances,
+}
+
+impl HiveRequest {
+    pub fn to_hive(&self) -> Hive {
+        let mut hive = Hive {
+            pubkey: self.pubkey.clone(),
+            balances: self.units.clone(),
+        };
+        hive.change(GUARDIANS, self.guardians);
+        hive.change(QUEENS, self.queens);
+        hive.change(EGGS, self.eggs);
+        hive
+    }
+}
+
+impl From<&Hive> for HiveRequest {
+    fn from(hive: &Hive) -> Self {
+        let mut units = hive.balances.clone();
+        units.retain(|id, _| !units::HIVE_TOKENS.contains(&id.as_str()));
+        HiveRequest {
+            pubkey: hive.pubkey.clone(),
+            guardians: hive.get(GUARDIANS),
+            queens: hive.get(QUEENS),
+            eggs: hive.get(EGGS),
+            units,
+        }
+    }
+}
+
+/// Documents that keep their balances keyed by unit id.
+pub trait Holdings: Sized {
+    fn holdings(&self) -> &Balances;
+    fn holdings_mut(&mut self) -> &mut Balances;
+    /// Amount of `id`.
+    fn get(&self, id: &str) -> i64 {
+        units::get(self.holdings(), id)
+    }
+    /// Adds `amount` to the balance of `id`.
+    fn change(&mut self, id: &str, amount: i64) {
+        units::change(self.holdings_mut(), id, amount);
+    }
+    /// Replaces the balance of `id` with `amount`.
+    fn set(&mut self, id: &str, amount: i64) {
+        let current = self.get(id);
+        self.change(id, amount - current);
+    }
+    /// Builder form of `set`.
+    fn with(mut self, id: &str, amount: i64) -> Self {
+        self.set(id, amount);
+        self
+    }
+}
+
+impl Holdings for Swarm {
+    fn holdings(&self) -> &Balances {
+        &self.balances
+    }
+    fn holdings_mut(&mut self) -> &mut Balances {
+        &mut self.balances
+    }
+}
+
+impl Holdings for Hive {
+    fn holdings(&self) -> &Balances {
+        &self.balances
+    }
+    fn holdings_mut(&mut self) -> &mut Balances {
+        &mut self.balances
+    }
+}
+
+impl Hive {
+    pub fn empty(pubkey: String) -> Self {
+        Hive {
+            pubkey,
+            balances: Balances::new(),
+        }
+    }
+}
+
+impl Swarm {
+    pub fn empty(pubkey: String) -> Self {
+        Swarm {
+            pubkey,
+            veterans: vec![],
+            balances: Balances::new(),
+        }
+    }
+
+    /// Recruits and veterans of all tiers.
+    pub fn all_berserkers(&self) -> i64 {
+        self.get(BERSERKERS) + self.veterans.iter().sum::<i64>()
+    }
+
+    /// Moves `count` berserkers into a new swarm, veterans of the highest
+    /// tier first. Returns None if the swarm has fewer berserkers.
+    pub fn take_berserkers(&mut self, count: i64) -> Option<Swarm> {
+        if count > self.all_berserkers() {
+            return None;
+        }
+        let mut taken = Swarm::empty(self.pubkey.clone());
+        let mut left = count;
+        taken.veterans = vec![0; self.veterans.len()];
+        for (tier, veterans) in self.veterans.iter_mut().enumerate().rev() {
+            let moved = left.min(*veterans);
+            *veterans -= moved;
+            taken.veterans[tier] = moved;
+            left -= moved;
+        }
+        self.change(BERSERKERS, -left);
+        taken.set(BERSERKERS, left);
+        trim_veterans(&mut self.veterans);
+        trim_veterans(&mut taken.veterans);
+        Some(taken)
+    }
+
+    /// Attack power of the units of the registry, veteran berserkers hit
+    /// with the percent of their tier.
+    pub fn attack_power(&self, units: &[UnitConfig], veteran_attack_percent: &[i64]) -> i64 {
+        let veterans: i64 = self
+            .veterans
+            .iter()
+            .enumerate()
+            .map(|(tier, count)| count * tier_percent(veteran_attack_percent, tier))
+            .sum();
+        let berserker_attack = units::find(units, BERSERKERS).map_or(0, |unit| unit.attack);
+        let others: i64 = units
+            .iter()
+            .filter(|unit| unit.id != BERSERKERS)
+            .map(|unit| self.get(&unit.id) * unit.attack)
+            .sum();
+        (self.get(BERSERKERS) * 100 + veterans) * berserker_attack / 100 + others
+    }
+
+    /// The berserkers that survive a won battle, `survivors` out of
+    /// `fighters` of each tier, promoted one tier up to `tiers`.
+    pub fn promote_survivors(&self, survivors: i64, fighters: i64, tiers: usize) -> Swarm {
+        let survive = |count: i64| match fighters {
+            0 => 0,
+            fighters => count * survivors / fighters,
+        };
+        let mut promoted = Swarm::empty(self.pubkey.clone());
+        promoted.veterans = vec![0; tiers];
+        let ranks = std::iter::once(self.get(BERSERKERS)).chain(self.veterans.iter().copied());
+        for (tier, count) in ranks.enumerate() {
+            if tiers == 0 {
+                promoted.change(BERSERKERS, survive(count));
+            } else {
+                promoted.veterans[tier.min(tiers - 1)] += survive(count);
+            }
+        }
+        trim_veterans(&mut promoted.veterans);
+        promoted
+    }
+}
+
+/// Percent of the veteran `tier`, tiers above the configured ones hit as
+/// hard as the highest.
+fn tier_percent(veteran_attack_percent: &[i64], tier: usize) -> i64 {
+    veteran_attack_percent
+        .get(tier)
+        .or(veteran_attack_percent.last())
+        .copied()
+        .unwrap_or(100)
+}
+
+fn trim_veterans(veterans: &mut Vec<i64>) {
+    while veterans.last() == Some(&0) {
+        veterans.pop();
+    }
 }
 
+/// Balances key of the veteran `tier`, `veterans[0]` is `veteran_1`.
+fn veteran_id(tier: usize) -> String {
+    format!("veteran_{}", tier + 1)
+}
+
+fn veteran_tier(id: &str) -> Option<usize> {
+    id.strip_prefix("veteran_")?
+        .parse::<usize>()
+        .ok()?
+        .checked_sub(1)
+}
+
+/// All balances of a single player.
 #[derive(Deserialize, Serialize)]
+pub struct Account {
+    pub swarm: Swarm,
+    pub sacred_hive: SacredHive,
+    pub hive: Hive,
+    pub rating: Rating,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Attack {
     pub swarm_pubkey: String,
     pub hive_pubkey: String,
     pub berserkers: i64,
+    /// Listed mercenary offer hired for the march.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mercenaries: Option<String>,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct HatchRequest {
     pub pubkey: String,
     pub eggs: i64,
@@ -61,6 +289,11 @@ pub enum SearchError {
 pub enum StakeError {
     InvalidPubkey,
     NotEnoughTokens,
+    NotStakeable,
+    /// A lock period the world does not offer.
+    InvalidLock,
+    /// The sacred queens are locked until the lock runs out.
+    Locked,
     DBError(MongoError),
 }
 
@@ -80,6 +313,39 @@ impl From<mongodb::error::Error> for StakeError {
     }
 }
 
+/// Errors of transactional model calls. `db_error` is the Mongo error that
+/// aborted the transaction, None for requests the game rules rejected.
+pub trait TransactionError {
+    fn db_error(&self) -> Option<&MongoError>;
+}
+
+impl TransactionError for SearchError {
+    fn db_error(&self) -> Option<&MongoError> {
+        match self {
+            SearchError::DBError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl TransactionError for StakeError {
+    fn db_error(&self) -> Option<&MongoError> {
+        match self {
+            StakeError::DBError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl TransactionError for AttackError {
+    fn db_error(&self) -> Option<&MongoError> {
+        match self {
+            AttackError::DBError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 impl From<mongodb::error::Error> for SearchError {
     fn from(e: mongodb::error::Error) -> SearchError {
         SearchError::DBError(e)
@@ -102,6 +368,10 @@ pub enum AttackError {
     InvalidPubkey,
     NotFound,
     NotEnoughTokens,
+    /// The target is below the power band of the attacker.
+    OutOfBand,
+    /// The attacker owns the target hive.
+    OwnHive,
     DBError(MongoError),
 }
 
@@ -143,6 +413,12 @@ impl KeyCloner for Hive {
     }
 }
 
+impl KeyCloner for HiveRequest {
+    fn clone_pubkey(&self) -> String {
+        self.pubkey.clone()
+    }
+}
+
 impl KeyCloner for Attack {
     fn clone_pubkey(&self) -> String {
         self.swarm_pubkey.clone()
@@ -155,40 +431,57 @@ impl KeyCloner for HatchRequest {
     }
 }
 
-pub trait Helpers {
+/// Generic balance arithmetic. Documents only convert to and from a map of
+/// unit ids, so new units need no code here.
+pub trait Helpers: KeyCloner + Sized {
     fn get_collection() -> &'static str;
-    fn is_negative(&self) -> bool;
-    fn as_swarm(&self) -> Swarm;
-    fn add(&mut self, addend: &Self);
-    fn negative(&self) -> Self;
-}
-
-impl Helpers for Swarm {
+    fn balances(&self) -> Balances;
+    /// Keeps the balances the document can hold and drops the others.
+    fn from_balances(pubkey: String, balances: &Balances) -> Self;
     fn is_negative(&self) -> bool {
-        self.eggs.is_negative()
-            || self.sacred_queens.is_negative()
-            || self.queens.is_negative()
-            || self.guardians.is_negative()
-            || self.berserkers.is_negative()
+        units::is_negative(&self.balances())
     }
     fn as_swarm(&self) -> Swarm {
-        self.clone()
+        Swarm::from_balances(self.clone_pubkey(), &self.balances())
+    }
+    fn from_swarm(swarm: &Swarm) -> Self {
+        Self::from_balances(swarm.pubkey.clone(), &swarm.balances())
     }
     fn add(&mut self, addend: &Self) {
-        self.sacred_queens += addend.sacred_queens;
-        self.queens += addend.queens;
-        self.guardians += addend.guardians;
-        self.berserkers += addend.berserkers;
-        self.eggs += addend.eggs;
+        let mut balances = self.balances();
+        units::add(&mut balances, &addend.balances());
+        *self = Self::from_balances(self.clone_pubkey(), &balances);
     }
     fn negative(&self) -> Self {
+        Self::from_balances(self.clone_pubkey(), &units::negative(&self.balances()))
+    }
+}
+
+impl Helpers for Swarm {
+    fn balances(&self) -> Balances {
+        let mut balances = self.balances.clone();
+        for (tier, count) in self.veterans.iter().enumerate() {
+            units::change(&mut balances, &veteran_id(tier), *count);
+        }
+        balances
+    }
+    fn from_balances(pubkey: String, balances: &Balances) -> Self {
+        let mut veterans = vec![];
+        for (id, count) in balances {
+            if let Some(tier) = veteran_tier(id) {
+                if veterans.len() <= tier {
+                    veterans.resize(tier + 1, 0);
+                }
+                veterans[tier] += count;
+            }
+        }
+        trim_veterans(&mut veterans);
+        let mut balances = balances.clone();
+        balances.retain(|id, amount| veteran_tier(id).is_none() && *amount != 0);
         Swarm {
-            pubkey: self.pubkey.clone(),
-            sacred_queens: -self.sacred_queens,
-            eggs: -self.eggs,
-            queens: -self.queens,
-            guardians: -self.guardians,
-            berserkers: -self.berserkers,
+            pubkey,
+            veterans,
+            balances,
         }
     }
     fn get_collection() -> &'static str {
@@ -196,28 +489,17 @@ impl Helpers for Swarm {
     }
 }
 impl Helpers for SacredHive {
-    fn is_negative(&self) -> bool {
-        self.eggs.is_negative() || self.sacred_queens.is_negative()
-    }
-    fn as_swarm(&self) -> Swarm {
-        Swarm {
-            pubkey: self.pubkey.clone(),
-            sacred_queens: self.sacred_queens,
-            eggs: self.eggs,
-            queens: 0,
-            berserkers: 0,
-            guardians: 0,
-        }
+    fn balances(&self) -> Balances {
+        let mut balances = Balances::new();
+        units::change(&mut balances, SACRED_QUEENS, self.sacred_queens);
+        units::change(&mut balances, EGGS, self.eggs);
+        balances
     }
-    fn add(&mut self, addend: &Self) {
-        self.sacred_queens += addend.sacred_queens;
-        self.eggs += addend.eggs;
-    }
-    fn negative(&self) -> Self {
+    fn from_balances(pubkey: String, balances: &Balances) -> Self {
         SacredHive {
-            pubkey: self.pubkey.clone(),
-            sacred_queens: -self.sacred_queens,
-            eggs: -self.eggs,
+            pubkey,
+            sacred_queens: units::get(balances, SACRED_QUEENS),
+            eggs: units::get(balances, EGGS),
         }
     }
     fn get_collection() -> &'static str {
@@ -225,31 +507,18 @@ impl Helpers for SacredHive {
     }
 }
 impl Helpers for Hive {
-    fn is_negative(&self) -> bool {
-        self.eggs.is_negative() || self.queens.is_negative() || self.guardians.is_negative()
-    }
-    fn as_swarm(&self) -> Swarm {
-        Swarm {
-            pubkey: self.pubkey.clone(),
-            queens: self.queens,
-            guardians: self.guardians,
-            eggs: self.eggs,
-            sacred_queens: 0,
-            berserkers: 0,
-        }
-    }
-    fn add(&mut self, addend: &Self) {
-        self.queens += addend.queens;
-        self.guardians += addend.guardians;
-        self.eggs += addend.eggs;
-    }
-    fn negative(&self) -> Self {
-        Hive {
-            pubkey: self.pubkey.clone(),
-            eggs: -self.eggs,
-            queens: -self.queens,
-            guardians: -self.guardians,
-        }
+    fn balances(&self) -> Balances {
+        self.balances.clone()
+    }
+    fn from_balances(pubkey: String, balances: &Balances) -> Self {
+        let mut balances = balances.clone();
+        // hives stake neither berserkers nor sacred queens
+        balances.retain(|id, amount| {
+            ![BERSERKERS, SACRED_QUEENS].contains(&id.as_str())
+                && veteran_tier(id).is_none()
+                && *amount != 0
+        });
+        Hive { pubkey, balances }
     }
     fn get_collection() -> &'static str {
         HIVE_COLL_NAME
@@ -257,11 +526,15 @@ impl Helpers for Hive {
 }
 
 pub trait Contract:
-    Helpers + KeyCloner + DeserializeOwned + Unpin + Send + Sync + Serialize
+    Helpers + KeyCloner + DeserializeOwned + Unpin + Send + Sync + Serialize + Clone
+{
+}
+impl<T: Helpers + KeyCloner + DeserializeOwned + Unpin + Send + Sync + Serialize + Clone> Contract
+    for T
 {
 }
-impl<T: Helpers + KeyCloner + DeserializeOwned + Unpin + Send + Sync + Serialize> Contract for T {}
 
+#[tracing::instrument(skip_all)]
 pub async fn db_search_hive_top(db: Database) -> Result<Vec<Hive>, SearchError> {
     let coll = db.collection::<Hive>("hives");
     let find_options = FindOptions::builder()
@@ -276,6 +549,7 @@ pub async fn db_search_hive_top(db: Database) -> Result<Vec<Hive>, SearchError>
     Ok(hives)
 }
 
+#[tracing::instrument(skip(db))]
 pub async fn db_search_hive_neigh(neigh: i64, db: Database) -> Result<Vec<Hive>, SearchError> {
     let coll = db.collection::<Hive>("hives");
 
@@ -300,10 +574,11 @@ pub async fn db_search_hive_neigh(neigh: i64, db: Database) -> Result<Vec<Hive>,
         hives.push(hive);
     }
 
-    hives.sort_by(|a, b| b.eggs.cmp(&a.eggs));
+    hives.sort_by_key(|h| std::cmp::Reverse(h.get(EGGS)));
     Ok(hives)
 }
 
+#[tracing::instrument(skip(db), fields(collection = T::get_collection()))]
 pub async fn db_search<T: Contract>(pubkey: String, db: Database) -> Result<T, SearchError> {
     if !pubkey_is_valid(&pubkey) {
         return Err(SearchError::InvalidPubkey);
@@ -316,7 +591,17 @@ pub async fn db_search<T: Contract>(pubkey: String, db: Database) -> Result<T, S
     }
 }
 
-async fn db_search_with_session<T: Contract>(
+#[tracing::instrument(skip(db))]
+pub async fn db_search_account(pubkey: String, db: Database) -> Result<Account, SearchError> {
+    Ok(Account {
+        swarm: db_search::<Swarm>(pubkey.clone(), db.clone()).await?,
+        sacred_hive: db_search::<SacredHive>(pubkey.clone(), db.clone()).await?,
+        hive: db_search::<Hive>(pubkey.clone(), db.clone()).await?,
+        rating: ratings::db_search_rating(pubkey, db).await?,
+    })
+}
+
+pub async fn db_search_with_session<T: Contract>(
     pubkey: String,
     db: Database,
     session: &mut ClientSession,
@@ -335,67 +620,160 @@ async fn db_search_with_session<T: Contract>(
     }
 }
 
-pub async fn airdrop(pubkey: String, db: Database) -> Result<(), AirdropError> {
+#[tracing::instrument(skip(world), fields(world = %world.name))]
+pub async fn airdrop(pubkey: String, world: &World) -> Result<(), AirdropError> {
     if !pubkey_is_valid(&pubkey) {
         return Err(AirdropError::InvalidPubkey);
     }
+    let mut session = world.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    let db = world.db.clone();
     let collection: Collection<Swarm> = db.collection(SWARMS_COLL_NAME);
-    match collection.find_one(doc! { "pubkey": &pubkey }, None).await {
+    match collection
+        .find_one_with_session(doc! { "pubkey": &pubkey }, None, &mut session)
+        .await
+    {
         Ok(Some(_)) => Err(AirdropError::AlreadyExists),
         Ok(None) => {
-            db.collection(SACRED_HIVE_COLL_NAME)
-                .insert_one(
-                    SacredHive {
-                        pubkey: pubkey.clone(),
-                        sacred_queens: 0,
-                        eggs: 0,
-                    },
-                    None,
-                )
+            let sacred_hive = SacredHive {
+                pubkey: pubkey.clone(),
+                sacred_queens: 0,
+                eggs: 0,
+            };
+            let hive = Hive::empty(pubkey.clone());
+            let swarm = Swarm::empty(pubkey.clone())
+                .with(SACRED_QUEENS, world.config.airdrop_sacred_queens);
+            db.collection::<SacredHive>(SACRED_HIVE_COLL_NAME)
+                .insert_one_with_session(&sacred_hive, None, &mut session)
                 .await?;
-            db.collection(HIVE_COLL_NAME)
-                .insert_one(
-                    Hive {
-                        pubkey: pubkey.clone(),
-                        queens: 0,
-                        guardians: 0,
-                        eggs: 0,
-                    },
-                    None,
-                )
+            db.collection::<Hive>(HIVE_COLL_NAME)
+                .insert_one_with_session(&hive, None, &mut session)
                 .await?;
             collection
-                .insert_one(
-                    Swarm {
-                        berserkers: 0,
-                        pubkey,
-                        sacred_queens: 10,
-                        queens: 0,
-                        guardians: 0,
-                        eggs: 0,
-                    },
-                    None,
-                )
+                .insert_one_with_session(&swarm, None, &mut session)
                 .await?;
+            let changes = vec![
+                Change::created(&swarm),
+                Change::created(&hive),
+                Change::created(&sacred_hive),
+            ];
+            ledger::append(&db, &mut session, Operation::Airdrop, &pubkey, changes).await?;
+            commit_with_retry(&mut session).await?;
+            metrics::AIRDROPS.inc();
+            tracing::info!(
+                sacred_queens = world.config.airdrop_sacred_queens,
+                "airdrop issued"
+            );
             Ok(())
         }
         Err(e) => Err(AirdropError::DBError(e)),
     }
 }
 
-pub async fn stake<T: Contract>(request: T, mongo_client: Arc<Client>) -> Result<(), StakeError> {
-    let mut session = mongo_client.start_session(None).await?;
+/// Waits a little longer after every failed attempt.
+async fn backoff(attempt: u32) {
+    let millis = TRANSACTION_BACKOFF_MILLIS << attempt.min(8);
+    actix_web::rt::time::sleep(Duration::from_millis(millis)).await;
+}
+
+/// Commits the transaction, retrying up to `MAX_TRANSACTION_ATTEMPTS` times
+/// while the commit result is unknown.
+pub async fn commit_with_retry(session: &mut ClientSession) -> Result<(), MongoError> {
+    let mut attempt = 1;
+    loop {
+        match session.commit_transaction().await {
+            Err(e)
+                if e.contains_label(UNKNOWN_TRANSACTION_COMMIT_RESULT)
+                    && attempt < MAX_TRANSACTION_ATTEMPTS =>
+            {
+                metrics::TRANSACTION_RETRIES.inc();
+                backoff(attempt).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Runs the transaction of `operation` again, up to `MAX_TRANSACTION_ATTEMPTS`
+/// times, while it fails with a transient transaction error such as a write
+/// conflict with a concurrent transaction.
+pub async fn retry_transient<T, E, F, Fut>(mut operation: F) -> Result<T, E>
+where
+    E: TransactionError,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Err(e)
+                if attempt < MAX_TRANSACTION_ATTEMPTS
+                    && e.db_error()
+                        .is_some_and(|e| e.contains_label(TRANSIENT_TRANSACTION_ERROR)) =>
+            {
+                metrics::TRANSACTION_RETRIES.inc();
+                backoff(attempt).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        world = %world.name,
+        pubkey = %request.clone_pubkey(),
+        collection = T::get_collection(),
+        request = %serde_json::to_string(&request).unwrap_or_default(),
+    )
+)]
+pub async fn stake<T: Contract>(request: T, world: &World) -> Result<(), StakeError> {
+    transfer::<T>(request, Operation::Stake, world).await
+}
+
+pub async fn unstake<T: Contract>(request: T, world: &World) -> Result<(), StakeError> {
+    transfer::<T>(request.negative(), Operation::Unstake, world).await
+}
+
+/// Moves the tokens of `request` from the swarm to the staking contract.
+async fn transfer<T: Contract>(
+    request: T,
+    operation: Operation,
+    world: &World,
+) -> Result<(), StakeError> {
+    let mut session = world.client.start_session(None).await?;
     session.start_transaction(None).await?;
-    let db = mongo_client
-        .default_database()
-        .expect("default db not specified");
+    let db = world.db.clone();
+    if T::get_collection() == HIVE_COLL_NAME {
+        if !units::stakeable(&world.config.units, &request.balances()) {
+            tracing::info!("units can not be staked");
+            return Err(StakeError::NotStakeable);
+        }
+        production::settle_with_session(&request.clone_pubkey(), world, &mut session).await?;
+    }
     let mut swarm =
         db_search_with_session::<Swarm>(request.clone_pubkey(), db.clone(), &mut session).await?;
     let mut staked_tokens =
         db_search_with_session::<T>(request.clone_pubkey(), db.clone(), &mut session).await?;
+    let changes_before = (swarm.clone(), staked_tokens.clone());
     swarm.add(&request.as_swarm().negative());
     staked_tokens.add(&request);
+    if operation == Operation::Unstake
+        && T::get_collection() == SACRED_HIVE_COLL_NAME
+        && staked_tokens.is_negative()
+    {
+        let pubkey = request.clone_pubkey();
+        let (locked, _) = locks::accrual_with_session(&pubkey, 0, &db, &mut session).await?;
+        if locked > 0 {
+            tracing::info!(locked, "sacred queens are locked");
+            return Err(StakeError::Locked);
+        }
+    }
     if swarm.is_negative() || staked_tokens.is_negative() {
+        tracing::info!("not enough tokens to stake");
         return Err(StakeError::NotEnoughTokens);
     };
     db.collection::<T>(T::get_collection())
@@ -406,130 +784,74 @@ pub async fn stake<T: Contract>(request: T, mongo_client: Arc<Client>) -> Result
             &mut session,
         )
         .await?;
-    db.collection(Swarm::get_collection())
+    db.collection::<Swarm>(Swarm::get_collection())
         .replace_one_with_session(
             doc! { "pubkey": request.clone_pubkey() },
-            swarm,
+            &swarm,
             None,
             &mut session,
         )
         .await?;
-    session.commit_transaction().await?;
+    let changes = vec![
+        Change::new(&changes_before.0, &swarm),
+        Change::new(&changes_before.1, &staked_tokens),
+    ];
+    matchmaking::store_powers_with_session(&changes, world, &mut session).await?;
+    ledger::append(
+        &db,
+        &mut session,
+        operation,
+        &request.clone_pubkey(),
+        changes,
+    )
+    .await?;
+    commit_with_retry(&mut session).await?;
+    tracing::info!(?operation, "tokens transferred");
     Ok(())
 }
 
-pub async fn unstake<T: Contract>(request: T, mongo_client: Arc<Client>) -> Result<(), StakeError> {
-    stake::<T>(request.negative(), mongo_client).await
-}
-
-pub async fn trigger(pubkey: String, mongo_client: Arc<Client>) -> Result<(), SearchError> {
-    let mut session = mongo_client.start_session(None).await?;
+#[tracing::instrument(skip(world), fields(world = %world.name))]
+pub async fn trigger(pubkey: String, world: &World) -> Result<(), SearchError> {
+    let mut session = world.client.start_session(None).await?;
     session.start_transaction(None).await?;
-    let db = mongo_client
-        .default_database()
-        .expect("default db not specified");
+    let db = world.db.clone();
     let mut sacred_hive =
-        db_search_with_session::<SacredHive>(pubkey, db.clone(), &mut session)
-            .await?;
-    sacred_hive.eggs += sacred_hive.sacred_queens * 100;
-    db.collection(SacredHive::get_collection())
+        db_search_with_session::<SacredHive>(pubkey, db.clone(), &mut session).await?;
+    let before = sacred_hive.clone();
+    let eggs_per_sacred_queen = world.config.eggs_per_sacred_queen;
+    let (locked, locked_eggs) = locks::accrual_with_session(
+        &sacred_hive.pubkey,
+        eggs_per_sacred_queen,
+        &db,
+        &mut session,
+    )
+    .await?;
+    let laid_eggs = sacred_hive.sacred_queens * eggs_per_sacred_queen + locked_eggs;
+    sacred_hive.eggs += laid_eggs;
+    db.collection::<SacredHive>(SacredHive::get_collection())
         .replace_one_with_session(
             doc! { "pubkey": sacred_hive.clone_pubkey() },
-            sacred_hive,
-            None,
-            &mut session,
-        )
-        .await?;
-    session.commit_transaction().await?;
-    Ok(())
-}
-
-pub async fn process_hatch_request(
-    request: HatchRequest,
-    mongo_client: Arc<Client>,
-) -> Result<(), StakeError> {
-    let mut session = mongo_client.start_session(None).await?;
-    session.start_transaction(None).await?;
-    let db = mongo_client
-        .default_database()
-        .expect("default db not specified");
-    let mut swarm =
-        db_search_with_session::<Swarm>(request.pubkey.clone(), db.clone(), &mut session).await?;
-    if swarm.eggs < request.eggs {
-        return Err(StakeError::NotEnoughTokens);
-    }
-    swarm.eggs -= request.eggs;
-    let mut eggs = request.eggs;
-    while eggs > 0 {
-        let drop = rand::random::<u64>() % 100;
-        if drop == 0 {
-            swarm.queens += 1;
-        } else if drop < 10 {
-            swarm.guardians += 1;
-        } else {
-            swarm.berserkers += 1;
-        }
-        eggs -= 1;
-    }
-    db.collection(Swarm::get_collection())
-        .replace_one_with_session(
-            doc! { "pubkey": swarm.clone_pubkey() },
-            swarm,
-            None,
-            &mut session,
-        )
-        .await?;
-    session.commit_transaction().await?;
-    Ok(())
-}
-
-pub async fn attack(request: Attack, mongo_client: Arc<Client>) -> Result<(), AttackError> {
-    let mut session = mongo_client.start_session(None).await?;
-    session.start_transaction(None).await?;
-    let db = mongo_client
-        .default_database()
-        .expect("default db not specified");
-    let mut swarm =
-        db_search_with_session::<Swarm>(request.swarm_pubkey.clone(), db.clone(), &mut session)
-            .await?;
-    let mut hive =
-        db_search_with_session::<Hive>(request.hive_pubkey.clone(), db.clone(), &mut session)
-            .await?;
-    if swarm.berserkers < request.berserkers {
-        return Err(AttackError::NotEnoughTokens);
-    }
-    swarm.berserkers -= request.berserkers;
-    if hive.eggs == 0 {
-        return Err(AttackError::NotFound);
-    }
-    let attack_power = request.berserkers;
-    let random_queen_defense: i64 = i64::from(rand::random::<u8>()) % 10;
-    let random_guardian_defense: i64 = i64::from(rand::random::<u8>()) % 2;
-    let defense_power =
-        (hive.queens * random_queen_defense) + (hive.guardians * (9 + random_guardian_defense));
-    if attack_power > defense_power {
-        swarm.eggs += hive.eggs;
-        hive.eggs = 0;
-        hive.queens = 0;
-        hive.guardians = 0;
-    }
-    db.collection(Hive::get_collection())
-        .replace_one_with_session(
-            doc! { "pubkey": hive.clone_pubkey() },
-            hive,
-            None,
-            &mut session,
-        )
-        .await?;
-    db.collection(Swarm::get_collection())
-        .replace_one_with_session(
-            doc! { "pubkey": swarm.clone_pubkey() },
-            swarm,
+            &sacred_hive,
             None,
             &mut session,
         )
         .await?;
-    session.commit_transaction().await?;
+    // The unchanged locked sacred queens let the ledger check the eggs
+    // they laid.
+    let mut changes = vec![Change::new(&before, &sacred_hive)];
+    if locked > 0 {
+        changes.push(locks::locked_change(&sacred_hive.pubkey, locked, locked));
+    }
+    ledger::append(
+        &db,
+        &mut session,
+        Operation::Trigger,
+        &sacred_hive.pubkey,
+        changes,
+    )
+    .await?;
+    commit_with_retry(&mut session).await?;
+    tracing::info!(laid_eggs, "sacred queens laid eggs");
     Ok(())
 }
 
@@ -556,62 +878,26 @@ pub async fn create_db_indexes(db: &Database) {
         .expect("creating an index should succeed");
 }
 
-pub async fn init_mockup_db(db: &Database) {
-    match db
-        .collection::<Swarm>(Swarm::get_collection())
-        .find_one(None, None)
-        .await
-    {
-        Ok(None) => add_random_data(&db).await,
-        _ => {}
-    }
-}
-
-async fn add_random_data(db: &Database) {
-    let n = 999;
-    let mut swarms: Vec<Swarm> = Vec::new();
-    let mut hives: Vec<Hive> = Vec::new();
-    let mut sacred_hives: Vec<SacredHive> = Vec::new();
-    for _ in 0..n {
-        let pubkey = get_pubkey(&generate_keypair());
-        let m = rand::random::<u64>() % 10;
-        swarms.push(Swarm {
-            pubkey: pubkey.clone(),
-            sacred_queens: (rand::random::<u64>() % 10 * m * (rand::random::<u64>() % 2)) as i64,
-            queens: (rand::random::<u64>() % 100 * m * (rand::random::<u64>() % 2)) as i64,
-            guardians: (rand::random::<u64>() % 1000 * m * (rand::random::<u64>() % 2)) as i64,
-            eggs: (rand::random::<u64>() % 10000 * (rand::random::<u64>() % 2)) as i64,
-            berserkers: (rand::random::<u64>() % 10000 * m * (rand::random::<u64>() % 2)) as i64,
-        });
-        let m = rand::random::<u64>() % 10;
-        hives.push(Hive {
-            pubkey: pubkey.clone(),
-            guardians: (rand::random::<u64>() % 1000 * m + 1) as i64,
-            queens: (rand::random::<u64>() % 100 * m + 10) as i64,
-            eggs: (rand::random::<u64>() % 1000 * m) as i64,
-        });
-        let m = rand::random::<u64>() % 10;
-        sacred_hives.push(SacredHive {
-            pubkey: pubkey.clone(),
-            sacred_queens: (rand::random::<u64>() % 10 * m) as i64,
-            eggs: (rand::random::<u64>() % 100) as i64,
-        });
+/// Moves the units that swarms and hives kept in a `units` sub-document
+/// into fields of their own, next to the tokens.
+pub async fn flatten_units(db: &Database) -> Result<(), MongoError> {
+    let pipeline = vec![
+        doc! { "$replaceWith": { "$mergeObjects": ["$$ROOT", "$units"] } },
+        doc! { "$unset": "units" },
+    ];
+    for collection in [SWARMS_COLL_NAME, HIVE_COLL_NAME] {
+        db.collection::<Document>(collection)
+            .update_many(
+                doc! { "units": { "$exists": true } },
+                pipeline.clone(),
+                None,
+            )
+            .await?;
     }
-    db.collection(Swarm::get_collection())
-        .insert_many(swarms.clone(), None)
-        .await
-        .unwrap();
-    db.collection(Hive::get_collection())
-        .insert_many(hives.clone(), None)
-        .await
-        .unwrap();
-    db.collection(SacredHive::get_collection())
-        .insert_many(sacred_hives.clone(), None)
-        .await
-        .unwrap();
+    Ok(())
 }
 
-fn pubkey_is_valid(pubkey: &str) -> bool {
+pub fn pubkey_is_valid(pubkey: &str) -> bool {
     bs58::decode(pubkey)
         .into_vec()
         .ok()
@@ -0,0 +1,118 @@
+use {
+    futures::stream::TryStreamExt,
+    mongodb::{
+        bson::doc, error::Error as MongoError, options::FindOptions, ClientSession, Database,
+        IndexModel,
+    },
+    serde::{Deserialize, Serialize},
+};
+
+pub const NOTIFICATIONS_COLL_NAME: &str = "notifications";
+pub const NOTIFICATIONS_PAGE_SIZE: i64 = 100;
+
+/// Something that happened to a player while they were away.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    /// A march was launched against the hive of the player.
+    MarchLaunched {
+        march: String,
+        attacker: String,
+        arrives_at: i64,
+    },
+    /// A helper sent guardians to defend the hive against a march.
+    Reinforced {
+        march: String,
+        helper: String,
+        guardians: i64,
+    },
+    /// The attacker recalled a march before it arrived.
+    MarchRecalled { march: String },
+    /// A march the player took part in arrived and was fought.
+    MarchResolved {
+        march: String,
+        hive: String,
+        won: bool,
+        loot: i64,
+    },
+    /// A scout saw the exact numbers of the hive of the player.
+    Scouted { scout: String, expires_at: i64 },
+    /// A sponsor put a bounty on the hive of the player.
+    BountyPlaced {
+        bounty: String,
+        sponsor: String,
+        eggs: i64,
+        expires_at: i64,
+    },
+    /// A swarm won a raid on a hive the player put a bounty on.
+    BountyClaimed {
+        bounty: String,
+        hive: String,
+        hunter: String,
+        eggs: i64,
+    },
+    /// A bounty of the player expired and its eggs came back.
+    BountyRefunded { bounty: String, eggs: i64 },
+    /// An attacker hired the mercenaries the player listed.
+    MercenariesHired {
+        offer: String,
+        attacker: String,
+        march: String,
+    },
+    /// Mercenaries of the player came back from a march with their pay.
+    MercenariesReturned {
+        march: String,
+        survivors: i64,
+        eggs: i64,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Notification {
+    pub pubkey: String,
+    /// Unix milliseconds.
+    pub timestamp: i64,
+    pub event: Event,
+}
+
+/// Notifies `pubkey` inside the transaction of `session`, so that only
+/// committed events are seen.
+pub async fn notify_with_session(
+    db: &Database,
+    session: &mut ClientSession,
+    pubkey: &str,
+    event: Event,
+) -> Result<(), MongoError> {
+    let notification = Notification {
+        pubkey: pubkey.to_string(),
+        timestamp: chrono::Utc::now().timestamp_millis(),
+        event,
+    };
+    db.collection::<Notification>(NOTIFICATIONS_COLL_NAME)
+        .insert_one_with_session(notification, None, session)
+        .await?;
+    Ok(())
+}
+
+/// Latest notifications of `pubkey`, newest first.
+pub async fn recent(pubkey: &str, db: &Database) -> Result<Vec<Notification>, MongoError> {
+    let options = FindOptions::builder()
+        .sort(doc! { "timestamp": -1 })
+        .limit(NOTIFICATIONS_PAGE_SIZE)
+        .build();
+    db.collection::<Notification>(NOTIFICATIONS_COLL_NAME)
+        .find(doc! { "pubkey": pubkey }, options)
+        .await?
+        .try_collect()
+        .await
+}
+
+pub async fn create_notification_indexes(db: &Database) {
+    let model = IndexModel::builder()
+        .keys(doc! { "pubkey": 1, "timestamp": -1 })
+        .build();
+    db.collection::<Notification>(NOTIFICATIONS_COLL_NAME)
+        .create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+}
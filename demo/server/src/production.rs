@@ -0,0 +1,133 @@
+use {
+    super::{
+        ledger::{self, Change, Operation},
+        model::*,
+        units::{EGGS, GUARDIANS, QUEENS},
+        world::{GameConfig, World},
+    },
+    futures::stream::TryStreamExt,
+    mongodb::{
+        bson::doc,
+        error::Error as MongoError,
+        options::{IndexOptions, UpdateOptions},
+        ClientSession, Database, IndexModel,
+    },
+    serde::{Deserialize, Serialize},
+};
+
+pub const HIVE_CLOCKS_COLL_NAME: &str = "hiveClocks";
+pub const HIVE_EGGS_PER_QUEEN_HOUR: i64 = 10;
+pub const HIVE_CAPACITY_PER_GUARDIAN: i64 = 100;
+
+/// Last time the production of a hive was settled (unix seconds). Kept
+/// outside of `Hive` so that the staking requests signed by clients keep
+/// their shape.
+#[derive(Deserialize, Serialize)]
+pub struct HiveClock {
+    pub pubkey: String,
+    pub settled_at: i64,
+}
+
+/// Eggs laid by the queens of `hive` since `settled_at` and the new
+/// settlement time. Hives stop laying at `guardians * capacity per guardian`
+/// eggs. Time that did not produce a whole egg is carried over, unless the
+/// hive is full or has no queens.
+pub fn hive_production(hive: &Hive, settled_at: i64, now: i64, config: &GameConfig) -> (i64, i64) {
+    let rate = hive.get(QUEENS) * config.hive_eggs_per_queen_hour;
+    let room = (hive.get(GUARDIANS) * config.hive_capacity_per_guardian - hive.get(EGGS)).max(0);
+    if rate <= 0 || room == 0 {
+        return (0, now);
+    }
+    let laid = (rate * (now - settled_at).max(0) / 3600).min(room);
+    match laid == room {
+        true => (laid, now),
+        false => (laid, settled_at + laid * 3600 / rate),
+    }
+}
+
+/// Adds the eggs produced since the last settlement to the hive of `pubkey`
+/// inside the transaction of `session`. Returns the number of laid eggs.
+pub async fn settle_with_session(
+    pubkey: &str,
+    world: &World,
+    session: &mut ClientSession,
+) -> Result<i64, SearchError> {
+    let db = &world.db;
+    let hive = db_search_with_session::<Hive>(pubkey.to_string(), db.clone(), session).await?;
+    let now = chrono::Utc::now().timestamp();
+    let clocks = db.collection::<HiveClock>(HIVE_CLOCKS_COLL_NAME);
+    let clock = clocks
+        .find_one_with_session(doc! { "pubkey": pubkey }, None, session)
+        .await?
+        .map(|clock| clock.settled_at);
+    let (laid, settled_at) = hive_production(&hive, clock.unwrap_or(now), now, &world.config);
+    if laid > 0 {
+        let mut after = hive.clone();
+        after.change(EGGS, laid);
+        db.collection::<Hive>(HIVE_COLL_NAME)
+            .replace_one_with_session(doc! { "pubkey": pubkey }, &after, None, session)
+            .await?;
+        ledger::append(
+            db,
+            session,
+            Operation::Produce,
+            pubkey,
+            vec![Change::new(&hive, &after)],
+        )
+        .await?;
+        tracing::info!(laid, "hive laid eggs");
+    }
+    if clock != Some(settled_at) {
+        clocks
+            .update_one_with_session(
+                doc! { "pubkey": pubkey },
+                doc! { "$set": { "settled_at": settled_at } },
+                UpdateOptions::builder().upsert(true).build(),
+                session,
+            )
+            .await?;
+    }
+    Ok(laid)
+}
+
+/// Settles the production of a hive in its own transaction, used before
+/// reads. Hive lists are not settled and show the last settled eggs.
+#[tracing::instrument(skip(world), fields(world = %world.name))]
+pub async fn settle(pubkey: String, world: &World) -> Result<i64, SearchError> {
+    let mut session = world.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    let laid = settle_with_session(&pubkey, world, &mut session).await?;
+    commit_with_retry(&mut session).await?;
+    Ok(laid)
+}
+
+/// Starts the clocks of hives that do not have one yet at `now`.
+pub async fn start_clocks(db: &Database, now: i64) -> Result<(), MongoError> {
+    let clocks = db.collection::<HiveClock>(HIVE_CLOCKS_COLL_NAME);
+    let mut cursor = db
+        .collection::<Hive>(HIVE_COLL_NAME)
+        .find(None, None)
+        .await?;
+    while let Some(hive) = cursor.try_next().await? {
+        clocks
+            .update_one(
+                doc! { "pubkey": &hive.pubkey },
+                doc! { "$setOnInsert": { "settled_at": now } },
+                UpdateOptions::builder().upsert(true).build(),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+pub async fn create_production_indexes(db: &Database) {
+    let options = IndexOptions::builder().unique(true).build();
+    let model = IndexModel::builder()
+        .keys(doc! { "pubkey": 1 })
+        .options(options)
+        .build();
+    db.collection::<HiveClock>(HIVE_CLOCKS_COLL_NAME)
+        .create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+}
@@ -0,0 +1,206 @@
+use {
+    super::model::*,
+    futures::stream::TryStreamExt,
+    mongodb::{
+        bson::doc,
+        error::Error as MongoError,
+        options::{FindOptions, IndexOptions, ReplaceOptions},
+        ClientSession, Database, IndexModel,
+    },
+    serde::{Deserialize, Serialize},
+};
+
+pub const RATINGS_COLL_NAME: &str = "ratings";
+pub const INITIAL_RATING: i64 = 1200;
+pub const RATING_K_FACTOR: i64 = 32;
+pub const LEADERBOARD_SIZE: i64 = 10;
+
+/// Elo ratings of a swarm as attacker and as defender. Swarms without a
+/// document have the initial ratings.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Rating {
+    pub pubkey: String,
+    pub attack: i64,
+    pub defense: i64,
+    /// Resolved attacks the ratings were updated with.
+    pub attacks: i64,
+    pub defenses: i64,
+}
+
+impl Rating {
+    pub fn new(pubkey: String) -> Self {
+        Rating {
+            pubkey,
+            attack: INITIAL_RATING,
+            defense: INITIAL_RATING,
+            attacks: 0,
+            defenses: 0,
+        }
+    }
+}
+
+/// The body of `/swarm/{pubkey}`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct RatedSwarm {
+    #[serde(flatten)]
+    pub swarm: Swarm,
+    pub rating: Rating,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RatingKind {
+    Attack,
+    Defense,
+}
+
+/// Chance of an attacker rated `attack` to beat a defender rated `defense`.
+pub fn expected_win(attack: i64, defense: i64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((defense - attack) as f64 / 400.0))
+}
+
+/// Rating points the attacker wins and the defender loses, negative when
+/// the attacker lost.
+pub fn rating_change(attack: i64, defense: i64, won: bool, k_factor: i64) -> i64 {
+    let actual = if won { 1.0 } else { 0.0 };
+    (k_factor as f64 * (actual - expected_win(attack, defense))).round() as i64
+}
+
+async fn load_with_session(
+    pubkey: &str,
+    db: &Database,
+    session: &mut ClientSession,
+) -> Result<Rating, MongoError> {
+    Ok(db
+        .collection::<Rating>(RATINGS_COLL_NAME)
+        .find_one_with_session(doc! { "pubkey": pubkey }, None, session)
+        .await?
+        .unwrap_or_else(|| Rating::new(pubkey.to_string())))
+}
+
+async fn write_with_session(
+    rating: &Rating,
+    db: &Database,
+    session: &mut ClientSession,
+) -> Result<(), MongoError> {
+    db.collection::<Rating>(RATINGS_COLL_NAME)
+        .replace_one_with_session(
+            doc! { "pubkey": &rating.pubkey },
+            rating,
+            ReplaceOptions::builder().upsert(true).build(),
+            session,
+        )
+        .await?;
+    Ok(())
+}
+
+/// Rates a resolved attack of `attacker` on the hive of `defender` inside
+/// the transaction of `session`. Returns the points the attacker won.
+/// Attacks on the own hive are not rated.
+pub async fn rate_with_session(
+    attacker: &str,
+    defender: &str,
+    won: bool,
+    k_factor: i64,
+    db: &Database,
+    session: &mut ClientSession,
+) -> Result<i64, MongoError> {
+    if attacker == defender {
+        return Ok(0);
+    }
+    let mut attacker = load_with_session(attacker, db, session).await?;
+    let mut defender = load_with_session(defender, db, session).await?;
+    let change = rating_change(attacker.attack, defender.defense, won, k_factor);
+    attacker.attack += change;
+    attacker.attacks += 1;
+    defender.defense -= change;
+    defender.defenses += 1;
+    write_with_session(&attacker, db, session).await?;
+    write_with_session(&defender, db, session).await?;
+    Ok(change)
+}
+
+#[tracing::instrument(skip(db))]
+pub async fn db_search_rating(pubkey: String, db: Database) -> Result<Rating, SearchError> {
+    if !pubkey_is_valid(&pubkey) {
+        return Err(SearchError::InvalidPubkey);
+    }
+    Ok(db
+        .collection::<Rating>(RATINGS_COLL_NAME)
+        .find_one(doc! { "pubkey": &pubkey }, None)
+        .await?
+        .unwrap_or_else(|| Rating::new(pubkey)))
+}
+
+/// Ratings of the swarms of `pubkeys`, initial ones for unrated swarms.
+pub async fn db_search_ratings(
+    pubkeys: &[String],
+    db: &Database,
+) -> Result<Vec<Rating>, MongoError> {
+    let rated: Vec<Rating> = db
+        .collection::<Rating>(RATINGS_COLL_NAME)
+        .find(doc! { "pubkey": { "$in": pubkeys } }, None)
+        .await?
+        .try_collect()
+        .await?;
+    Ok(pubkeys
+        .iter()
+        .map(|pubkey| {
+            rated
+                .iter()
+                .find(|rating| &rating.pubkey == pubkey)
+                .cloned()
+                .unwrap_or_else(|| Rating::new(pubkey.clone()))
+        })
+        .collect())
+}
+
+#[tracing::instrument(skip(db))]
+pub async fn db_search_swarm_rated(
+    pubkey: String,
+    db: Database,
+) -> Result<RatedSwarm, SearchError> {
+    Ok(RatedSwarm {
+        swarm: db_search::<Swarm>(pubkey.clone(), db.clone()).await?,
+        rating: db_search_rating(pubkey, db).await?,
+    })
+}
+
+/// Best rated swarms of `kind`, best first.
+#[tracing::instrument(skip(db))]
+pub async fn leaderboard(kind: RatingKind, db: Database) -> Result<Vec<Rating>, SearchError> {
+    let (field, played) = match kind {
+        RatingKind::Attack => ("attack", "attacks"),
+        RatingKind::Defense => ("defense", "defenses"),
+    };
+    let options = FindOptions::builder()
+        .sort(doc! { field: -1 })
+        .limit(LEADERBOARD_SIZE)
+        .build();
+    Ok(db
+        .collection::<Rating>(RATINGS_COLL_NAME)
+        .find(doc! { played: { "$gt": 0 } }, options)
+        .await?
+        .try_collect()
+        .await?)
+}
+
+pub async fn create_rating_indexes(db: &Database) {
+    let ratings = db.collection::<Rating>(RATINGS_COLL_NAME);
+    let options = IndexOptions::builder().unique(true).build();
+    let model = IndexModel::builder()
+        .keys(doc! { "pubkey": 1 })
+        .options(options)
+        .build();
+    ratings
+        .create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+    for field in ["attack", "defense"] {
+        let model = IndexModel::builder().keys(doc! { field: -1 }).build();
+        ratings
+            .create_index(model, None)
+            .await
+            .expect("creating an index should succeed");
+    }
+}
@@ -0,0 +1,233 @@
+use {
+    super::{
+        ledger::{self, Change, Operation},
+        model::*,
+        notifications::{self, Event},
+        production,
+        units::{BERSERKERS, EGGS, GUARDIANS, QUEENS},
+        world::World,
+    },
+    futures::stream::TryStreamExt,
+    mongodb::{
+        bson::doc,
+        error::Error as MongoError,
+        options::{IndexOptions, ReplaceOptions},
+        Database, IndexModel,
+    },
+    serde::{Deserialize, Serialize},
+};
+
+pub const INTEL_COLL_NAME: &str = "intel";
+pub const SCOUT_BERSERKERS: i64 = 10;
+pub const SCOUT_EGGS: i64 = 100;
+pub const INTEL_SECS: i64 = 3600;
+
+/// Range a hidden number lies in, both ends included.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Estimate {
+    pub min: i64,
+    pub max: i64,
+}
+
+/// Rounds `n` to the power of two range it falls in, so 100 guardians show
+/// as 64 to 127. Empty stays exact.
+pub fn estimate(n: i64) -> Estimate {
+    if n <= 0 {
+        return Estimate { min: 0, max: 0 };
+    }
+    let min = 1 << (63 - n.leading_zeros());
+    Estimate {
+        min,
+        max: min + (min - 1),
+    }
+}
+
+/// A hive as everybody but its owner and its scouts sees it. The eggs are
+/// the loot and stay exact.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct HiveEstimate {
+    pub pubkey: String,
+    pub guardians: Estimate,
+    pub queens: Estimate,
+    pub eggs: i64,
+}
+
+impl From<&Hive> for HiveEstimate {
+    fn from(hive: &Hive) -> Self {
+        HiveEstimate {
+            pubkey: hive.pubkey.clone(),
+            guardians: estimate(hive.get(GUARDIANS)),
+            queens: estimate(hive.get(QUEENS)),
+            eggs: hive.get(EGGS),
+        }
+    }
+}
+
+/// Tokens a scout is paid with.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoutPayment {
+    Berserkers,
+    Eggs,
+}
+
+/// Body of `/scout`, signed by the scouting swarm.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Scout {
+    pub pubkey: String,
+    pub hive_pubkey: String,
+    pub pay_with: ScoutPayment,
+}
+
+impl KeyCloner for Scout {
+    fn clone_pubkey(&self) -> String {
+        self.pubkey.clone()
+    }
+}
+
+/// The exact hive as `scout` saw it at `scouted_at`. Readable by the scout
+/// until `expires_at` (unix seconds), scouting the hive again replaces it.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Intel {
+    pub scout: String,
+    pub hive: Hive,
+    pub scouted_at: i64,
+    pub expires_at: i64,
+}
+
+pub enum ScoutError {
+    InvalidPubkey,
+    NotFound,
+    NotEnoughTokens,
+    DBError(MongoError),
+}
+
+impl From<SearchError> for ScoutError {
+    fn from(e: SearchError) -> ScoutError {
+        match e {
+            SearchError::NotFound => ScoutError::NotEnoughTokens,
+            SearchError::InvalidPubkey => ScoutError::InvalidPubkey,
+            SearchError::DBError(e) => ScoutError::DBError(e),
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for ScoutError {
+    fn from(e: mongodb::error::Error) -> ScoutError {
+        ScoutError::DBError(e)
+    }
+}
+
+impl TransactionError for ScoutError {
+    fn db_error(&self) -> Option<&MongoError> {
+        match self {
+            ScoutError::DBError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Spends the scouting cost from the swarm of the scout and records the
+/// exact hive for `intel_secs`. The scouted hive is notified. The spent
+/// tokens are gone.
+#[tracing::instrument(
+    skip_all,
+    fields(world = %world.name, pubkey = %request.pubkey, hive_pubkey = %request.hive_pubkey)
+)]
+pub async fn scout(request: Scout, world: &World) -> Result<Intel, ScoutError> {
+    if !pubkey_is_valid(&request.hive_pubkey) {
+        return Err(ScoutError::InvalidPubkey);
+    }
+    let mut session = world.client.start_session(None).await?;
+    session.start_transaction(None).await?;
+    let db = world.db.clone();
+    let mut swarm =
+        db_search_with_session::<Swarm>(request.pubkey.clone(), db.clone(), &mut session).await?;
+    match production::settle_with_session(&request.hive_pubkey, world, &mut session).await {
+        Err(SearchError::NotFound) => return Err(ScoutError::NotFound),
+        result => result?,
+    };
+    let hive =
+        db_search_with_session::<Hive>(request.hive_pubkey.clone(), db.clone(), &mut session)
+            .await?;
+    let before = swarm.clone();
+    let (token, cost) = match request.pay_with {
+        ScoutPayment::Berserkers => (BERSERKERS, world.config.scout_berserkers),
+        ScoutPayment::Eggs => (EGGS, world.config.scout_eggs),
+    };
+    let available = swarm.get(token);
+    if available < cost {
+        tracing::info!(available, cost, "not enough tokens to scout");
+        return Err(ScoutError::NotEnoughTokens);
+    }
+    swarm.change(token, -cost);
+    let now = chrono::Utc::now().timestamp();
+    let intel = Intel {
+        scout: request.pubkey.clone(),
+        hive,
+        scouted_at: now,
+        expires_at: now + world.config.intel_secs,
+    };
+    db.collection::<Swarm>(Swarm::get_collection())
+        .replace_one_with_session(doc! { "pubkey": &swarm.pubkey }, &swarm, None, &mut session)
+        .await?;
+    db.collection::<Intel>(INTEL_COLL_NAME)
+        .replace_one_with_session(
+            doc! { "scout": &intel.scout, "hive.pubkey": &intel.hive.pubkey },
+            &intel,
+            ReplaceOptions::builder().upsert(true).build(),
+            &mut session,
+        )
+        .await?;
+    ledger::append(
+        &db,
+        &mut session,
+        Operation::Scout,
+        &swarm.pubkey,
+        vec![Change::new(&before, &swarm)],
+    )
+    .await?;
+    notifications::notify_with_session(
+        &db,
+        &mut session,
+        &request.hive_pubkey,
+        Event::Scouted {
+            scout: request.pubkey.clone(),
+            expires_at: intel.expires_at,
+        },
+    )
+    .await?;
+    commit_with_retry(&mut session).await?;
+    tracing::info!(expires_at = intel.expires_at, "hive scouted");
+    Ok(intel)
+}
+
+/// Intel of `scout` that did not expire yet.
+#[tracing::instrument(skip(db))]
+pub async fn live_intel(scout: String, db: Database) -> Result<Vec<Intel>, SearchError> {
+    if !pubkey_is_valid(&scout) {
+        return Err(SearchError::InvalidPubkey);
+    }
+    let filter = doc! {
+        "scout": &scout,
+        "expires_at": { "$gt": chrono::Utc::now().timestamp() },
+    };
+    Ok(db
+        .collection::<Intel>(INTEL_COLL_NAME)
+        .find(filter, None)
+        .await?
+        .try_collect()
+        .await?)
+}
+
+pub async fn create_intel_indexes(db: &Database) {
+    let options = IndexOptions::builder().unique(true).build();
+    let model = IndexModel::builder()
+        .keys(doc! { "scout": 1, "hive.pubkey": 1 })
+        .options(options)
+        .build();
+    db.collection::<Intel>(INTEL_COLL_NAME)
+        .create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+}
@@ -0,0 +1,230 @@
+use {
+    super::{
+        ledger::{self, Change, LedgerEntry, Operation},
+        model::*,
+        units::{BERSERKERS, EGGS, GUARDIANS, QUEENS, SACRED_QUEENS},
+    },
+    ed25519_dalek::Keypair,
+    mongodb::{error::Error as MongoError, Database},
+    rand::{Rng, SeedableRng},
+    rand_chacha::ChaCha8Rng,
+    std::str::FromStr,
+};
+
+/// Shape of the generated player base.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Preset {
+    /// Players of every size, like a server that ran for a while.
+    Balanced,
+    /// Mostly small players and a few (5%) very rich ones.
+    Whales,
+    /// Freshly airdropped players with a handful of hatched units.
+    Newbies,
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "balanced" => Ok(Preset::Balanced),
+            "whales" => Ok(Preset::Whales),
+            "newbies" => Ok(Preset::Newbies),
+            _ => Err(format!("unknown preset {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SeedConfig {
+    pub seed: u64,
+    pub players: usize,
+    pub preset: Preset,
+}
+
+impl SeedConfig {
+    /// Reads `SEED`, `SEED_PLAYERS` (default 999) and `SEED_PRESET` (default
+    /// balanced). Without `SEED` the server does not seed on startup.
+    pub fn from_env() -> Option<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        Some(SeedConfig {
+            seed: var("SEED")?.parse().expect("SEED is not a number"),
+            players: var("SEED_PLAYERS")
+                .map_or(999, |p| p.parse().expect("SEED_PLAYERS is not a number")),
+            preset: var("SEED_PRESET").map_or(Preset::Balanced, |p| {
+                p.parse()
+                    .expect("SEED_PRESET is not one of balanced, whales or newbies")
+            }),
+        })
+    }
+}
+
+/// Documents of a generated world, in player order.
+#[derive(Debug, PartialEq)]
+pub struct SeedWorld {
+    pub swarms: Vec<Swarm>,
+    pub hives: Vec<Hive>,
+    pub sacred_hives: Vec<SacredHive>,
+}
+
+fn balanced(rng: &mut ChaCha8Rng, pubkey: &str, m: i64) -> (Swarm, Hive, SacredHive) {
+    let mut amount = |max: i64, m: i64| rng.gen_range(0, max) * m * rng.gen_range(0, 2);
+    let swarm = Swarm::empty(pubkey.to_string())
+        .with(SACRED_QUEENS, amount(10, m))
+        .with(QUEENS, amount(100, m))
+        .with(GUARDIANS, amount(1000, m))
+        .with(EGGS, amount(10000, 1))
+        .with(BERSERKERS, amount(10000, m));
+    let hive = Hive::empty(pubkey.to_string())
+        .with(GUARDIANS, rng.gen_range(0, 1000) * m + 1)
+        .with(QUEENS, rng.gen_range(0, 100) * m + 10)
+        .with(EGGS, rng.gen_range(0, 1000) * m);
+    let sacred_hive = SacredHive {
+        pubkey: pubkey.to_string(),
+        sacred_queens: rng.gen_range(0, 10) * m,
+        eggs: rng.gen_range(0, 100),
+    };
+    (swarm, hive, sacred_hive)
+}
+
+fn newbie(rng: &mut ChaCha8Rng, pubkey: &str) -> (Swarm, Hive, SacredHive) {
+    let staked = rng.gen_range(0, AIRDROP_SACRED_QUEENS + 1);
+    let swarm = Swarm::empty(pubkey.to_string())
+        .with(SACRED_QUEENS, AIRDROP_SACRED_QUEENS - staked)
+        .with(BERSERKERS, rng.gen_range(0, 50))
+        .with(EGGS, rng.gen_range(0, 300));
+    let hive = Hive::empty(pubkey.to_string())
+        .with(GUARDIANS, rng.gen_range(1, 5))
+        .with(QUEENS, rng.gen_range(0, 2))
+        .with(EGGS, rng.gen_range(0, 100));
+    let sacred_hive = SacredHive {
+        pubkey: pubkey.to_string(),
+        sacred_queens: staked,
+        eggs: 0,
+    };
+    (swarm, hive, sacred_hive)
+}
+
+/// Generates the same world for the same config.
+pub fn generate(config: &SeedConfig) -> SeedWorld {
+    let mut rng = ChaCha8Rng::seed_from_u64(config.seed);
+    let mut world = SeedWorld {
+        swarms: Vec::with_capacity(config.players),
+        hives: Vec::with_capacity(config.players),
+        sacred_hives: Vec::with_capacity(config.players),
+    };
+    for _ in 0..config.players {
+        let pubkey = get_pubkey(&Keypair::generate(&mut rng));
+        let (swarm, hive, sacred_hive) = match config.preset {
+            Preset::Balanced => {
+                let m = rng.gen_range(0, 10);
+                balanced(&mut rng, &pubkey, m)
+            }
+            Preset::Whales => {
+                let m = if rng.gen_bool(0.05) {
+                    100
+                } else {
+                    rng.gen_range(0, 3)
+                };
+                balanced(&mut rng, &pubkey, m)
+            }
+            Preset::Newbies => newbie(&mut rng, &pubkey),
+        };
+        world.swarms.push(swarm);
+        world.hives.push(hive);
+        world.sacred_hives.push(sacred_hive);
+    }
+    world
+}
+
+/// Inserts a world and its genesis ledger entries.
+pub async fn write(world: SeedWorld, db: &Database) -> Result<(), MongoError> {
+    if world.swarms.is_empty() {
+        return Ok(());
+    }
+    let timestamp = chrono::Utc::now().timestamp_millis();
+    let genesis: Vec<LedgerEntry> = world
+        .swarms
+        .iter()
+        .zip(world.hives.iter())
+        .zip(world.sacred_hives.iter())
+        .map(|((swarm, hive), sacred_hive)| LedgerEntry {
+            timestamp,
+            operation: Operation::Genesis,
+            pubkey: swarm.pubkey.clone(),
+            changes: vec![
+                Change::created(swarm),
+                Change::created(hive),
+                Change::created(sacred_hive),
+            ],
+        })
+        .collect();
+    db.collection::<Swarm>(Swarm::get_collection())
+        .insert_many(world.swarms, None)
+        .await?;
+    db.collection::<Hive>(Hive::get_collection())
+        .insert_many(world.hives, None)
+        .await?;
+    db.collection::<SacredHive>(SacredHive::get_collection())
+        .insert_many(world.sacred_hives, None)
+        .await?;
+    db.collection::<LedgerEntry>(ledger::LEDGER_COLL_NAME)
+        .insert_many(genesis, None)
+        .await?;
+    Ok(())
+}
+
+async fn is_empty(db: &Database) -> Result<bool, MongoError> {
+    Ok(db
+        .collection::<Swarm>(Swarm::get_collection())
+        .find_one(None, None)
+        .await?
+        .is_none())
+}
+
+/// Seeds the world on startup if `SEED` is set and there are no players yet.
+pub async fn seed_on_startup(db: &Database) {
+    if let Some(config) = SeedConfig::from_env() {
+        if is_empty(db).await.expect("reading swarms should succeed") {
+            tracing::info!(?config, "seeding world");
+            write(generate(&config), db)
+                .await
+                .expect("seeding the world should succeed");
+        }
+    }
+}
+
+/// `seed <seed> <players> [balanced|whales|newbies] [target_db]`: writes a
+/// generated world into `target_db` (default the configured database), which
+/// must not have players yet.
+pub async fn run_seed(args: &[String], db: Database) -> Result<bool, MongoError> {
+    let preset = args.get(2).map_or(Ok(Preset::Balanced), |p| p.parse());
+    let config = match (
+        args.first().map(|s| s.parse()),
+        args.get(1).map(|p| p.parse()),
+        preset,
+    ) {
+        (Some(Ok(seed)), Some(Ok(players)), Ok(preset)) => SeedConfig {
+            seed,
+            players,
+            preset,
+        },
+        _ => {
+            eprintln!("usage: server seed <seed> <players> [balanced|whales|newbies] [target_db]");
+            return Ok(false);
+        }
+    };
+    if !is_empty(&db).await? {
+        println!("{} already has players", db.name());
+        return Ok(false);
+    }
+    write(generate(&config), &db).await?;
+    println!(
+        "{} {:?} players seeded from {} into {}",
+        config.players,
+        config.preset,
+        config.seed,
+        db.name()
+    );
+    Ok(true)
+}
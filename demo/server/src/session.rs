@@ -0,0 +1,181 @@
+use {
+    super::model::{generate_keypair, pubkey_is_valid, KeyCloner},
+    anyhow::Result,
+    ed25519_dalek::*,
+    mongodb::{
+        bson::{doc, DateTime},
+        error::Error as MongoError,
+        options::IndexOptions,
+        Database, IndexModel,
+    },
+    serde::{Deserialize, Serialize},
+    std::time::Duration,
+};
+
+pub const CHALLENGES_COLL_NAME: &str = "challenges";
+pub const CHALLENGE_TTL_SECS: i64 = 60;
+pub const SESSION_TTL_SECS: i64 = 3600;
+
+#[derive(Deserialize, Serialize)]
+pub struct Challenge {
+    pub pubkey: String,
+    pub challenge: String,
+    pub expires_at: i64,
+}
+
+/// A challenge as stored. Mongo removes it once `expires_at` has passed.
+#[derive(Deserialize, Serialize)]
+struct ChallengeDocument {
+    pubkey: String,
+    challenge: String,
+    expires_at: DateTime,
+}
+
+/// Body of `/auth/login`, signed by the key that asked for the challenge.
+#[derive(Deserialize, Serialize)]
+pub struct Login {
+    pub pubkey: String,
+    pub challenge: String,
+}
+
+/// Response of a successful login.
+#[derive(Deserialize, Serialize)]
+pub struct Session {
+    pub token: String,
+    pub expires_at: i64,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SessionToken {
+    pub pubkey: String,
+    pub expires_at: i64,
+}
+
+/// Server key used to sign and verify session tokens.
+pub struct SessionKey(pub Keypair);
+
+impl SessionKey {
+    /// Loads the bs58 encoded keypair from `SESSION_KEYPAIR`. Without it a
+    /// fresh key is generated and tokens do not survive a restart.
+    pub fn from_env() -> Self {
+        match std::env::var("SESSION_KEYPAIR") {
+            Ok(privkey) => {
+                let decoded: &[u8] = &bs58::decode(privkey)
+                    .into_vec()
+                    .expect("SESSION_KEYPAIR is not bs58");
+                SessionKey(Keypair::from_bytes(decoded).expect("SESSION_KEYPAIR is not a keypair"))
+            }
+            Err(_) => SessionKey(generate_keypair()),
+        }
+    }
+
+    /// Issues a token in the form `<bs58 json>.<bs58 signature>`.
+    pub fn issue(&self, token: &SessionToken) -> String {
+        let payload = serde_json::to_string(token).expect("token serialization failed");
+        let signature: Signature = self.0.sign(payload.as_bytes());
+        format!(
+            "{}.{}",
+            bs58::encode(payload).into_string(),
+            bs58::encode(signature).into_string()
+        )
+    }
+
+    /// Returns the pubkey of a token signed by this key that did not expire.
+    pub fn verify(&self, token: &str, now: i64) -> Result<String> {
+        let (payload, signature) = token
+            .split_once('.')
+            .ok_or_else(|| anyhow::Error::msg("malformed token"))?;
+        let payload = bs58::decode(payload).into_vec()?;
+        let signature = Signature::from_bytes(&bs58::decode(signature).into_vec()?)?;
+        self.0.public.verify(&payload, &signature)?;
+        let token: SessionToken = serde_json::from_slice(&payload)?;
+        if token.expires_at <= now {
+            return Err(anyhow::Error::msg("token expired"));
+        }
+        Ok(token.pubkey)
+    }
+}
+
+pub enum SessionError {
+    InvalidPubkey,
+    InvalidChallenge,
+    DBError(MongoError),
+}
+
+impl From<mongodb::error::Error> for SessionError {
+    fn from(e: mongodb::error::Error) -> SessionError {
+        SessionError::DBError(e)
+    }
+}
+
+impl KeyCloner for Login {
+    fn clone_pubkey(&self) -> String {
+        self.pubkey.clone()
+    }
+}
+
+#[tracing::instrument(skip(db))]
+pub async fn create_challenge(pubkey: String, db: Database) -> Result<Challenge, SessionError> {
+    if !pubkey_is_valid(&pubkey) {
+        return Err(SessionError::InvalidPubkey);
+    }
+    let challenge = Challenge {
+        pubkey,
+        challenge: bs58::encode(rand::random::<[u8; 32]>()).into_string(),
+        expires_at: chrono::Utc::now().timestamp() + CHALLENGE_TTL_SECS,
+    };
+    let document = ChallengeDocument {
+        pubkey: challenge.pubkey.clone(),
+        challenge: challenge.challenge.clone(),
+        expires_at: DateTime::from_millis(challenge.expires_at * 1000),
+    };
+    db.collection::<ChallengeDocument>(CHALLENGES_COLL_NAME)
+        .insert_one(&document, None)
+        .await?;
+    Ok(challenge)
+}
+
+/// Consumes the challenge of a signed login request and issues a token.
+#[tracing::instrument(skip_all, fields(pubkey = %request.pubkey))]
+pub async fn login(
+    request: Login,
+    key: &SessionKey,
+    db: Database,
+) -> Result<Session, SessionError> {
+    let now = chrono::Utc::now().timestamp();
+    let challenge = db
+        .collection::<ChallengeDocument>(CHALLENGES_COLL_NAME)
+        .find_one_and_delete(
+            doc! {
+                "pubkey": &request.pubkey,
+                "challenge": &request.challenge,
+                "expires_at": { "$gt": DateTime::from_millis(now * 1000) },
+            },
+            None,
+        )
+        .await?;
+    if challenge.is_none() {
+        return Err(SessionError::InvalidChallenge);
+    }
+    let expires_at = now + SESSION_TTL_SECS;
+    let token = key.issue(&SessionToken {
+        pubkey: request.pubkey,
+        expires_at,
+    });
+    Ok(Session { token, expires_at })
+}
+
+/// Lets Mongo remove challenges that expired without a login.
+pub async fn create_challenge_indexes(db: &Database) {
+    let options = IndexOptions::builder()
+        .expire_after(Duration::from_secs(0))
+        .build();
+    let model = IndexModel::builder()
+        .keys(doc! { "expires_at": 1 })
+        .options(options)
+        .build();
+    db.collection::<ChallengeDocument>(CHALLENGES_COLL_NAME)
+        .create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+}
@@ -1,19 +1,31 @@
 #![cfg(test)]
 
 use {
-    super::{model::*, *},
+    super::{
+        delegation::*,
+        matchmaking::OUT_OF_BAND_LOOT_PERCENT,
+        model::*,
+        session::*,
+        units::{Balances, BERSERKERS, EGGS, GUARDIANS, QUEENS, SACRED_QUEENS},
+        world::*,
+        *,
+    },
     actix_http::{body::MessageBody, Request},
     actix_web::{
         dev::{Service, ServiceResponse},
         error::Error,
         rt::time::timeout,
-        test::{call_service, init_service, read_body, TestRequest},
+        test::{call_service, init_service, read_body, read_body_json, TestRequest},
     },
     ed25519_dalek::*,
+    futures::stream::TryStreamExt,
     http::StatusCode,
     mongodb::{bson::doc, Client, Database},
     serde::Serialize,
-    std::time::Duration,
+    std::{
+        io::{Read, Write},
+        time::Duration,
+    },
 };
 
 #[derive(Debug)]
@@ -127,7 +139,16 @@ macro_rules! init_app_and_db {
             .expect("failed to connect to database");
         let app = init_service(
             App::new()
-                .app_data(web::Data::new(mongo_client.clone()))
+                .app_data(web::Data::new(Worlds::single(mongo_client.clone())))
+                .app_data(web::Data::new(SessionKey::from_env()))
+                .wrap_fn(|req, srv| {
+                    let response = srv.call(req);
+                    async move {
+                        let response = response.await?;
+                        finish_signed_request(&response).await;
+                        Ok(response)
+                    }
+                })
                 $(.service($service))*,
         )
         .await;
@@ -138,6 +159,34 @@ macro_rules! init_app_and_db {
     }};
 }
 
+/// The world served by `init_app_and_db!`, for calling game logic directly.
+async fn default_world() -> World {
+    let uri = std::env::var("MONGODB_URI").unwrap();
+    let mongo_client = Client::with_uri_str(uri)
+        .await
+        .expect("failed to connect to database");
+    Worlds::single(mongo_client).default_world().clone()
+}
+
+/// The body of `/swarm/{pubkey}` for a swarm that was never rated.
+fn rated(swarm: Swarm) -> ratings::RatedSwarm {
+    ratings::RatedSwarm {
+        rating: ratings::Rating::new(swarm.pubkey.clone()),
+        swarm,
+    }
+}
+
+/// Lets the march of `swarm_pubkey` arrive right away.
+async fn land_march(db: &Database, swarm_pubkey: &str) -> march::March {
+    let marches = db.collection::<march::March>(march::MARCHES_COLL_NAME);
+    let filter = doc! { "swarm_pubkey": swarm_pubkey, "status": "marching" };
+    marches
+        .update_one(filter.clone(), doc! { "$set": { "arrives_at": 0 } }, None)
+        .await
+        .unwrap();
+    marches.find_one(filter, None).await.unwrap().unwrap()
+}
+
 #[actix_web::test]
 async fn airdrop_and_swarm() {
     let (app, _) = init_app_and_db!(get_airdrop, get_swarm);
@@ -158,14 +207,7 @@ async fn airdrop_and_swarm() {
     // get account to see it has 10 sacred_queens
     wrap_test!(
         "/swarm/".to_string() + &pubkey,
-        Swarm {
-            berserkers: 0,
-            pubkey: pubkey.clone(),
-            sacred_queens: 10,
-            queens: 0,
-            guardians: 0,
-            eggs: 0,
-        },
+        rated(Swarm::empty(pubkey.clone()).with(SACRED_QUEENS, 10)),
         StatusCode::OK
     );
 
@@ -184,7 +226,14 @@ async fn airdrop_and_swarm() {
 
 #[actix_web::test]
 async fn hatch() {
-    let (app, db) = init_app_and_db!(post_hatchery);
+    let (app, db) = init_app_and_db!(
+        get_swarm,
+        post_hatchery,
+        get_hatch_jobs,
+        post_hatch_claim,
+        get_challenge,
+        post_login
+    );
 
     let keypair = generate_keypair();
     let pubkey = get_pubkey(&keypair);
@@ -198,14 +247,7 @@ async fn hatch() {
     db_insert!(
         db,
         SWARMS_COLL_NAME,
-        Swarm {
-            berserkers: 0,
-            pubkey: pubkey.clone(),
-            sacred_queens: 0,
-            queens: 0,
-            guardians: 0,
-            eggs: 10000,
-        }
+        Swarm::empty(pubkey.clone()).with(EGGS, 10000)
     );
 
     // try to hatch more eggs than in account - should fail
@@ -230,22 +272,111 @@ async fn hatch() {
         StatusCode::OK
     );
 
+    // the eggs left the swarm and incubate in a job
+    wrap_test!(
+        "/swarm/".to_string() + &pubkey,
+        rated(Swarm::empty(pubkey.clone())),
+        StatusCode::OK
+    );
+    // the hatch queue is only shown to a session of its owner
+    wrap_test!(
+        "/hatchery/jobs/".to_string() + &pubkey,
+        StatusCode::UNAUTHORIZED
+    );
+    let other_token = login(&app, &generate_keypair()).await;
+    let req = TestRequest::get()
+        .uri(&("/hatchery/jobs/".to_string() + &pubkey))
+        .insert_header(("Authorization", format!("Bearer {}", other_token)))
+        .to_request();
+    assert_eq!(
+        StatusCode::UNAUTHORIZED,
+        call_service(&app, req).await.status()
+    );
+    let token = login(&app, &keypair).await;
+    let hatch_jobs = || {
+        TestRequest::get()
+            .uri(&("/hatchery/jobs/".to_string() + &pubkey))
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request()
+    };
+    let jobs: Vec<incubation::HatchJob> =
+        read_body_json(call_service(&app, hatch_jobs()).await).await;
+    assert_eq!(1, jobs.len());
+    assert_eq!(10000, jobs[0].eggs);
+    assert_eq!(
+        jobs[0].started_at + 10000 * incubation::HATCH_SECS_PER_EGG,
+        jobs[0].completes_at
+    );
+    assert!(jobs[0].hatched.is_none());
+    let claim = incubation::HatchClaim {
+        pubkey: pubkey.clone(),
+        job: jobs[0].id.clone(),
+    };
+
+    // claim before the job completed - should fail
+    wrap_test!(
+        "/hatchery/claim".to_string(),
+        incubation::HatchClaim {
+            pubkey: pubkey.clone(),
+            job: claim.job.clone(),
+        },
+        Empty {},
+        StatusCode::FORBIDDEN
+    );
+
+    // complete the job, listing rolls the units
+    db.collection::<incubation::HatchJob>(incubation::HATCH_JOBS_COLL_NAME)
+        .update_one(
+            doc! { "id": &jobs[0].id },
+            doc! { "$set": { "completes_at": 0 } },
+            None,
+        )
+        .await
+        .unwrap();
+    let jobs: Vec<incubation::HatchJob> =
+        read_body_json(call_service(&app, hatch_jobs()).await).await;
+    let rolled = jobs[0].hatched.clone().expect("completed jobs are rolled");
+
+    // claim the job
+    let body = serde_json::to_string(&claim).unwrap();
+    let signature = bs58::encode(keypair.sign(body.as_bytes())).into_string();
+    let req = TestRequest::post()
+        .uri("/hatchery/claim")
+        .set_json(&claim)
+        .insert_header(("ed25519-singature", signature))
+        .to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(StatusCode::OK, response.status());
+    let hatched: Swarm = read_body_json(response).await;
+    assert_eq!(rolled, hatched);
+
     let swarm = match db_search::<Swarm>(pubkey.clone(), db.clone()).await {
         Ok(s) => s,
         Err(_) => panic!("Failed to get swarm {}", pubkey),
     };
 
-    if swarm.eggs > 0
-        || swarm.queens > 120
-        || swarm.guardians > 1100
-        || swarm.berserkers < 8800
-        || swarm.queens + swarm.guardians + swarm.berserkers != 10000
+    if swarm.get(EGGS) > 0
+        || swarm.get(QUEENS) > 120
+        || swarm.get(GUARDIANS) > 1100
+        || swarm.get(BERSERKERS) < 8800
+        || swarm.get(QUEENS) + swarm.get(GUARDIANS) + swarm.get(BERSERKERS) != 10000
     {
         panic!(
             "Swarm does not have correct ammount of tokens: \n{}",
             serde_json::to_string(&swarm).unwrap()
         );
     }
+
+    // jobs are claimed once
+    wrap_test!(
+        "/hatchery/claim".to_string(),
+        claim,
+        Empty {},
+        StatusCode::NOT_FOUND
+    );
+    let jobs: Vec<incubation::HatchJob> =
+        read_body_json(call_service(&app, hatch_jobs()).await).await;
+    assert!(jobs.is_empty());
 }
 
 #[actix_web::test]
@@ -270,14 +401,11 @@ async fn sacred_hive() {
     db_insert!(
         db,
         SWARMS_COLL_NAME,
-        Swarm {
-            berserkers: 300,
-            pubkey: pubkey.clone(),
-            sacred_queens: 200,
-            queens: 50,
-            guardians: 0,
-            eggs: 100,
-        }
+        Swarm::empty(pubkey.clone())
+            .with(BERSERKERS, 300)
+            .with(SACRED_QUEENS, 200)
+            .with(QUEENS, 50)
+            .with(EGGS, 100)
     );
     db_insert!(
         db,
@@ -350,14 +478,12 @@ async fn sacred_hive() {
     // get swarm data after staking and unstaking
     wrap_test!(
         "/swarm/".to_string() + &pubkey.clone(),
-        Swarm {
-            pubkey: pubkey.clone(),
-            berserkers: 300,
-            guardians: 0,
-            sacred_queens: 0,
-            queens: 50,
-            eggs: 100,
-        },
+        rated(
+            Swarm::empty(pubkey.clone())
+                .with(BERSERKERS, 300)
+                .with(QUEENS, 50)
+                .with(EGGS, 100)
+        ),
         StatusCode::OK
     );
 
@@ -431,62 +557,49 @@ async fn hive() {
     db_insert!(
         db,
         SWARMS_COLL_NAME,
-        Swarm {
-            berserkers: 0,
-            pubkey: pubkey.clone(),
-            sacred_queens: 0,
-            queens: 5,
-            guardians: 170,
-            eggs: 0,
-        }
+        Swarm::empty(pubkey.clone())
+            .with(QUEENS, 5)
+            .with(GUARDIANS, 170)
     );
 
     // add some existing tokens to the sacred_hive of this swarm
     db_insert!(
         db,
         HIVE_COLL_NAME,
-        Hive {
-            pubkey: pubkey.clone(),
-            guardians: 150,
-            queens: 20,
-            eggs: 100,
-        }
+        Hive::empty(pubkey.clone())
+            .with(GUARDIANS, 150)
+            .with(QUEENS, 20)
+            .with(EGGS, 100)
     );
 
     // stake tokens - should succeed
     wrap_test!(
         "/hive/stake".to_string(),
-        Hive {
-            pubkey: pubkey.clone(),
-            queens: 5,
-            guardians: 70,
-            eggs: 0,
-        },
+        HiveRequest::from(
+            &Hive::empty(pubkey.clone())
+                .with(QUEENS, 5)
+                .with(GUARDIANS, 70)
+        ),
         Empty {},
         StatusCode::OK
     );
 
-    // get staked data
+    // get staked data, only estimated for the public
     wrap_test!(
         "/hive/get/".to_string() + &pubkey.clone(),
-        Hive {
-            pubkey: pubkey.clone(),
-            guardians: 220,
-            queens: 25,
-            eggs: 100,
-        },
+        scouting::HiveEstimate::from(
+            &Hive::empty(pubkey.clone())
+                .with(GUARDIANS, 220)
+                .with(QUEENS, 25)
+                .with(EGGS, 100)
+        ),
         StatusCode::OK
     );
 
     // unstake tokens - should succeed
     wrap_test!(
         "/hive/unstake".to_string(),
-        Hive {
-            pubkey: pubkey.clone(),
-            guardians: 0,
-            queens: 0,
-            eggs: 100,
-        },
+        HiveRequest::from(&Hive::empty(pubkey.clone()).with(EGGS, 100)),
         Empty {},
         StatusCode::OK
     );
@@ -494,26 +607,22 @@ async fn hive() {
     // get swarm data after staking and unstaking
     wrap_test!(
         "/swarm/".to_string() + &pubkey.clone(),
-        Swarm {
-            pubkey: pubkey.clone(),
-            berserkers: 0,
-            guardians: 100,
-            sacred_queens: 0,
-            queens: 0,
-            eggs: 100,
-        },
+        rated(
+            Swarm::empty(pubkey.clone())
+                .with(GUARDIANS, 100)
+                .with(EGGS, 100)
+        ),
         StatusCode::OK
     );
 
     // try to stake more tokens than in swarm - should fail
     wrap_test!(
         "/hive/stake".to_string(),
-        Hive {
-            pubkey: pubkey.clone(),
-            guardians: 2500,
-            queens: 500,
-            eggs: 0,
-        },
+        HiveRequest::from(
+            &Hive::empty(pubkey.clone())
+                .with(GUARDIANS, 2500)
+                .with(QUEENS, 500)
+        ),
         Empty {},
         StatusCode::FORBIDDEN
     );
@@ -521,15 +630,36 @@ async fn hive() {
     // try to unstake more tokens than in hive - should fail
     wrap_test!(
         "/hive/unstake".to_string(),
-        Hive {
-            pubkey: pubkey.clone(),
-            guardians: 0,
-            queens: 800,
-            eggs: 2000,
-        },
+        HiveRequest::from(
+            &Hive::empty(pubkey.clone())
+                .with(QUEENS, 800)
+                .with(EGGS, 2000)
+        ),
         Empty {},
         StatusCode::FORBIDDEN
     );
+
+    // units missing from the registry can not be staked
+    wrap_test!(
+        "/hive/stake".to_string(),
+        HiveRequest::from(&Hive::empty(pubkey.clone()).with("drones", 1)),
+        Empty {},
+        StatusCode::CONFLICT
+    );
+
+    // the signature covers the exact JSON the client sends
+    let body = format!(
+        r#"{{"pubkey":"{}","guardians":1,"queens":0,"eggs":0}}"#,
+        pubkey
+    );
+    let signature = bs58::encode(keypair.sign(body.as_bytes())).into_string();
+    let req = TestRequest::post()
+        .uri("/hive/stake")
+        .insert_header(("content-type", "application/json"))
+        .insert_header(("ed25519-singature", signature))
+        .set_payload(body)
+        .to_request();
+    assert_eq!(StatusCode::OK, call_service(&app, req).await.status());
 }
 
 #[actix_web::test]
@@ -539,12 +669,12 @@ async fn hive_list() {
 
     let mut hives: Vec<Hive> = Vec::new();
     for i in 0..50 {
-        hives.push(Hive {
-            pubkey: get_pubkey(&generate_keypair()),
-            guardians: 100,
-            queens: 10,
-            eggs: 5000 + i,
-        });
+        hives.push(
+            Hive::empty(get_pubkey(&generate_keypair()))
+                .with(GUARDIANS, 100)
+                .with(QUEENS, 10)
+                .with(EGGS, 5000 + i),
+        );
     }
 
     coll.delete_many(doc! { "eggs": { "$gte": 5000} }, None)
@@ -554,13 +684,13 @@ async fn hive_list() {
 
     // get top ten hives
     let mut top_ten: Vec<Hive> = hives.clone().drain(40..).collect();
-    top_ten.sort_by(|a, b| b.eggs.cmp(&a.eggs));
+    top_ten.sort_by_key(|h| std::cmp::Reverse(h.get(EGGS)));
     TestData {
         method: TestMethod::Get,
         uri: "/hive/list/top".to_string(),
         keypair: &generate_keypair(),
         req: Empty {},
-        res: top_ten,
+        res: hive_estimates(&top_ten),
         status: StatusCode::OK,
     }
     .run(&app)
@@ -573,13 +703,13 @@ async fn hive_list() {
         .collect::<Vec<Hive>>()
         .drain(..10)
         .collect();
-    neighbours.sort_by(|a, b| b.eggs.cmp(&a.eggs));
+    neighbours.sort_by_key(|h| std::cmp::Reverse(h.get(EGGS)));
     TestData {
         method: TestMethod::Get,
         uri: "/hive/list/neigh/5015".to_string(),
         keypair: &generate_keypair(),
         req: Empty {},
-        res: neighbours,
+        res: hive_estimates(&neighbours),
         status: StatusCode::OK,
     }
     .run(&app)
@@ -602,25 +732,16 @@ async fn attack_errors() {
     db_insert!(
         db,
         SWARMS_COLL_NAME,
-        Swarm {
-            pubkey: attacker_pubkey.clone(),
-            berserkers: 900,
-            eggs: 0,
-            queens: 0,
-            sacred_queens: 0,
-            guardians: 0,
-        }
+        Swarm::empty(attacker_pubkey.clone()).with(BERSERKERS, 900)
     );
 
     db_insert!(
         db,
         HIVE_COLL_NAME,
-        Hive {
-            pubkey: defender_pubkey.clone(),
-            queens: 10,
-            guardians: 90,
-            eggs: 100,
-        }
+        Hive::empty(defender_pubkey.clone())
+            .with(QUEENS, 10)
+            .with(GUARDIANS, 90)
+            .with(EGGS, 100)
     );
 
     // test attack with an invalid swarm pubkey - should fail
@@ -630,6 +751,7 @@ async fn attack_errors() {
             swarm_pubkey: "thisIsABadString".to_string(),
             hive_pubkey: defender_pubkey.clone(),
             berserkers: 100,
+            mercenaries: None,
         },
         Empty {},
         StatusCode::UNAUTHORIZED
@@ -642,11 +764,31 @@ async fn attack_errors() {
             swarm_pubkey: attacker_pubkey.clone(),
             hive_pubkey: "thisIsABadString".to_string(),
             berserkers: 100,
+            mercenaries: None,
         },
         Empty {},
         StatusCode::BAD_REQUEST
     );
 
+    // try to attack a hive without eggs - should fail
+    let empty_pubkey = get_pubkey(&generate_keypair());
+    db_insert!(
+        db,
+        HIVE_COLL_NAME,
+        Hive::empty(empty_pubkey.clone()).with(GUARDIANS, 1)
+    );
+    wrap_test!(
+        "/hive/attack".to_string(),
+        Attack {
+            swarm_pubkey: attacker_pubkey.clone(),
+            hive_pubkey: empty_pubkey,
+            berserkers: 100,
+            mercenaries: None,
+        },
+        Empty {},
+        StatusCode::NOT_FOUND
+    );
+
     // try to attack without having enough berserkers - should fail
     wrap_test!(
         "/hive/attack".to_string(),
@@ -654,6 +796,7 @@ async fn attack_errors() {
             swarm_pubkey: attacker_pubkey,
             hive_pubkey: defender_pubkey.clone(),
             berserkers: 99999,
+            mercenaries: None,
         },
         Empty {},
         StatusCode::FORBIDDEN
@@ -670,6 +813,7 @@ async fn attack_errors() {
             swarm_pubkey: random_pubkey,
             hive_pubkey: defender_pubkey.clone(),
             berserkers: 100,
+            mercenaries: None,
         },
         Empty {},
         StatusCode::FORBIDDEN
@@ -729,25 +873,16 @@ where
     db_insert!(
         db,
         SWARMS_COLL_NAME,
-        Swarm {
-            pubkey: attacker_pubkey.clone(),
-            berserkers: 900,
-            eggs: 0,
-            queens: 0,
-            sacred_queens: 0,
-            guardians: 0,
-        }
+        Swarm::empty(attacker_pubkey.clone()).with(BERSERKERS, 900)
     );
 
     db_insert!(
         db,
         HIVE_COLL_NAME,
-        Hive {
-            pubkey: defender_pubkey.clone(),
-            queens: 10,
-            guardians: 90,
-            eggs: 100,
-        }
+        Hive::empty(defender_pubkey.clone())
+            .with(QUEENS, 10)
+            .with(GUARDIANS, 90)
+            .with(EGGS, 100)
     );
 
     perform_test!(
@@ -758,18 +893,23 @@ where
             swarm_pubkey: attacker_pubkey.clone(),
             hive_pubkey: defender_pubkey.clone(),
             berserkers: 900,
+            mercenaries: None,
         },
         Empty {},
         StatusCode::OK
     );
+    let march = land_march(&db, &attacker_pubkey).await;
+    assert!(march::resolve(&march.id, &default_world().await)
+        .await
+        .is_ok());
 
     let swarm_eggs = match db_search::<Swarm>(attacker_pubkey.clone(), db.clone()).await {
-        Ok(s) => s.eggs,
+        Ok(s) => s.get(EGGS),
         Err(_) => panic!("Failed to get swarm {}", attacker_pubkey),
     };
 
     let hive_eggs = match db_search::<Hive>(defender_pubkey.clone(), db.clone()).await {
-        Ok(h) => h.eggs,
+        Ok(h) => h.get(EGGS),
         Err(_) => panic!("Failed to get hive {}", defender_pubkey),
     };
 
@@ -777,107 +917,2769 @@ where
 }
 
 #[actix_web::test]
-async fn unauthorized_requests() {
+async fn marches() {
     let (app, db) = init_app_and_db!(
-        get_hive,
-        stake_hive,
-        stake_sacred_hive,
-        unstake_hive,
-        unstake_sacred_hive,
-        post_attack
+        post_attack,
+        post_recall,
+        post_help_call,
+        post_reinforce,
+        get_marches,
+        get_help_calls,
+        get_leaderboard,
+        get_challenge,
+        post_login
     );
+    let world = default_world().await;
+    let attacker_keypair = generate_keypair();
+    let attacker_pubkey = get_pubkey(&attacker_keypair);
+    let defender_keypair = generate_keypair();
+    let defender_pubkey = get_pubkey(&defender_keypair);
+    let helper_keypair = generate_keypair();
+    let helper_pubkey = get_pubkey(&helper_keypair);
 
-    let real_keypair = generate_keypair();
-    let real_pubkey = get_pubkey(&real_keypair);
-    let fake_keypair = generate_keypair();
-    let fake_pubkey = get_pubkey(&fake_keypair);
-
-    // create new_swarm that will be used for testing purposes
     db_insert!(
         db,
         SWARMS_COLL_NAME,
-        Swarm {
-            berserkers: 0,
-            pubkey: real_pubkey.clone(),
-            sacred_queens: 0,
-            queens: 5,
-            guardians: 170,
-            eggs: 0,
-        }
+        Swarm::empty(attacker_pubkey.clone()).with(BERSERKERS, 900)
     );
-
-    // add some existing tokens to the hive of this swarm
     db_insert!(
         db,
-        HIVE_COLL_NAME,
-        Hive {
-            pubkey: real_pubkey.clone(),
-            guardians: 150,
-            queens: 20,
-            eggs: 100,
-        }
+        SWARMS_COLL_NAME,
+        Swarm::empty(helper_pubkey.clone()).with(GUARDIANS, 1000)
     );
-
-    // add some existing tokens to the sacred_hive of this swarm
     db_insert!(
         db,
-        SACRED_HIVE_COLL_NAME,
-        SacredHive {
-            pubkey: real_pubkey.clone(),
-            sacred_queens: 0,
-            eggs: 0,
-        }
+        HIVE_COLL_NAME,
+        Hive::empty(defender_pubkey.clone())
+            .with(QUEENS, 10)
+            .with(GUARDIANS, 90)
+            .with(EGGS, 100)
     );
+    let attack = || Attack {
+        swarm_pubkey: attacker_pubkey.clone(),
+        hive_pubkey: defender_pubkey.clone(),
+        berserkers: 900,
+        mercenaries: None,
+    };
+    let swarm = |pubkey: &String| db_search::<Swarm>(pubkey.clone(), db.clone());
+    let notified = |pubkey: &String| {
+        let db = db.clone();
+        let pubkey = pubkey.clone();
+        async move { notifications::recent(&pubkey, &db).await.unwrap() }
+    };
 
-    // unauthorized sacred hive stake post
+    // launch a march, the defender sees it coming
     perform_test!(
         &app,
-        &fake_keypair,
-        "/sacred_hive/stake".to_string(),
-        SacredHive {
-            pubkey: real_pubkey.clone(),
-            sacred_queens: 50,
-            eggs: 100,
+        &attacker_keypair,
+        "/hive/attack".to_string(),
+        attack(),
+        Empty {},
+        StatusCode::OK
+    );
+    assert_eq!(
+        0,
+        swarm(&attacker_pubkey).await.ok().unwrap().get(BERSERKERS)
+    );
+    let req = TestRequest::get()
+        .uri(&("/march/list/".to_string() + &defender_pubkey))
+        .to_request();
+    let marches: Vec<march::March> = read_body_json(call_service(&app, req).await).await;
+    assert_eq!(1, marches.len());
+    assert_eq!(
+        marches[0].launched_at + march::MARCH_SECS,
+        marches[0].arrives_at
+    );
+    let march_id = marches[0].id.clone();
+    assert!(matches!(
+        notified(&defender_pubkey).await[0].event,
+        notifications::Event::MarchLaunched { .. }
+    ));
+    let reinforce = || march::Reinforce {
+        pubkey: helper_pubkey.clone(),
+        march: march_id.clone(),
+        guardians: 1000,
+    };
+
+    // reinforcements need a call for help, which only the defender can make
+    perform_test!(
+        &app,
+        &helper_keypair,
+        "/march/reinforce".to_string(),
+        reinforce(),
+        Empty {},
+        StatusCode::NOT_FOUND
+    );
+    perform_test!(
+        &app,
+        &attacker_keypair,
+        "/march/help".to_string(),
+        march::HelpCall {
+            pubkey: attacker_pubkey.clone(),
+            march: march_id.clone(),
         },
         Empty {},
-        StatusCode::UNAUTHORIZED
+        StatusCode::NOT_FOUND
+    );
+    perform_test!(
+        &app,
+        &defender_keypair,
+        "/march/help".to_string(),
+        march::HelpCall {
+            pubkey: defender_pubkey.clone(),
+            march: march_id.clone(),
+        },
+        Empty {},
+        StatusCode::OK
+    );
+    let req = TestRequest::get().uri("/march/help/list").to_request();
+    let calls: Vec<march::March> = read_body_json(call_service(&app, req).await).await;
+    assert!(calls.iter().any(|m| m.id == march_id));
+    perform_test!(
+        &app,
+        &helper_keypair,
+        "/march/reinforce".to_string(),
+        reinforce(),
+        Empty {},
+        StatusCode::OK
     );
+    assert_eq!(0, swarm(&helper_pubkey).await.ok().unwrap().get(GUARDIANS));
 
-    // unauthorized hive stake post
+    // only the defender and the helper see the reinforcement
+    let seen_guardians = |token: Option<String>| {
+        let mut req = TestRequest::get().uri(&("/march/list/".to_string() + &defender_pubkey));
+        if let Some(token) = token {
+            req = req.insert_header(("Authorization", format!("Bearer {}", token)));
+        }
+        let app = &app;
+        async move {
+            let marches: Vec<march::March> =
+                read_body_json(call_service(app, req.to_request()).await).await;
+            marches[0]
+                .reinforcements
+                .iter()
+                .map(|r| r.guardians)
+                .sum::<i64>()
+        }
+    };
+    for (keypair, guardians) in [
+        (&defender_keypair, 1000),
+        (&helper_keypair, 1000),
+        (&attacker_keypair, 0),
+    ] {
+        let token = login(&app, keypair).await;
+        assert_eq!(guardians, seen_guardians(Some(token)).await);
+    }
+    assert_eq!(0, seen_guardians(None).await);
+
+    // recalling brings everybody home
     perform_test!(
         &app,
-        &fake_keypair,
-        "/hive/stake".to_string(),
-        Hive {
-            pubkey: real_pubkey.clone(),
-            queens: 5,
-            guardians: 70,
-            eggs: 0,
+        &attacker_keypair,
+        "/march/recall".to_string(),
+        march::Recall {
+            pubkey: attacker_pubkey.clone(),
+            march: march_id.clone(),
         },
         Empty {},
-        StatusCode::UNAUTHORIZED
+        StatusCode::OK
+    );
+    assert_eq!(
+        900,
+        swarm(&attacker_pubkey).await.ok().unwrap().get(BERSERKERS)
+    );
+    assert_eq!(
+        1000,
+        swarm(&helper_pubkey).await.ok().unwrap().get(GUARDIANS)
+    );
+    perform_test!(
+        &app,
+        &attacker_keypair,
+        "/march/list/".to_string() + &defender_pubkey,
+        Vec::<march::March>::new(),
+        StatusCode::OK
     );
 
-    // unauthorized attack
+    // the reinforced hive holds against the next march
     perform_test!(
         &app,
-        &fake_keypair,
+        &attacker_keypair,
         "/hive/attack".to_string(),
-        Attack {
-            swarm_pubkey: real_pubkey.clone(),
-            hive_pubkey: fake_pubkey.clone(),
-            berserkers: 900,
-        },
+        attack(),
         Empty {},
-        StatusCode::UNAUTHORIZED
+        StatusCode::OK
+    );
+    let march_id = land_march(&db, &attacker_pubkey).await.id;
+    db.collection::<march::March>(march::MARCHES_COLL_NAME)
+        .update_one(
+            doc! { "id": &march_id },
+            doc! {
+                "$set": {
+                    "help_called": true,
+                    "arrives_at": chrono::Utc::now().timestamp() + 60,
+                }
+            },
+            None,
+        )
+        .await
+        .unwrap();
+    perform_test!(
+        &app,
+        &helper_keypair,
+        "/march/reinforce".to_string(),
+        reinforce(),
+        Empty {},
+        StatusCode::OK
+    );
+    let march = land_march(&db, &attacker_pubkey).await;
+    let resolved = match march::resolve(&march.id, &world).await {
+        Ok(march) => march,
+        Err(_) => panic!("march {} was not resolved", march.id),
+    };
+    assert_eq!(Some(false), resolved.won);
+    assert_eq!(
+        0,
+        swarm(&attacker_pubkey).await.ok().unwrap().get(BERSERKERS)
     );
+    assert_eq!(
+        1000,
+        swarm(&helper_pubkey).await.ok().unwrap().get(GUARDIANS)
+    );
+    for pubkey in [&attacker_pubkey, &defender_pubkey, &helper_pubkey] {
+        assert!(matches!(
+            notified(pubkey).await[0].event,
+            notifications::Event::MarchResolved { won: false, .. }
+        ));
+    }
+
+    // the equally rated defender held and took 16 points from the attacker
+    assert_eq!(-16, resolved.rating_change);
+    let rating = |pubkey: &String| ratings::db_search_rating(pubkey.clone(), db.clone());
+    let attacker = rating(&attacker_pubkey).await.ok().unwrap();
+    let defender = rating(&defender_pubkey).await.ok().unwrap();
+    assert_eq!((1184, 1), (attacker.attack, attacker.attacks));
+    assert_eq!((1216, 1), (defender.defense, defender.defenses));
+    let req = TestRequest::get().uri("/ratings/top/defense").to_request();
+    let leaders: Vec<ratings::Rating> = read_body_json(call_service(&app, req).await).await;
+    assert!(leaders.windows(2).all(|w| w[0].defense >= w[1].defense));
+    assert!(leaders.iter().all(|rating| rating.defenses > 0));
+
+    // attacks on the own hive move no points
+    let mut session = world.client.start_session(None).await.unwrap();
+    session.start_transaction(None).await.unwrap();
+    let change = ratings::rate_with_session(
+        &attacker_pubkey,
+        &attacker_pubkey,
+        true,
+        32,
+        &db,
+        &mut session,
+    )
+    .await
+    .unwrap();
+    commit_with_retry(&mut session).await.unwrap();
+    assert_eq!(0, change);
+    assert_eq!(attacker, rating(&attacker_pubkey).await.ok().unwrap());
+
+    // every move was written to the ledger by the rules
+    for pubkey in [&attacker_pubkey, &helper_pubkey] {
+        let rebuilt = ledger::rebuild(&db, Some(pubkey)).await.unwrap();
+        let live = ledger::live_state(&db, Some(pubkey)).await.unwrap();
+        assert!(ledger::diff(&rebuilt, &live).is_empty());
+    }
+    let entries: Vec<ledger::LedgerEntry> = db
+        .collection::<ledger::LedgerEntry>(ledger::LEDGER_COLL_NAME)
+        .find(doc! { "changes.after.pubkey": &attacker_pubkey }, None)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert!(entries
+        .iter()
+        .all(|e| economy::check_entry(e, &GameConfig::default()).is_none()));
 }
 
 #[actix_web::test]
-#[ignore = "run with '-- --ignored' to clean the DB"]
-async fn clean_db() {
-    let uri = std::env::var("MONGODB_URI").unwrap();
-    let db = Client::with_uri_str(uri)
+async fn matchmaking() {
+    let (app, db) = init_app_and_db!(get_hive_targets, post_attack);
+    let world = default_world().await;
+    let attacker_keypair = generate_keypair();
+    let attacker_pubkey = get_pubkey(&attacker_keypair);
+    let pubkeys: Vec<String> = (0..3).map(|_| get_pubkey(&generate_keypair())).collect();
+
+    // the attacker has a power of 100
+    db_insert!(
+        db,
+        SWARMS_COLL_NAME,
+        Swarm::empty(attacker_pubkey.clone()).with(BERSERKERS, 100)
+    );
+    db_insert!(
+        db,
+        SACRED_HIVE_COLL_NAME,
+        SacredHive::from_balances(attacker_pubkey.clone(), &Balances::new())
+    );
+    db_insert!(
+        db,
+        HIVE_COLL_NAME,
+        Hive::from_balances(attacker_pubkey.clone(), &Balances::new())
+    );
+    // defended with 90, 9 and 9000
+    for (pubkey, guardians) in pubkeys.iter().zip([10, 1, 1000]) {
+        db_insert!(
+            db,
+            HIVE_COLL_NAME,
+            Hive::from_balances(pubkey.clone(), &Balances::new())
+                .with(GUARDIANS, guardians)
+                .with(EGGS, 1_000_000)
+        );
+    }
+
+    // hives written outside of the game have their power stored on start
+    for pubkey in pubkeys.iter().chain([&attacker_pubkey]) {
+        matchmaking::store_powers(&world, Some(pubkey))
+            .await
+            .unwrap();
+    }
+
+    // only the hive within the band is suggested
+    let req = TestRequest::get()
+        .uri(&("/hive/targets/".to_string() + &attacker_pubkey))
+        .to_request();
+    let targets: Vec<scouting::HiveEstimate> = read_body_json(call_service(&app, req).await).await;
+    let suggested = |pubkey: &String| targets.iter().any(|hive| &hive.pubkey == pubkey);
+    assert!(suggested(&pubkeys[0]));
+    assert!(!suggested(&pubkeys[1]));
+    assert!(!suggested(&pubkeys[2]));
+
+    // attacks below the band are looted less
+    perform_test!(
+        &app,
+        &attacker_keypair,
+        "/hive/attack".to_string(),
+        Attack {
+            swarm_pubkey: attacker_pubkey.clone(),
+            hive_pubkey: pubkeys[1].clone(),
+            berserkers: 50,
+            mercenaries: None,
+        },
+        Empty {},
+        StatusCode::OK
+    );
+    // the berserkers on the road no longer count
+    let stored = db
+        .collection::<matchmaking::Power>(matchmaking::POWERS_COLL_NAME)
+        .find_one(doc! { "pubkey": &attacker_pubkey }, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(50, stored.power);
+    let march = land_march(&db, &attacker_pubkey).await;
+    assert_eq!(OUT_OF_BAND_LOOT_PERCENT, march.loot_percent);
+    let resolved = march::resolve(&march.id, &world).await.ok().unwrap();
+    assert_eq!(Some(true), resolved.won);
+    assert_eq!(250_000, resolved.loot);
+
+    // or refused when the band is enforced
+    let mut strict = world.clone();
+    strict.config.matchmaking.enforce_band = true;
+    let attack = |hive_pubkey: &String| Attack {
+        swarm_pubkey: attacker_pubkey.clone(),
+        hive_pubkey: hive_pubkey.clone(),
+        berserkers: 10,
+        mercenaries: None,
+    };
+    assert!(matches!(
+        march::launch(attack(&pubkeys[1]), &strict).await,
+        Err(AttackError::OutOfBand)
+    ));
+    let march = march::launch(attack(&pubkeys[2]), &strict)
+        .await
+        .ok()
+        .unwrap();
+    assert_eq!(100, march.loot_percent);
+}
+
+#[actix_web::test]
+async fn scouting() {
+    let (app, db) = init_app_and_db!(get_hive, post_scout, get_challenge, post_login, get_intel);
+    let scout_keypair = generate_keypair();
+    let scout_pubkey = get_pubkey(&scout_keypair);
+    let target_pubkey = get_pubkey(&generate_keypair());
+    let config = GameConfig::default();
+    db_insert!(
+        db,
+        SWARMS_COLL_NAME,
+        Swarm::empty(scout_pubkey.clone())
+            .with(BERSERKERS, config.scout_berserkers)
+            .with(EGGS, config.scout_eggs)
+    );
+    let target = Hive::empty(target_pubkey.clone())
+        .with(GUARDIANS, 100)
+        .with(QUEENS, 5);
+    db_insert!(db, HIVE_COLL_NAME, target.clone());
+    let scout = |pay_with| scouting::Scout {
+        pubkey: scout_pubkey.clone(),
+        hive_pubkey: target_pubkey.clone(),
+        pay_with,
+    };
+    let post_scout_request = |pay_with| {
+        let body = scout(pay_with);
+        let message = serde_json::to_string(&body).unwrap();
+        let signature = bs58::encode(scout_keypair.sign(message.as_bytes())).into_string();
+        TestRequest::post()
+            .uri("/scout")
+            .set_json(&body)
+            .insert_header(("ed25519-singature", signature))
+            .to_request()
+    };
+
+    // the public only sees estimates
+    perform_test!(
+        &app,
+        &scout_keypair,
+        "/hive/get/".to_string() + &target_pubkey,
+        scouting::HiveEstimate {
+            pubkey: target_pubkey.clone(),
+            guardians: scouting::Estimate { min: 64, max: 127 },
+            queens: scouting::Estimate { min: 4, max: 7 },
+            eggs: 0,
+        },
+        StatusCode::OK
+    );
+
+    // scouting with berserkers reveals the exact hive
+    let response = call_service(&app, post_scout_request(scouting::ScoutPayment::Berserkers)).await;
+    assert_eq!(StatusCode::OK, response.status());
+    let intel: scouting::Intel = read_body_json(response).await;
+    assert_eq!(target, intel.hive);
+    assert_eq!(intel.scouted_at + config.intel_secs, intel.expires_at);
+    let notified = notifications::recent(&target_pubkey, &db).await.unwrap();
+    assert_eq!(
+        notifications::Event::Scouted {
+            scout: scout_pubkey.clone(),
+            expires_at: intel.expires_at,
+        },
+        notified[0].event
+    );
+
+    // the berserkers are spent, eggs can pay for the next scout
+    let response = call_service(&app, post_scout_request(scouting::ScoutPayment::Berserkers)).await;
+    assert_eq!(StatusCode::FORBIDDEN, response.status());
+    let response = call_service(&app, post_scout_request(scouting::ScoutPayment::Eggs)).await;
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(
+        Swarm::empty(scout_pubkey.clone()),
+        db_search::<Swarm>(scout_pubkey.clone(), db.clone())
+            .await
+            .ok()
+            .unwrap()
+    );
+    perform_test!(
+        &app,
+        &scout_keypair,
+        "/scout".to_string(),
+        scouting::Scout {
+            hive_pubkey: get_pubkey(&generate_keypair()),
+            ..scout(scouting::ScoutPayment::Eggs)
+        },
+        Empty {},
+        StatusCode::NOT_FOUND
+    );
+
+    // the scout reads the intel with a session, it is stored once per target
+    let token = login(&app, &scout_keypair).await;
+    let req = TestRequest::get()
+        .uri("/scout/intel")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let intel: Vec<scouting::Intel> = read_body_json(call_service(&app, req).await).await;
+    assert_eq!(1, intel.len());
+    assert_eq!(target, intel[0].hive);
+
+    // both scouts burned their cost by the rules
+    let entries: Vec<ledger::LedgerEntry> = db
+        .collection::<ledger::LedgerEntry>(ledger::LEDGER_COLL_NAME)
+        .find(doc! { "pubkey": &scout_pubkey, "operation": "scout" }, None)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(2, entries.len());
+    assert!(entries
+        .iter()
+        .all(|e| economy::check_entry(e, &config).is_none()));
+}
+
+#[actix_web::test]
+async fn fortifications() {
+    let (app, db) = init_app_and_db!(
+        post_fortify,
+        get_fortifications,
+        post_hatchery,
+        get_hatch_jobs,
+        post_attack,
+        get_challenge,
+        post_login
+    );
+    let world = default_world().await;
+    let keypair = generate_keypair();
+    let pubkey = get_pubkey(&keypair);
+    let attacker_keypair = generate_keypair();
+    let attacker_pubkey = get_pubkey(&attacker_keypair);
+    db_insert!(
+        db,
+        SWARMS_COLL_NAME,
+        Swarm::empty(pubkey.clone()).with(EGGS, 3000)
+    );
+    db_insert!(
+        db,
+        HIVE_COLL_NAME,
+        Hive::empty(pubkey.clone())
+            .with(GUARDIANS, 10)
+            .with(EGGS, 1000)
+    );
+    db_insert!(
+        db,
+        SWARMS_COLL_NAME,
+        Swarm::empty(attacker_pubkey.clone()).with(BERSERKERS, 320)
+    );
+    let fortify = |building, level| fortifications::Fortify {
+        pubkey: pubkey.clone(),
+        building,
+        level,
+    };
+    let built = |walls, nursery, vault| fortifications::Fortifications {
+        pubkey: pubkey.clone(),
+        walls,
+        nursery,
+        vault,
+    };
+    macro_rules! wrap_test {
+        ($($param:expr),*) => {
+            perform_test!(&app, &keypair $(,$param)*);
+        };
+    }
+
+    // buildings are raised one level at a time
+    wrap_test!(
+        "/hive/fortifications/".to_string() + &pubkey,
+        built(0, 0, 0),
+        StatusCode::OK
+    );
+    wrap_test!(
+        "/hive/fortify".to_string(),
+        fortify(fortifications::Building::Walls, 2),
+        Empty {},
+        StatusCode::CONFLICT
+    );
+    wrap_test!(
+        "/hive/fortify".to_string(),
+        fortify(fortifications::Building::Walls, 1),
+        built(1, 0, 0),
+        StatusCode::OK
+    );
+    wrap_test!(
+        "/hive/fortify".to_string(),
+        fortify(fortifications::Building::Walls, 1),
+        Empty {},
+        StatusCode::CONFLICT
+    );
+    wrap_test!(
+        "/hive/fortify".to_string(),
+        fortify(fortifications::Building::Walls, 2),
+        built(2, 0, 0),
+        StatusCode::OK
+    );
+    wrap_test!(
+        "/hive/fortify".to_string(),
+        fortify(fortifications::Building::Vault, 1),
+        built(2, 0, 1),
+        StatusCode::OK
+    );
+    // the 3000 eggs are spent
+    wrap_test!(
+        "/hive/fortify".to_string(),
+        fortify(fortifications::Building::Nursery, 1),
+        Empty {},
+        StatusCode::FORBIDDEN
+    );
+    let entries: Vec<ledger::LedgerEntry> = db
+        .collection::<ledger::LedgerEntry>(ledger::LEDGER_COLL_NAME)
+        .find(doc! { "pubkey": &pubkey, "operation": "fortify" }, None)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(3, entries.len());
+    assert!(entries
+        .iter()
+        .all(|e| economy::check_entry(e, &GameConfig::default()).is_none()));
+
+    // the nursery shortens the incubation to 80%
+    db.collection::<fortifications::Fortifications>(fortifications::FORTIFICATIONS_COLL_NAME)
+        .update_one(
+            doc! { "pubkey": &pubkey },
+            doc! { "$set": { "nursery": 1 } },
+            None,
+        )
+        .await
+        .unwrap();
+    db.collection::<Swarm>(SWARMS_COLL_NAME)
+        .update_one(
+            doc! { "pubkey": &pubkey },
+            doc! { "$set": { "eggs": 10 } },
+            None,
+        )
+        .await
+        .unwrap();
+    wrap_test!(
+        "/hatchery".to_string(),
+        HatchRequest {
+            pubkey: pubkey.clone(),
+            eggs: 10,
+        },
+        Empty {},
+        StatusCode::OK
+    );
+    let req = TestRequest::get()
+        .uri(&("/hatchery/jobs/".to_string() + &pubkey))
+        .insert_header((
+            "Authorization",
+            format!("Bearer {}", login(&app, &keypair).await),
+        ))
+        .to_request();
+    let jobs: Vec<incubation::HatchJob> = read_body_json(call_service(&app, req).await).await;
+    assert_eq!(24, jobs[0].completes_at - jobs[0].started_at);
+
+    // 10 guardians defend with 90 to 100, the walls make it 135 to 150
+    let launch = |berserkers| {
+        let attack = Attack {
+            swarm_pubkey: attacker_pubkey.clone(),
+            hive_pubkey: pubkey.clone(),
+            berserkers,
+            mercenaries: None,
+        };
+        let message = serde_json::to_string(&attack).unwrap();
+        let signature = bs58::encode(attacker_keypair.sign(message.as_bytes())).into_string();
+        TestRequest::post()
+            .uri("/hive/attack")
+            .set_json(&attack)
+            .insert_header(("ed25519-singature", signature))
+            .to_request()
+    };
+    let response = call_service(&app, launch(120)).await;
+    assert_eq!(StatusCode::OK, response.status());
+    let march = land_march(&db, &attacker_pubkey).await;
+    let resolved = march::resolve(&march.id, &world).await.ok().unwrap();
+    assert_eq!(Some(false), resolved.won);
+
+    // the vault keeps 10% of the eggs out of the loot
+    let response = call_service(&app, launch(200)).await;
+    assert_eq!(StatusCode::OK, response.status());
+    let march = land_march(&db, &attacker_pubkey).await;
+    let resolved = march::resolve(&march.id, &world).await.ok().unwrap();
+    assert_eq!(Some(true), resolved.won);
+    assert_eq!(900, resolved.loot);
+    let hive = db_search::<Hive>(pubkey.clone(), db.clone())
+        .await
+        .ok()
+        .unwrap();
+    assert_eq!(100, hive.get(EGGS));
+
+    // the berserkers that were not needed come home as veterans
+    assert!((50..=65).contains(&resolved.survivors));
+    let attacker = db_search::<Swarm>(attacker_pubkey.clone(), db.clone())
+        .await
+        .ok()
+        .unwrap();
+    assert_eq!(0, attacker.get(BERSERKERS));
+    assert_eq!(vec![resolved.survivors], attacker.veterans);
+    let rebuilt = ledger::rebuild(&db, Some(&attacker_pubkey)).await.unwrap();
+    let live = ledger::live_state(&db, Some(&attacker_pubkey))
+        .await
+        .unwrap();
+    assert!(ledger::diff(&rebuilt, &live).is_empty());
+}
+
+#[actix_web::test]
+async fn bounties() {
+    let (app, db) = init_app_and_db!(post_bounty, get_bounties, post_attack);
+    let world = default_world().await;
+    let sponsor_keypair = generate_keypair();
+    let sponsor = get_pubkey(&sponsor_keypair);
+    let target_keypair = generate_keypair();
+    let target = get_pubkey(&target_keypair);
+    let hunter_keypair = generate_keypair();
+    let hunter = get_pubkey(&hunter_keypair);
+    db_insert!(
+        db,
+        SWARMS_COLL_NAME,
+        Swarm::empty(sponsor.clone()).with(EGGS, 500)
+    );
+    db_insert!(db, HIVE_COLL_NAME, Hive::empty(sponsor.clone()));
+    db_insert!(
+        db,
+        HIVE_COLL_NAME,
+        Hive::empty(target.clone())
+            .with(GUARDIANS, 10)
+            .with(EGGS, 100)
+    );
+    db_insert!(
+        db,
+        SWARMS_COLL_NAME,
+        Swarm::empty(hunter.clone()).with(BERSERKERS, 200)
+    );
+    let place = |hive_pubkey: &str, eggs| bounties::PlaceBounty {
+        pubkey: sponsor.clone(),
+        hive_pubkey: hive_pubkey.to_string(),
+        eggs,
+    };
+    let signed = |request: bounties::PlaceBounty| {
+        let message = serde_json::to_string(&request).unwrap();
+        let signature = bs58::encode(sponsor_keypair.sign(message.as_bytes())).into_string();
+        TestRequest::post()
+            .uri("/bounty")
+            .set_json(&request)
+            .insert_header(("ed25519-singature", signature))
+            .to_request()
+    };
+    macro_rules! wrap_test {
+        ($($param:expr),*) => {
+            perform_test!(&app, &sponsor_keypair $(,$param)*);
+        };
+    }
+
+    // bounties need another existing hive and enough eggs
+    wrap_test!(
+        "/bounty".to_string(),
+        place(&sponsor, 100),
+        Empty {},
+        StatusCode::CONFLICT
+    );
+    wrap_test!(
+        "/bounty".to_string(),
+        place("CF4eGJXudCwqnEgTyhQ6LwsrkqE3myoEoen6rYzVFwif", 100),
+        Empty {},
+        StatusCode::NOT_FOUND
+    );
+    wrap_test!(
+        "/bounty".to_string(),
+        place(&target, 600),
+        Empty {},
+        StatusCode::FORBIDDEN
+    );
+
+    // the eggs move from the swarm into the bounties
+    let response = call_service(&app, signed(place(&target, 300))).await;
+    assert_eq!(StatusCode::OK, response.status());
+    let big: bounties::Bounty = read_body_json(response).await;
+    assert_eq!(bounties::BountyStatus::Open, big.status);
+    assert_eq!(
+        GameConfig::default().bounty_secs,
+        big.expires_at - big.placed_at
+    );
+    let response = call_service(&app, signed(place(&target, 100))).await;
+    assert_eq!(StatusCode::OK, response.status());
+    let small: bounties::Bounty = read_body_json(response).await;
+    let swarm = db_search::<Swarm>(sponsor.clone(), db.clone())
+        .await
+        .ok()
+        .unwrap();
+    assert_eq!(100, swarm.get(EGGS));
+
+    // open bounties are listed by eggs or by expiry
+    let collection = db.collection::<bounties::Bounty>(bounties::BOUNTIES_COLL_NAME);
+    collection
+        .update_one(
+            doc! { "id": &small.id },
+            doc! { "$inc": { "expires_at": -60 } },
+            None,
+        )
+        .await
+        .unwrap();
+    let listed = |sort: &str| {
+        let req = TestRequest::get()
+            .uri(&("/bounty/list/".to_string() + sort))
+            .to_request();
+        let app = &app;
+        let sponsor = sponsor.clone();
+        async move {
+            let bounties: Vec<bounties::Bounty> =
+                read_body_json(call_service(app, req).await).await;
+            bounties
+                .into_iter()
+                .filter(|bounty| bounty.sponsor == sponsor)
+                .map(|bounty| bounty.eggs)
+                .collect::<Vec<i64>>()
+        }
+    };
+    assert_eq!(vec![300, 100], listed("eggs").await);
+    assert_eq!(vec![100, 300], listed("expiry").await);
+
+    // an expired bounty goes back to the sponsor
+    collection
+        .update_one(
+            doc! { "id": &small.id },
+            doc! { "$set": { "expires_at": 0 } },
+            None,
+        )
+        .await
+        .unwrap();
+    assert!(bounties::refund_expired(&world).await.unwrap() >= 1);
+    let swarm = db_search::<Swarm>(sponsor.clone(), db.clone())
+        .await
+        .ok()
+        .unwrap();
+    assert_eq!(200, swarm.get(EGGS));
+    assert_eq!(vec![300], listed("eggs").await);
+
+    // the owner of the hive can neither raid it nor claim its bounties
+    let attack = |keypair: &Keypair| {
+        let attack = Attack {
+            swarm_pubkey: get_pubkey(keypair),
+            hive_pubkey: target.clone(),
+            berserkers: 200,
+            mercenaries: None,
+        };
+        let message = serde_json::to_string(&attack).unwrap();
+        let signature = bs58::encode(keypair.sign(message.as_bytes())).into_string();
+        TestRequest::post()
+            .uri("/hive/attack")
+            .set_json(&attack)
+            .insert_header(("ed25519-singature", signature))
+            .to_request()
+    };
+    let response = call_service(&app, attack(&target_keypair)).await;
+    assert_eq!(StatusCode::CONFLICT, response.status());
+    let mut session = world.client.start_session(None).await.unwrap();
+    session.start_transaction(None).await.unwrap();
+    let (paid, changes) = bounties::claim_with_session(&target, &target, &db, &mut session)
+        .await
+        .unwrap();
+    assert_eq!((0, 0), (paid, changes.len()));
+    session.abort_transaction().await.unwrap();
+    assert_eq!(vec![300], listed("eggs").await);
+
+    // the next won raid on the hive takes the open bounty with the loot
+    let response = call_service(&app, attack(&hunter_keypair)).await;
+    assert_eq!(StatusCode::OK, response.status());
+    let march = land_march(&db, &hunter).await;
+    let resolved = march::resolve(&march.id, &world).await.ok().unwrap();
+    assert_eq!(Some(true), resolved.won);
+    assert_eq!(300, resolved.bounty);
+    let swarm = db_search::<Swarm>(hunter.clone(), db.clone())
+        .await
+        .ok()
+        .unwrap();
+    assert_eq!(resolved.loot + 300, swarm.get(EGGS));
+    let claimed = collection
+        .find_one(doc! { "id": &big.id }, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(bounties::BountyStatus::Claimed, claimed.status);
+    assert_eq!(Some(hunter.clone()), claimed.claimed_by);
+    assert!(listed("eggs").await.is_empty());
+
+    let entries: Vec<ledger::LedgerEntry> = db
+        .collection::<ledger::LedgerEntry>(ledger::LEDGER_COLL_NAME)
+        .find(doc! { "pubkey": { "$in": [&sponsor, &hunter] } }, None)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(5, entries.len());
+    assert!(entries
+        .iter()
+        .all(|e| economy::check_entry(e, &GameConfig::default()).is_none()));
+    let live = ledger::live_state(&db, Some(&sponsor)).await.unwrap();
+    assert_eq!(
+        Some(0),
+        live.get(&(bounties::BOUNTIES_COLL_NAME.to_string(), sponsor.clone()))
+            .map(|escrowed| escrowed.get(EGGS))
+    );
+}
+
+#[actix_web::test]
+async fn mercenaries() {
+    let (app, db) = init_app_and_db!(
+        post_mercenaries,
+        post_withdraw_mercenaries,
+        get_mercenary_offers,
+        post_attack,
+        post_recall
+    );
+    let world = default_world().await;
+    let owner_keypair = generate_keypair();
+    let owner = get_pubkey(&owner_keypair);
+    let attacker_keypair = generate_keypair();
+    let attacker = get_pubkey(&attacker_keypair);
+    let target = get_pubkey(&generate_keypair());
+    db_insert!(
+        db,
+        SWARMS_COLL_NAME,
+        Swarm::empty(owner.clone()).with(BERSERKERS, 100)
+    );
+    db_insert!(
+        db,
+        SWARMS_COLL_NAME,
+        Swarm::empty(attacker.clone())
+            .with(BERSERKERS, 100)
+            .with(EGGS, 50)
+    );
+    db_insert!(
+        db,
+        HIVE_COLL_NAME,
+        Hive::empty(target.clone())
+            .with(GUARDIANS, 10)
+            .with(EGGS, 1000)
+    );
+    let list = |berserkers, price, loot_percent| mercenaries::ListMercenaries {
+        pubkey: owner.clone(),
+        berserkers,
+        price,
+        loot_percent,
+    };
+    fn signed<T: Serialize>(uri: &str, keypair: &Keypair, body: &T) -> Request {
+        let message = serde_json::to_string(body).unwrap();
+        let signature = bs58::encode(keypair.sign(message.as_bytes())).into_string();
+        TestRequest::post()
+            .uri(uri)
+            .set_json(body)
+            .insert_header(("ed25519-singature", signature))
+            .to_request()
+    }
+    let attack = |berserkers, offer: &str| Attack {
+        swarm_pubkey: attacker.clone(),
+        hive_pubkey: target.clone(),
+        berserkers,
+        mercenaries: Some(offer.to_string()),
+    };
+    let swarm = |pubkey: &String| db_search::<Swarm>(pubkey.clone(), db.clone());
+    macro_rules! wrap_test {
+        ($($param:expr),*) => {
+            perform_test!(&app, &owner_keypair $(,$param)*);
+        };
+    }
+
+    // offers need valid terms and listed berserkers leave the swarm
+    wrap_test!(
+        "/mercenaries/list".to_string(),
+        list(50, 10, 150),
+        Empty {},
+        StatusCode::BAD_REQUEST
+    );
+    wrap_test!(
+        "/mercenaries/list".to_string(),
+        list(200, 10, 20),
+        Empty {},
+        StatusCode::FORBIDDEN
+    );
+    let response = call_service(
+        &app,
+        signed("/mercenaries/list", &owner_keypair, &list(100, 50, 20)),
+    )
+    .await;
+    assert_eq!(StatusCode::OK, response.status());
+    let offer: mercenaries::Offer = read_body_json(response).await;
+    assert_eq!(mercenaries::OfferStatus::Listed, offer.status);
+    assert_eq!(0, swarm(&owner).await.ok().unwrap().get(BERSERKERS));
+    let req = TestRequest::get().uri("/mercenaries/offers").to_request();
+    let offers: Vec<mercenaries::Offer> = read_body_json(call_service(&app, req).await).await;
+    assert!(offers.contains(&offer));
+    wrap_test!(
+        "/mercenaries/withdraw".to_string(),
+        mercenaries::WithdrawMercenaries {
+            pubkey: owner.clone(),
+            offer: "unknown".to_string(),
+        },
+        Empty {},
+        StatusCode::NOT_FOUND
+    );
+
+    // the attacker hires them for one march and the march holds the price
+    let response = call_service(
+        &app,
+        signed("/hive/attack", &attacker_keypair, &attack(100, &offer.id)),
+    )
+    .await;
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(0, swarm(&attacker).await.ok().unwrap().get(EGGS));
+    let response = call_service(
+        &app,
+        signed("/hive/attack", &attacker_keypair, &attack(1, &offer.id)),
+    )
+    .await;
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+
+    // 200 berserkers beat 10 guardians, the survivors are split by head
+    // count and the owner gets the price and 20% of the loot
+    let march = land_march(&db, &attacker).await;
+    let resolved = march::resolve(&march.id, &world).await.ok().unwrap();
+    assert_eq!(Some(true), resolved.won);
+    assert_eq!(1000, resolved.loot);
+    let hire = resolved.mercenaries.clone().unwrap();
+    assert_eq!(250, hire.paid);
+    assert!((50..=55).contains(&hire.survivors));
+    assert_eq!(
+        hire.survivors,
+        swarm(&owner).await.ok().unwrap().get(BERSERKERS)
+    );
+    assert_eq!(250, swarm(&owner).await.ok().unwrap().get(EGGS));
+    let attacker_swarm = swarm(&attacker).await.ok().unwrap();
+    assert_eq!(800, attacker_swarm.get(EGGS));
+    assert_eq!(resolved.survivors, attacker_swarm.all_berserkers());
+
+    // recalled mercenaries come home unpaid
+    let response = call_service(
+        &app,
+        signed("/mercenaries/list", &owner_keypair, &list(10, 0, 0)),
+    )
+    .await;
+    let offer: mercenaries::Offer = read_body_json(response).await;
+    let response = call_service(
+        &app,
+        signed("/hive/attack", &attacker_keypair, &attack(10, &offer.id)),
+    )
+    .await;
+    assert_eq!(StatusCode::OK, response.status());
+    let march = db
+        .collection::<march::March>(march::MARCHES_COLL_NAME)
+        .find_one(
+            doc! { "swarm_pubkey": &attacker, "status": "marching" },
+            None,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    let response = call_service(
+        &app,
+        signed(
+            "/march/recall",
+            &attacker_keypair,
+            &march::Recall {
+                pubkey: attacker.clone(),
+                march: march.id.clone(),
+            },
+        ),
+    )
+    .await;
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(
+        hire.survivors,
+        swarm(&owner).await.ok().unwrap().get(BERSERKERS)
+    );
+
+    let entries: Vec<ledger::LedgerEntry> = db
+        .collection::<ledger::LedgerEntry>(ledger::LEDGER_COLL_NAME)
+        .find(doc! { "pubkey": { "$in": [&owner, &attacker] } }, None)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(6, entries.len());
+    assert!(entries
+        .iter()
+        .all(|e| economy::check_entry(e, &GameConfig::default()).is_none()));
+    let live = ledger::live_state(&db, Some(&owner)).await.unwrap();
+    for collection in [march::MARCHES_COLL_NAME, mercenaries::MERCENARIES_COLL_NAME] {
+        assert_eq!(
+            Some(Swarm::empty(owner.clone())),
+            live.get(&(collection.to_string(), owner.clone())).cloned()
+        );
+    }
+}
+
+#[actix_web::test]
+async fn stake_locks() {
+    let (app, db) = init_app_and_db!(
+        get_airdrop,
+        stake_sacred_hive,
+        unstake_sacred_hive,
+        lock_sacred_hive,
+        unlock_sacred_hive,
+        get_sacred_hive_locks,
+        trigger_sacred_hive
+    );
+    let keypair = generate_keypair();
+    let pubkey = get_pubkey(&keypair);
+    let week = locks::default_locks()[0];
+    let lock = |sacred_queens, lock_secs| locks::LockStake {
+        pubkey: pubkey.clone(),
+        sacred_queens,
+        lock_secs,
+    };
+    let sacred_hive = |sacred_queens| SacredHive {
+        pubkey: pubkey.clone(),
+        sacred_queens,
+        eggs: 0,
+    };
+    let unlock = |lock: &str| locks::Unlock {
+        pubkey: pubkey.clone(),
+        lock: lock.to_string(),
+    };
+    macro_rules! wrap_test {
+        ($($param:expr),*) => {
+            perform_test!(&app, &keypair $(,$param)*);
+        };
+    }
+
+    // only the lock periods of the world are offered and locked sacred
+    // queens leave the swarm
+    wrap_test!("/airdrop/".to_string() + &pubkey, StatusCode::OK);
+    wrap_test!(
+        "/sacred_hive/lock".to_string(),
+        lock(4, week.secs + 1),
+        Empty {},
+        StatusCode::CONFLICT
+    );
+    wrap_test!(
+        "/sacred_hive/lock".to_string(),
+        lock(11, week.secs),
+        Empty {},
+        StatusCode::FORBIDDEN
+    );
+    let message = serde_json::to_string(&lock(4, week.secs)).unwrap();
+    let signature = bs58::encode(keypair.sign(message.as_bytes())).into_string();
+    let req = TestRequest::post()
+        .uri("/sacred_hive/lock")
+        .set_json(lock(4, week.secs))
+        .insert_header(("ed25519-singature", signature))
+        .to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(StatusCode::OK, response.status());
+    let position: locks::StakeLock = read_body_json(response).await;
+    assert_eq!(week.yield_percent, position.yield_percent);
+    assert_eq!(week.secs, position.unlocks_at - position.locked_at);
+    let swarm = db_search::<Swarm>(pubkey.clone(), db.clone()).await;
+    assert_eq!(6, swarm.ok().unwrap().get(SACRED_QUEENS));
+    let req = TestRequest::get()
+        .uri(&("/sacred_hive/locks/".to_string() + &pubkey))
+        .to_request();
+    let positions: Vec<locks::StakeLock> = read_body_json(call_service(&app, req).await).await;
+    assert_eq!(vec![position.clone()], positions);
+
+    // staked sacred queens can still be unstaked, locked ones can not
+    wrap_test!(
+        "/sacred_hive/stake".to_string(),
+        sacred_hive(2),
+        Empty {},
+        StatusCode::OK
+    );
+    wrap_test!(
+        "/sacred_hive/unstake".to_string(),
+        sacred_hive(3),
+        Empty {},
+        StatusCode::LOCKED
+    );
+    wrap_test!(
+        "/sacred_hive/unstake".to_string(),
+        sacred_hive(2),
+        Empty {},
+        StatusCode::OK
+    );
+    wrap_test!(
+        "/sacred_hive/unlock".to_string(),
+        unlock(&position.id),
+        Empty {},
+        StatusCode::LOCKED
+    );
+
+    // locked sacred queens lay eggs with the yield of their lock
+    wrap_test!(
+        "/sacred_hive/trigger/".to_string() + &pubkey,
+        StatusCode::OK
+    );
+    let hive = db_search::<SacredHive>(pubkey.clone(), db.clone()).await;
+    assert_eq!(
+        4 * EGGS_PER_SACRED_QUEEN * week.yield_percent / 100,
+        hive.ok().unwrap().eggs
+    );
+
+    // once the lock ran out the sacred queens go back to the swarm
+    db.collection::<locks::StakeLock>(locks::STAKE_LOCKS_COLL_NAME)
+        .update_one(
+            doc! { "id": &position.id },
+            doc! { "$set": { "unlocks_at": 0 } },
+            None,
+        )
+        .await
+        .unwrap();
+    wrap_test!(
+        "/sacred_hive/unlock".to_string(),
+        unlock(&position.id),
+        Empty {},
+        StatusCode::OK
+    );
+    wrap_test!(
+        "/sacred_hive/unlock".to_string(),
+        unlock(&position.id),
+        Empty {},
+        StatusCode::FORBIDDEN
+    );
+    let swarm = db_search::<Swarm>(pubkey.clone(), db.clone()).await;
+    assert_eq!(10, swarm.ok().unwrap().get(SACRED_QUEENS));
+
+    let entries: Vec<ledger::LedgerEntry> = db
+        .collection::<ledger::LedgerEntry>(ledger::LEDGER_COLL_NAME)
+        .find(doc! { "pubkey": &pubkey }, None)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(6, entries.len());
+    assert!(entries
+        .iter()
+        .all(|e| economy::check_entry(e, &GameConfig::default()).is_none()));
+    let rebuilt = ledger::rebuild(&db, Some(&pubkey)).await.unwrap();
+    let live = ledger::live_state(&db, Some(&pubkey)).await.unwrap();
+    assert!(ledger::diff(&rebuilt, &live).is_empty());
+}
+
+#[actix_web::test]
+async fn unauthorized_requests() {
+    let (app, db) = init_app_and_db!(
+        get_hive,
+        stake_hive,
+        stake_sacred_hive,
+        unstake_hive,
+        unstake_sacred_hive,
+        post_attack
+    );
+
+    let real_keypair = generate_keypair();
+    let real_pubkey = get_pubkey(&real_keypair);
+    let fake_keypair = generate_keypair();
+    let fake_pubkey = get_pubkey(&fake_keypair);
+
+    // create new_swarm that will be used for testing purposes
+    db_insert!(
+        db,
+        SWARMS_COLL_NAME,
+        Swarm::empty(real_pubkey.clone())
+            .with(QUEENS, 5)
+            .with(GUARDIANS, 170)
+    );
+
+    // add some existing tokens to the hive of this swarm
+    db_insert!(
+        db,
+        HIVE_COLL_NAME,
+        Hive::empty(real_pubkey.clone())
+            .with(GUARDIANS, 150)
+            .with(QUEENS, 20)
+            .with(EGGS, 100)
+    );
+
+    // add some existing tokens to the sacred_hive of this swarm
+    db_insert!(
+        db,
+        SACRED_HIVE_COLL_NAME,
+        SacredHive {
+            pubkey: real_pubkey.clone(),
+            sacred_queens: 0,
+            eggs: 0,
+        }
+    );
+
+    // unauthorized sacred hive stake post
+    perform_test!(
+        &app,
+        &fake_keypair,
+        "/sacred_hive/stake".to_string(),
+        SacredHive {
+            pubkey: real_pubkey.clone(),
+            sacred_queens: 50,
+            eggs: 100,
+        },
+        Empty {},
+        StatusCode::UNAUTHORIZED
+    );
+
+    // unauthorized hive stake post
+    perform_test!(
+        &app,
+        &fake_keypair,
+        "/hive/stake".to_string(),
+        HiveRequest::from(
+            &Hive::empty(real_pubkey.clone())
+                .with(QUEENS, 5)
+                .with(GUARDIANS, 70)
+        ),
+        Empty {},
+        StatusCode::UNAUTHORIZED
+    );
+
+    // unauthorized attack
+    perform_test!(
+        &app,
+        &fake_keypair,
+        "/hive/attack".to_string(),
+        Attack {
+            swarm_pubkey: real_pubkey.clone(),
+            hive_pubkey: fake_pubkey.clone(),
+            berserkers: 900,
+            mercenaries: None,
+        },
+        Empty {},
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+/// Logs in with a signed challenge and returns the session token.
+async fn login<S, B>(app: &S, keypair: &Keypair) -> String
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let pubkey = get_pubkey(keypair);
+    let req = TestRequest::get()
+        .uri(&("/auth/challenge/".to_string() + &pubkey))
+        .to_request();
+    let challenge: Challenge = read_body_json(call_service(app, req).await).await;
+    let login = Login {
+        pubkey,
+        challenge: challenge.challenge,
+    };
+    let signature =
+        bs58::encode(keypair.sign(serde_json::to_string(&login).unwrap().as_bytes())).into_string();
+    let req = TestRequest::post()
+        .uri("/auth/login")
+        .set_json(&login)
+        .insert_header(("ed25519-singature", signature))
+        .to_request();
+    let session: Session = read_body_json(call_service(app, req).await).await;
+    session.token
+}
+
+async fn perform_delegated_test<S, B, Req: Serialize>(
+    app: &S,
+    delegate: &Keypair,
+    uri: &str,
+    req: Req,
+    status: StatusCode,
+) where
+    S: Service<Request, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let message_string = serde_json::to_string(&req).unwrap();
+    let signature: Signature = delegate.sign(message_string.as_bytes());
+    let req = TestRequest::post()
+        .uri(uri)
+        .set_json(&req)
+        .insert_header(("ed25519-singature", bs58::encode(signature).into_string()))
+        .insert_header(("ed25519-delegate", get_pubkey(delegate)))
+        .to_request();
+    println!("testing... delegated Post {}", uri);
+    let response = timeout(Duration::from_secs(2), call_service(&app, req))
+        .await
+        .unwrap();
+    assert_eq!(status, response.status());
+}
+
+#[actix_web::test]
+async fn delegated_session_keys() {
+    let (app, db) = init_app_and_db!(
+        post_delegation,
+        post_revoke_delegation,
+        post_hatchery,
+        post_attack,
+        stake_hive
+    );
+    let keypair = generate_keypair();
+    let pubkey = get_pubkey(&keypair);
+    let delegate_keypair = generate_keypair();
+    let delegate_pubkey = get_pubkey(&delegate_keypair);
+    macro_rules! wrap_test {
+        ($($param:expr),*) => {
+            perform_test!(&app, &keypair $(,$param)*);
+        };
+    }
+
+    db_insert!(
+        db,
+        SWARMS_COLL_NAME,
+        Swarm::empty(pubkey.clone())
+            .with(BERSERKERS, 2000)
+            .with(GUARDIANS, 10)
+            .with(EGGS, 1000)
+    );
+    db_insert!(db, HIVE_COLL_NAME, Hive::empty(pubkey.clone()));
+
+    let delegation = Delegation {
+        pubkey: pubkey.clone(),
+        delegate: delegate_pubkey.clone(),
+        expires_at: chrono::Utc::now().timestamp() + 3600,
+        actions: vec![Action::Hatch, Action::Attack],
+        max_berserkers: Some(1000),
+        max_eggs: None,
+        spent_berserkers: 0,
+        spent_eggs: 0,
+    };
+
+    // a delegate can not sign its own certificate - should fail
+    perform_delegated_test(
+        &app,
+        &delegate_keypair,
+        "/delegation/create",
+        delegation.clone(),
+        StatusCode::UNAUTHORIZED,
+    )
+    .await;
+
+    // an already expired certificate - should fail
+    wrap_test!(
+        "/delegation/create".to_string(),
+        Delegation {
+            expires_at: chrono::Utc::now().timestamp() - 1,
+            ..delegation.clone()
+        },
+        Empty {},
+        StatusCode::BAD_REQUEST
+    );
+
+    // delegate hatching and attacking - should succeed
+    wrap_test!(
+        "/delegation/create".to_string(),
+        delegation.clone(),
+        Empty {},
+        StatusCode::OK
+    );
+
+    // the same delegate key can only be registered once - should fail
+    wrap_test!(
+        "/delegation/create".to_string(),
+        delegation.clone(),
+        Empty {},
+        StatusCode::FORBIDDEN
+    );
+
+    // hatch with the delegate key - should succeed
+    let hatch_request = HatchRequest {
+        pubkey: pubkey.clone(),
+        eggs: 10,
+    };
+    perform_delegated_test(
+        &app,
+        &delegate_keypair,
+        "/hatchery",
+        hatch_request,
+        StatusCode::OK,
+    )
+    .await;
+
+    // stake is not in scope of the delegation - should fail
+    let stake_request = HiveRequest::from(&Hive::empty(pubkey.clone()).with(GUARDIANS, 10));
+    perform_delegated_test(
+        &app,
+        &delegate_keypair,
+        "/hive/stake",
+        stake_request,
+        StatusCode::UNAUTHORIZED,
+    )
+    .await;
+
+    // attack with more berserkers than delegated - should fail
+    let attack_request = Attack {
+        swarm_pubkey: pubkey.clone(),
+        hive_pubkey: get_pubkey(&generate_keypair()),
+        berserkers: 1001,
+        mercenaries: None,
+    };
+    perform_delegated_test(
+        &app,
+        &delegate_keypair,
+        "/hive/attack",
+        attack_request,
+        StatusCode::UNAUTHORIZED,
+    )
+    .await;
+
+    // attack a hive that does not exist - should fail and not count
+    let attack_request = Attack {
+        swarm_pubkey: pubkey.clone(),
+        hive_pubkey: get_pubkey(&generate_keypair()),
+        berserkers: 600,
+        mercenaries: None,
+    };
+    perform_delegated_test(
+        &app,
+        &delegate_keypair,
+        "/hive/attack",
+        attack_request,
+        StatusCode::NOT_FOUND,
+    )
+    .await;
+
+    let target_pubkey = get_pubkey(&generate_keypair());
+    db_insert!(
+        db,
+        HIVE_COLL_NAME,
+        Hive::empty(target_pubkey.clone())
+            .with(GUARDIANS, 10)
+            .with(EGGS, 100)
+    );
+
+    // attack within the caps - should succeed
+    let attack_request = Attack {
+        swarm_pubkey: pubkey.clone(),
+        hive_pubkey: target_pubkey.clone(),
+        berserkers: 600,
+        mercenaries: None,
+    };
+    perform_delegated_test(
+        &app,
+        &delegate_keypair,
+        "/hive/attack",
+        attack_request,
+        StatusCode::OK,
+    )
+    .await;
+
+    // the caps hold across requests - should fail
+    let attack_request = Attack {
+        swarm_pubkey: pubkey.clone(),
+        hive_pubkey: target_pubkey.clone(),
+        berserkers: 600,
+        mercenaries: None,
+    };
+    perform_delegated_test(
+        &app,
+        &delegate_keypair,
+        "/hive/attack",
+        attack_request,
+        StatusCode::UNAUTHORIZED,
+    )
+    .await;
+
+    let stored = db
+        .collection::<Delegation>(DELEGATIONS_COLL_NAME)
+        .find_one(doc! { "delegate": &delegate_pubkey }, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stored.spent_berserkers, 600);
+    assert_eq!(stored.spent_eggs, 0);
+
+    // revoke the delegation - should succeed
+    wrap_test!(
+        "/delegation/revoke".to_string(),
+        RevokeDelegation {
+            pubkey: pubkey.clone(),
+            delegate: delegate_pubkey.clone(),
+        },
+        Empty {},
+        StatusCode::OK
+    );
+
+    // hatch with a revoked delegate key - should fail
+    let hatch_request = HatchRequest {
+        pubkey: pubkey.clone(),
+        eggs: 10,
+    };
+    perform_delegated_test(
+        &app,
+        &delegate_keypair,
+        "/hatchery",
+        hatch_request,
+        StatusCode::UNAUTHORIZED,
+    )
+    .await;
+
+    // master key still works after revocation - should succeed
+    wrap_test!(
+        "/hatchery".to_string(),
+        HatchRequest {
+            pubkey: pubkey.clone(),
+            eggs: 10,
+        },
+        Empty {},
+        StatusCode::OK
+    );
+}
+
+#[actix_web::test]
+async fn delegated_mercenary_hire() {
+    let (app, db) = init_app_and_db!(post_delegation, post_attack);
+    let keypair = generate_keypair();
+    let pubkey = get_pubkey(&keypair);
+    let delegate_keypair = generate_keypair();
+    let delegate_pubkey = get_pubkey(&delegate_keypair);
+    let owner = get_pubkey(&generate_keypair());
+    let target = get_pubkey(&generate_keypair());
+    db_insert!(
+        db,
+        SWARMS_COLL_NAME,
+        Swarm::empty(pubkey.clone())
+            .with(BERSERKERS, 100)
+            .with(EGGS, 500)
+    );
+    db_insert!(
+        db,
+        HIVE_COLL_NAME,
+        Hive::empty(target.clone())
+            .with(GUARDIANS, 10)
+            .with(EGGS, 1000)
+    );
+    for (id, price) in [("expensive", 150), ("cheap", 50)] {
+        db_insert!(
+            db,
+            mercenaries::MERCENARIES_COLL_NAME,
+            mercenaries::Offer {
+                id: id.to_string(),
+                owner: owner.clone(),
+                berserkers: 10,
+                price,
+                loot_percent: 0,
+                listed_at: 0,
+                status: mercenaries::OfferStatus::Listed,
+                march: None,
+            }
+        );
+    }
+    perform_test!(
+        &app,
+        &keypair,
+        "/delegation/create".to_string(),
+        Delegation {
+            pubkey: pubkey.clone(),
+            delegate: delegate_pubkey.clone(),
+            expires_at: chrono::Utc::now().timestamp() + 3600,
+            actions: vec![Action::Attack],
+            max_berserkers: None,
+            max_eggs: Some(100),
+            spent_berserkers: 0,
+            spent_eggs: 0,
+        },
+        Empty {},
+        StatusCode::OK
+    );
+    let attack = |offer: &str| Attack {
+        swarm_pubkey: pubkey.clone(),
+        hive_pubkey: target.clone(),
+        berserkers: 10,
+        mercenaries: Some(offer.to_string()),
+    };
+
+    // hire priced above the delegated eggs - should fail
+    perform_delegated_test(
+        &app,
+        &delegate_keypair,
+        "/hive/attack",
+        attack("expensive"),
+        StatusCode::UNAUTHORIZED,
+    )
+    .await;
+
+    // hire within the delegated eggs - should succeed
+    perform_delegated_test(
+        &app,
+        &delegate_keypair,
+        "/hive/attack",
+        attack("cheap"),
+        StatusCode::OK,
+    )
+    .await;
+
+    let stored = db
+        .collection::<Delegation>(DELEGATIONS_COLL_NAME)
+        .find_one(doc! { "delegate": &delegate_pubkey }, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stored.spent_eggs, 50);
+}
+
+#[actix_web::test]
+async fn session_login() {
+    let (app, db) = init_app_and_db!(get_airdrop, get_challenge, post_login, get_account);
+    let keypair = generate_keypair();
+    let pubkey = get_pubkey(&keypair);
+    macro_rules! wrap_test {
+        ($($param:expr),*) => {
+            perform_test!(&app, &keypair $(,$param)*);
+        };
+    }
+
+    wrap_test!("/airdrop/".to_string() + &pubkey, StatusCode::OK);
+
+    // account without a session token - should fail
+    wrap_test!("/account".to_string(), StatusCode::UNAUTHORIZED);
+
+    // login with a challenge that was never issued - should fail
+    wrap_test!(
+        "/auth/login".to_string(),
+        Login {
+            pubkey: pubkey.clone(),
+            challenge: "thisIsABadString".to_string(),
+        },
+        Empty {},
+        StatusCode::UNAUTHORIZED
+    );
+
+    let req = TestRequest::get()
+        .uri(&("/auth/challenge/".to_string() + &pubkey))
+        .to_request();
+    let challenge: Challenge = read_body_json(call_service(&app, req).await).await;
+
+    // challenges are stored with a date the TTL index can expire
+    let stored = db
+        .collection::<mongodb::bson::Document>(CHALLENGES_COLL_NAME)
+        .find_one(doc! { "challenge": &challenge.challenge }, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        challenge.expires_at * 1000,
+        stored
+            .get_datetime("expires_at")
+            .unwrap()
+            .timestamp_millis()
+    );
+
+    // login with the challenge signed by another key - should fail
+    perform_test!(
+        &app,
+        &generate_keypair(),
+        "/auth/login".to_string(),
+        Login {
+            pubkey: pubkey.clone(),
+            challenge: challenge.challenge.clone(),
+        },
+        Empty {},
+        StatusCode::UNAUTHORIZED
+    );
+
+    // login with the signed challenge - should succeed
+    let login = Login {
+        pubkey: pubkey.clone(),
+        challenge: challenge.challenge.clone(),
+    };
+    let signature: Signature = keypair.sign(serde_json::to_string(&login).unwrap().as_bytes());
+    let req = TestRequest::post()
+        .uri("/auth/login")
+        .set_json(&login)
+        .insert_header(("ed25519-singature", bs58::encode(signature).into_string()))
+        .to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(StatusCode::OK, response.status());
+    let session: Session = read_body_json(response).await;
+
+    // challenges can only be used once - should fail
+    wrap_test!(
+        "/auth/login".to_string(),
+        login,
+        Empty {},
+        StatusCode::UNAUTHORIZED
+    );
+
+    // get the private account view with the session token
+    let req = TestRequest::get()
+        .uri("/account")
+        .insert_header(("Authorization", "Bearer ".to_string() + &session.token))
+        .to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(StatusCode::OK, response.status());
+    let account: Account = read_body_json(response).await;
+    assert_eq!(pubkey, account.swarm.pubkey);
+    assert_eq!(10, account.swarm.get(SACRED_QUEENS));
+    assert_eq!(ratings::Rating::new(pubkey.clone()), account.rating);
+
+    // tampered session token - should fail
+    let req = TestRequest::get()
+        .uri("/account")
+        .insert_header(("Authorization", "Bearer x".to_string() + &session.token))
+        .to_request();
+    assert_eq!(
+        StatusCode::UNAUTHORIZED,
+        call_service(&app, req).await.status()
+    );
+}
+
+#[actix_web::test]
+async fn metrics_and_health() {
+    let (app, db) = init_app_and_db!(get_airdrop, get_metrics, get_healthz, get_readyz);
+    let keypair = generate_keypair();
+    let pubkey = get_pubkey(&keypair);
+    macro_rules! wrap_test {
+        ($($param:expr),*) => {
+            perform_test!(&app, &keypair $(,$param)*);
+        };
+    }
+
+    // the test database runs as a replica set
+    let health = metrics::Health {
+        mongo: true,
+        transactions: true,
+    };
+    wrap_test!("/healthz".to_string(), health, StatusCode::OK);
+    // every world is checked for readiness
+    let health = std::collections::BTreeMap::from([(
+        DEFAULT_WORLD.to_string(),
+        metrics::Health {
+            mongo: true,
+            transactions: true,
+        },
+    )]);
+    wrap_test!("/readyz".to_string(), health, StatusCode::OK);
+
+    wrap_test!("/airdrop/".to_string() + &pubkey, StatusCode::OK);
+    // the supply gauges are set by the snapshot task, not by scrapes
+    metrics::set_token_supply(DEFAULT_WORLD, &economy::stats(&db).await.unwrap());
+
+    let req = TestRequest::get().uri("/metrics").to_request();
+    let response = call_service(&app, req).await;
+    assert_eq!(StatusCode::OK, response.status());
+    let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+    assert!(body.contains("airdrops_total"));
+    assert!(body
+        .contains("token_supply{collection=\"swarms\",token=\"sacred_queens\",world=\"default\"}"));
+    // the gauges cover everything the ledger is checked against
+    assert!(
+        body.contains("token_supply{collection=\"marches\",token=\"veterans\",world=\"default\"}")
+    );
+    assert!(body.contains(
+        "token_supply{collection=\"stakeLocks\",token=\"sacred_queens\",world=\"default\"}"
+    ));
+}
+
+#[actix_web::test]
+async fn request_ids() {
+    let app = init_service(
+        App::new()
+            .wrap_fn(logging::trace_request)
+            .route("/", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+    let request_id = |header: Option<String>| {
+        let mut req = TestRequest::get().uri("/");
+        if let Some(header) = header {
+            req = req.insert_header((logging::REQUEST_ID_HEADER, header));
+        }
+        let app = &app;
+        async move {
+            let response = call_service(app, req.to_request()).await;
+            response
+                .headers()
+                .get(logging::REQUEST_ID_HEADER)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        }
+    };
+
+    // the ID of a proxy is echoed
+    assert_eq!("proxy-1", request_id(Some("proxy-1".to_string())).await);
+    // over-long IDs are replaced
+    let long = "x".repeat(65);
+    let replaced = request_id(Some(long.clone())).await;
+    assert_ne!(long, replaced);
+    assert_eq!(32, replaced.len());
+    // missing IDs are generated, a new one for every request
+    let generated = request_id(None).await;
+    assert_eq!(32, generated.len());
+    assert!(generated.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_ne!(generated, request_id(None).await);
+}
+
+#[test]
+fn transaction_outcomes() {
+    let count = |outcome| {
+        metrics::TRANSACTIONS
+            .with_label_values(&["outcome_test", outcome])
+            .get()
+    };
+    metrics::observe_transaction::<(), StakeError>("outcome_test", &Ok(()));
+    metrics::observe_transaction::<(), StakeError>(
+        "outcome_test",
+        &Err(StakeError::NotEnoughTokens),
+    );
+    assert_eq!(1, count("committed"));
+    assert_eq!(1, count("rejected"));
+    assert_eq!(0, count("aborted"));
+}
+
+#[actix_web::test]
+async fn ledger_rebuild() {
+    let (app, db) = init_app_and_db!(
+        get_airdrop,
+        stake_sacred_hive,
+        unstake_sacred_hive,
+        trigger_sacred_hive
+    );
+    let keypair = generate_keypair();
+    let pubkey = get_pubkey(&keypair);
+    macro_rules! wrap_test {
+        ($($param:expr),*) => {
+            perform_test!(&app, &keypair $(,$param)*);
+        };
+    }
+
+    wrap_test!("/airdrop/".to_string() + &pubkey, StatusCode::OK);
+    wrap_test!(
+        "/sacred_hive/stake".to_string(),
+        SacredHive {
+            pubkey: pubkey.clone(),
+            sacred_queens: 5,
+            eggs: 0,
+        },
+        Empty {},
+        StatusCode::OK
+    );
+    wrap_test!(
+        "/sacred_hive/trigger/".to_string() + &pubkey,
+        StatusCode::OK
+    );
+    wrap_test!(
+        "/sacred_hive/unstake".to_string(),
+        SacredHive {
+            pubkey: pubkey.clone(),
+            sacred_queens: 0,
+            eggs: 300,
+        },
+        Empty {},
+        StatusCode::OK
+    );
+
+    // every mutation was written to the ledger
+    let entries = db
+        .collection::<ledger::LedgerEntry>(ledger::LEDGER_COLL_NAME)
+        .count_documents(doc! { "pubkey": &pubkey }, None)
+        .await
+        .unwrap();
+    assert_eq!(4, entries);
+
+    // replaying the ledger gives back the live state
+    let rebuilt = ledger::rebuild(&db, Some(&pubkey)).await.unwrap();
+    let live = ledger::live_state(&db, Some(&pubkey)).await.unwrap();
+    assert_eq!(3, rebuilt.len());
+    assert!(ledger::diff(&rebuilt, &live).is_empty());
+    assert_eq!(
+        Some(
+            &Swarm::empty(pubkey.clone())
+                .with(EGGS, 300)
+                .with(SACRED_QUEENS, 5)
+        ),
+        rebuilt.get(&(SWARMS_COLL_NAME.to_string(), pubkey.clone()))
+    );
+
+    // a balance changed outside of the ledger is detected
+    db.collection::<Swarm>(SWARMS_COLL_NAME)
+        .update_one(
+            doc! { "pubkey": &pubkey },
+            doc! { "$inc": { "eggs": 1 } },
+            None,
+        )
+        .await
+        .unwrap();
+    let live = ledger::live_state(&db, Some(&pubkey)).await.unwrap();
+    assert_eq!(1, ledger::diff(&rebuilt, &live).len());
+
+    // a target that holds game documents is not replaced without --force
+    assert!(ledger::write_state(&rebuilt, &db, false).await.is_err());
+
+    // documents without ledger history get a genesis entry, once
+    let legacy = get_pubkey(&generate_keypair());
+    db_insert!(
+        db,
+        SWARMS_COLL_NAME,
+        Swarm::empty(legacy.clone()).with(EGGS, 42)
+    );
+    ledger::backfill_genesis(&db, Some(&legacy)).await.unwrap();
+    ledger::backfill_genesis(&db, Some(&legacy)).await.unwrap();
+    let genesis = db
+        .collection::<ledger::LedgerEntry>(ledger::LEDGER_COLL_NAME)
+        .count_documents(doc! { "pubkey": &legacy, "operation": "genesis" }, None)
+        .await
+        .unwrap();
+    assert_eq!(1, genesis);
+    let rebuilt = ledger::rebuild(&db, Some(&legacy)).await.unwrap();
+    let live = ledger::live_state(&db, Some(&legacy)).await.unwrap();
+    assert!(ledger::diff(&rebuilt, &live).is_empty());
+}
+
+#[actix_web::test]
+async fn transparency_log() {
+    let (app, db) = init_app_and_db!(
+        get_airdrop,
+        stake_sacred_hive,
+        stake_hive,
+        get_transparency_head,
+        get_transparency_log
+    );
+    transparency::create_transparency_indexes(&db).await;
+    let keypair = generate_keypair();
+    let pubkey = get_pubkey(&keypair);
+    macro_rules! wrap_test {
+        ($($param:expr),*) => {
+            perform_test!(&app, &keypair $(,$param)*);
+        };
+    }
+
+    let req = TestRequest::get().uri("/transparency/head").to_request();
+    let start: transparency::LogHead = read_body_json(call_service(&app, req).await).await;
+
+    wrap_test!("/airdrop/".to_string() + &pubkey, StatusCode::OK);
+    wrap_test!(
+        "/sacred_hive/stake".to_string(),
+        SacredHive {
+            pubkey: pubkey.clone(),
+            sacred_queens: 5,
+            eggs: 0,
+        },
+        Empty {},
+        StatusCode::OK
+    );
+    // every signed request is logged with its outcome, even if the game
+    // rejects it
+    wrap_test!(
+        "/sacred_hive/stake".to_string(),
+        SacredHive {
+            pubkey: pubkey.clone(),
+            sacred_queens: 500,
+            eggs: 0,
+        },
+        Empty {},
+        StatusCode::FORBIDDEN
+    );
+    // hive stakes are logged without revealing the hive
+    wrap_test!(
+        "/hive/stake".to_string(),
+        HiveRequest::from(&Hive::empty(pubkey.clone()).with(GUARDIANS, 7)),
+        Empty {},
+        StatusCode::FORBIDDEN
+    );
+    // requests with a wrong signature are not
+    wrap_test!(
+        "/sacred_hive/stake".to_string(),
+        SacredHive {
+            pubkey: get_pubkey(&generate_keypair()),
+            sacred_queens: 1,
+            eggs: 0,
+        },
+        Empty {},
+        StatusCode::UNAUTHORIZED
+    );
+
+    let req = TestRequest::get()
+        .uri(&format!("/transparency/log/{}", start.seq + 1))
+        .to_request();
+    let records: Vec<transparency::LogRecord> = read_body_json(call_service(&app, req).await).await;
+    let own: Vec<&transparency::LogRecord> =
+        records.iter().filter(|r| r.pubkey == pubkey).collect();
+    assert_eq!(3, own.len());
+    assert_eq!("/sacred_hive/stake", own[0].route);
+    assert_eq!(
+        vec![Some(200), Some(403), Some(403)],
+        own.iter().map(|r| r.status).collect::<Vec<_>>()
+    );
+    assert_eq!("/hive/stake", own[2].route);
+    assert!(own[2].body.is_empty());
+    let stored = db
+        .collection::<transparency::LogRecord>(transparency::TRANSPARENCY_COLL_NAME)
+        .find_one(doc! { "seq": own[2].seq }, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(stored.body.contains("\"guardians\":7"));
+    assert_eq!(
+        own[2].body_sha256,
+        Some(transparency::body_digest(&stored.body))
+    );
+
+    // the page links to the head seen before and verifies
+    assert_eq!(start.hash, records[0].prev_hash);
+    let head = transparency::verify_chain(&records).unwrap();
+    let req = TestRequest::get().uri("/transparency/head").to_request();
+    let published: transparency::LogHead = read_body_json(call_service(&app, req).await).await;
+    assert!(published.seq >= head.seq);
+
+    // a rewritten body breaks the chain
+    let mut tampered = records.clone();
+    let index = tampered.iter().position(|r| r.pubkey == pubkey).unwrap();
+    tampered[index].body = tampered[index].body.replace('5', "6");
+    assert!(transparency::verify_chain(&tampered).is_err());
+
+    // so does a rewritten outcome
+    let mut tampered = records.clone();
+    tampered[index].status = Some(200);
+    assert!(transparency::verify_chain(&tampered).is_err());
+}
+
+#[actix_web::test]
+async fn economy_invariants() {
+    let (app, db) = init_app_and_db!(get_airdrop, stake_sacred_hive, trigger_sacred_hive);
+    let keypair = generate_keypair();
+    let pubkey = get_pubkey(&keypair);
+    macro_rules! wrap_test {
+        ($($param:expr),*) => {
+            perform_test!(&app, &keypair $(,$param)*);
+        };
+    }
+
+    wrap_test!("/airdrop/".to_string() + &pubkey, StatusCode::OK);
+    wrap_test!(
+        "/sacred_hive/stake".to_string(),
+        SacredHive {
+            pubkey: pubkey.clone(),
+            sacred_queens: 5,
+            eggs: 0,
+        },
+        Empty {},
+        StatusCode::OK
+    );
+    wrap_test!(
+        "/sacred_hive/trigger/".to_string() + &pubkey,
+        StatusCode::OK
+    );
+
+    // every entry written for the account follows the rules
+    let entries: Vec<ledger::LedgerEntry> = db
+        .collection::<ledger::LedgerEntry>(ledger::LEDGER_COLL_NAME)
+        .find(doc! { "pubkey": &pubkey }, None)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(3, entries.len());
+    assert!(entries
+        .iter()
+        .all(|e| economy::check_entry(e, &GameConfig::default()).is_none()));
+    let own_violations = |violations: Vec<economy::Violation>| {
+        violations
+            .into_iter()
+            .filter(|v| v.subject == pubkey)
+            .count()
+    };
+    assert_eq!(
+        0,
+        own_violations(economy::check_accounts(&db).await.unwrap())
+    );
+
+    // eggs laid outside of the trigger rules are detected
+    let mut minted = entries
+        .iter()
+        .find(|e| e.operation == ledger::Operation::Trigger)
+        .unwrap()
+        .clone();
+    minted.changes[0].after.change(EGGS, 1);
+    assert!(economy::check_entry(&minted, &GameConfig::default()).is_some());
+
+    // as are negative balances and orphan documents
+    db.collection::<Swarm>(SWARMS_COLL_NAME)
+        .update_one(
+            doc! { "pubkey": &pubkey },
+            doc! { "$set": { "berserkers": -1 } },
+            None,
+        )
+        .await
+        .unwrap();
+    db.collection::<Hive>(HIVE_COLL_NAME)
+        .delete_one(doc! { "pubkey": &pubkey }, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        2,
+        own_violations(economy::check_accounts(&db).await.unwrap())
+    );
+}
+
+#[actix_web::test]
+async fn economy_stats() {
+    let (app, db) = init_app_and_db!(
+        get_airdrop,
+        stake_sacred_hive,
+        trigger_sacred_hive,
+        get_economy_stats,
+        get_economy_snapshots
+    );
+    economy::create_snapshot_collection(&db).await;
+    let keypair = generate_keypair();
+    let pubkey = get_pubkey(&keypair);
+    macro_rules! wrap_test {
+        ($($param:expr),*) => {
+            perform_test!(&app, &keypair $(,$param)*);
+        };
+    }
+
+    let req = TestRequest::get().uri("/economy/stats").to_request();
+    let before: economy::EconomyStats = read_body_json(call_service(&app, req).await).await;
+    let snapshot = economy::take_snapshot(&db).await.unwrap();
+
+    wrap_test!("/airdrop/".to_string() + &pubkey, StatusCode::OK);
+    wrap_test!(
+        "/sacred_hive/stake".to_string(),
+        SacredHive {
+            pubkey: pubkey.clone(),
+            sacred_queens: 5,
+            eggs: 0,
+        },
+        Empty {},
+        StatusCode::OK
+    );
+    wrap_test!(
+        "/sacred_hive/trigger/".to_string() + &pubkey,
+        StatusCode::OK
+    );
+
+    // other tests share the DB, so totals only grow by at least our part
+    let req = TestRequest::get().uri("/economy/stats").to_request();
+    let after: economy::EconomyStats = read_body_json(call_service(&app, req).await).await;
+    assert!(after.eggs_minted >= before.eggs_minted + 500);
+    assert!(after.sacred_hives.get(SACRED_QUEENS) >= before.sacred_hives.get(SACRED_QUEENS) + 5);
+    assert_eq!(
+        after.total.get(SACRED_QUEENS),
+        after.swarms.get(SACRED_QUEENS) + after.sacred_hives.get(SACRED_QUEENS)
+    );
+
+    // the snapshot taken before is in the range around it
+    let seconds = snapshot.timestamp / 1000;
+    let req = TestRequest::get()
+        .uri(&format!("/economy/snapshots/{}/{}", seconds, seconds + 1))
+        .to_request();
+    let snapshots: Vec<economy::Snapshot> = read_body_json(call_service(&app, req).await).await;
+    assert!(snapshots.iter().any(|s| s.timestamp == snapshot.timestamp));
+    let req = TestRequest::get()
+        .uri(&format!(
+            "/economy/snapshots/{}/{}",
+            seconds - 7200,
+            seconds - 3600
+        ))
+        .to_request();
+    let snapshots: Vec<economy::Snapshot> = read_body_json(call_service(&app, req).await).await;
+    assert!(snapshots.iter().all(|s| s.timestamp != snapshot.timestamp));
+}
+
+#[test]
+fn seeded_world() {
+    let config = seed::SeedConfig {
+        seed: 42,
+        players: 100,
+        preset: seed::Preset::Balanced,
+    };
+    let world = seed::generate(&config);
+    assert_eq!(100, world.swarms.len());
+    assert_eq!(world, seed::generate(&config));
+    assert_ne!(
+        world,
+        seed::generate(&seed::SeedConfig { seed: 43, ..config })
+    );
+    assert!(world.swarms.iter().all(|s| pubkey_is_valid(&s.pubkey)));
+
+    let newbies = seed::generate(&seed::SeedConfig {
+        seed: 42,
+        players: 100,
+        preset: seed::Preset::Newbies,
+    });
+    assert!(newbies.swarms.iter().zip(newbies.sacred_hives.iter()).all(
+        |(swarm, sacred_hive)| swarm.get(SACRED_QUEENS) + sacred_hive.sacred_queens
+            == AIRDROP_SACRED_QUEENS
+    ));
+    assert!(newbies.hives.iter().all(|hive| !hive.is_negative()));
+}
+
+#[actix_web::test]
+async fn schema_migrations() {
+    let (_, db) = init_app_and_db!();
+    let latest = migrations::latest_version();
+    assert!(matches!(migrations::migrate(&db).await, Ok(v) if v == latest));
+    assert_eq!(latest, migrations::current_version(&db).await.unwrap());
+    // running again applies nothing
+    assert!(migrations::migrate(&db).await.is_ok());
+
+    // a database migrated by a newer build is refused
+    let versions = db.collection::<migrations::SchemaVersion>(migrations::SCHEMA_VERSION_COLL_NAME);
+    versions
+        .insert_one(
+            migrations::SchemaVersion {
+                version: latest + 1,
+                description: "from the future".to_string(),
+                applied_at: 0,
+            },
+            None,
+        )
+        .await
+        .unwrap();
+    let refused = migrations::migrate(&db).await;
+    versions
+        .delete_one(doc! { "version": latest + 1 }, None)
+        .await
+        .unwrap();
+    assert!(matches!(
+        refused,
+        Err(migrations::MigrationError::DatabaseAhead { database, .. }) if database == latest + 1
+    ));
+}
+
+#[actix_web::test]
+async fn export_and_restore() {
+    let (app, db) = init_app_and_db!(get_airdrop);
+    let keypair = generate_keypair();
+    let pubkey = get_pubkey(&keypair);
+    perform_test!(
+        &app,
+        &keypair,
+        "/airdrop/".to_string() + &pubkey,
+        StatusCode::OK
+    );
+
+    let path = std::env::temp_dir().join(format!("{}.jsonl.gz", pubkey));
+    let path = path.to_str().unwrap();
+    let manifest = archive::export(&default_world().await, path).await.unwrap();
+    assert_eq!(manifest, archive::validate(path).unwrap());
+
+    // restore into a fresh database
+    let uri = std::env::var("MONGODB_URI").unwrap();
+    let target = Client::with_uri_str(uri)
+        .await
+        .unwrap()
+        .database(&format!("restore_{}", &pubkey[..8]));
+    assert_eq!(manifest, archive::restore(path, &target).await.unwrap());
+    assert_eq!(
+        db_search::<Swarm>(pubkey.clone(), db.clone()).await.ok(),
+        db_search::<Swarm>(pubkey.clone(), target.clone())
+            .await
+            .ok()
+    );
+    // a second restore into the same database is refused
+    assert!(archive::restore(path, &target).await.is_err());
+    target.drop(None).await.unwrap();
+
+    // a restore that fails halfway, here at the ledger the target refuses,
+    // leaves the target as it was
+    let record = doc! { "version": 1, "description": "baseline", "applied_at": 0 };
+    target
+        .collection::<mongodb::bson::Document>(migrations::SCHEMA_VERSION_COLL_NAME)
+        .insert_one(record.clone(), None)
+        .await
+        .unwrap();
+    let refuse_all = mongodb::options::CreateCollectionOptions::builder()
+        .validator(doc! { "operation": { "$exists": false } })
+        .build();
+    target
+        .create_collection(ledger::LEDGER_COLL_NAME, refuse_all)
+        .await
+        .unwrap();
+    assert!(archive::restore(path, &target).await.is_err());
+    assert!(db_search::<Swarm>(pubkey.clone(), target.clone())
+        .await
+        .is_err());
+    let versions: Vec<mongodb::bson::Document> = target
+        .collection::<mongodb::bson::Document>(migrations::SCHEMA_VERSION_COLL_NAME)
+        .find(
+            None,
+            mongodb::options::FindOptions::builder()
+                .projection(doc! { "_id": 0 })
+                .build(),
+        )
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(vec![record], versions);
+    target.drop(None).await.unwrap();
+
+    // a modified archive does not validate
+    let mut archived = String::new();
+    flate2::read::GzDecoder::new(std::fs::File::open(path).unwrap())
+        .read_to_string(&mut archived)
+        .unwrap();
+    let mut out = flate2::write::GzEncoder::new(
+        std::fs::File::create(path).unwrap(),
+        flate2::Compression::default(),
+    );
+    out.write_all(
+        archived
+            .replacen("\"sacred_queens\":10", "\"sacred_queens\":11", 1)
+            .as_bytes(),
+    )
+    .unwrap();
+    out.finish().unwrap();
+    assert!(archive::validate(path).is_err());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[actix_web::test]
+async fn multiple_worlds() {
+    let uri = std::env::var("MONGODB_URI").unwrap();
+    let mongo_client = Client::with_uri_str(uri)
+        .await
+        .expect("failed to connect to database");
+    let keypair = generate_keypair();
+    let pubkey = get_pubkey(&keypair);
+    let worlds_file = std::env::temp_dir().join(format!("{}.json", pubkey));
+    std::fs::write(
+        &worlds_file,
+        format!(
+            r#"[{{"name": "sandbox", "database": "sandbox_{}", "config": {{"airdrop_sacred_queens": 20}}}}]"#,
+            &pubkey[..8]
+        ),
+    )
+    .unwrap();
+    let worlds = Worlds::load(mongo_client, Some(&worlds_file)).unwrap();
+    std::fs::remove_file(&worlds_file).unwrap();
+    let sandbox = worlds.get(Some("sandbox")).unwrap().clone();
+    let default_world = worlds.default_world().clone();
+    let app = init_service(
+        App::new()
+            .app_data(web::Data::new(worlds))
+            .service(get_metrics)
+            .service(get_readyz)
+            .service(web::scope("/worlds/{world}").configure(routes))
+            .service(web::scope("").configure(routes)),
+    )
+    .await;
+
+    // selected by path prefix, with the rules of the world
+    perform_test!(
+        &app,
+        &keypair,
+        "/worlds/sandbox/airdrop/".to_string() + &pubkey,
+        StatusCode::OK
+    );
+    perform_test!(
+        &app,
+        &keypair,
+        "/worlds/sandbox/swarm/".to_string() + &pubkey,
+        rated(Swarm::empty(pubkey.clone()).with(SACRED_QUEENS, 20)),
+        StatusCode::OK
+    );
+    // the default world does not see the account
+    perform_test!(
+        &app,
+        &keypair,
+        "/swarm/".to_string() + &pubkey,
+        StatusCode::NOT_FOUND
+    );
+    // selected by header
+    let req = TestRequest::get()
+        .uri(&("/swarm/".to_string() + &pubkey))
+        .insert_header((WORLD_HEADER, "sandbox"))
+        .to_request();
+    assert_eq!(StatusCode::OK, call_service(&app, req).await.status());
+    // unknown worlds
+    perform_test!(
+        &app,
+        &keypair,
+        "/worlds/nowhere/swarm/".to_string() + &pubkey,
+        StatusCode::NOT_FOUND
+    );
+
+    // every world is reported on
+    for world in [&sandbox, &default_world] {
+        metrics::set_token_supply(&world.name, &economy::stats(&world.db).await.unwrap());
+    }
+    let req = TestRequest::get().uri("/metrics").to_request();
+    let body = String::from_utf8(read_body(call_service(&app, req).await).await.to_vec()).unwrap();
+    assert!(body.contains(
+        "token_supply{collection=\"swarms\",token=\"sacred_queens\",world=\"sandbox\"} 20"
+    ));
+    assert!(body.contains("world=\"default\""));
+    let req = TestRequest::get().uri("/readyz").to_request();
+    let health: serde_json::Value = read_body_json(call_service(&app, req).await).await;
+    assert!(health["sandbox"]["transactions"].as_bool().unwrap());
+    assert!(health[DEFAULT_WORLD]["transactions"].as_bool().unwrap());
+    sandbox.db.drop(None).await.unwrap();
+}
+
+#[test]
+fn hive_production_rates() {
+    let config = GameConfig::default();
+    let hive = Hive::empty(String::new())
+        .with(GUARDIANS, 10)
+        .with(QUEENS, 3);
+    // 3 queens lay 30 eggs an hour, one every 120 seconds
+    assert_eq!(
+        (30, 3600),
+        production::hive_production(&hive, 0, 3600, &config)
+    );
+    // time that did not lay a whole egg is carried over
+    assert_eq!(
+        (1, 120),
+        production::hive_production(&hive, 0, 200, &config)
+    );
+    assert_eq!((0, 0), production::hive_production(&hive, 0, 100, &config));
+    // 10 guardians hold 1000 eggs
+    assert_eq!(
+        (1000, 360_000),
+        production::hive_production(&hive, 0, 360_000, &config)
+    );
+    let full = hive.with(EGGS, 1000);
+    assert_eq!((0, 50), production::hive_production(&full, 0, 50, &config));
+    // hives without queens do not bank time
+    let mut empty = full.clone();
+    empty.set(QUEENS, 0);
+    assert_eq!((0, 50), production::hive_production(&empty, 0, 50, &config));
+}
+
+#[test]
+fn veteran_tiers() {
+    let mut swarm = Swarm {
+        veterans: vec![4, 0, 2],
+        ..Swarm::empty(String::new()).with(BERSERKERS, 10)
+    };
+    // veterans of the highest tier march first
+    let army = swarm.take_berserkers(7).unwrap();
+    assert_eq!(
+        (1, vec![4, 0, 2]),
+        (army.get(BERSERKERS), army.veterans.clone())
+    );
+    assert_eq!((9, vec![]), (swarm.get(BERSERKERS), swarm.veterans.clone()));
+    assert!(swarm.take_berserkers(10).is_none());
+
+    // 100, 150, 200 and 300 percent per tier
+    let percent = GameConfig::default().veteran_attack_percent;
+    assert_eq!(
+        1 + 6 + 6,
+        army.attack_power(&units::default_units(), &percent)
+    );
+
+    // half survive and are promoted, the highest tier stays the highest
+    let promoted = army.promote_survivors(1, 2, percent.len());
+    assert_eq!(
+        (0, vec![0, 2, 1]),
+        (promoted.get(BERSERKERS), promoted.veterans)
+    );
+
+    // empty tiers are trimmed so that balances compare equal
+    let mut balance = Swarm::empty(String::new());
+    balance.add(&army);
+    balance.add(&army.negative());
+    assert_eq!(Swarm::empty(String::new()), balance);
+}
+
+#[test]
+fn flat_balances() {
+    // every token of a hive is written, units of the registry sit next to
+    // them
+    let hive = Hive::empty("pubkey".to_string())
+        .with(GUARDIANS, 3)
+        .with("drones", 2);
+    let document = mongodb::bson::to_document(&hive).unwrap();
+    assert_eq!(
+        doc! {
+            "pubkey": "pubkey",
+            "guardians": 3_i64,
+            "queens": 0_i64,
+            "eggs": 0_i64,
+            "drones": 2_i64,
+        },
+        document
+    );
+    assert_eq!(hive, mongodb::bson::from_document(document).unwrap());
+
+    // the _id Mongo adds is not a balance, fractions are refused
+    let stored = doc! { "_id": mongodb::bson::oid::ObjectId::new(), "pubkey": "pubkey", "guardians": 3, "drones": 2_i64 };
+    assert_eq!(hive, mongodb::bson::from_document(stored).unwrap());
+    let fraction = doc! { "pubkey": "pubkey", "guardians": 1.5 };
+    assert!(mongodb::bson::from_document::<Hive>(fraction).is_err());
+}
+
+#[test]
+fn hive_request_shape() {
+    // signatures are checked over this, it must match what clients sign
+    let client = r#"{"pubkey":"pubkey","guardians":1,"queens":2,"eggs":3}"#;
+    let request: HiveRequest = serde_json::from_str(client).unwrap();
+    assert_eq!(client, serde_json::to_string(&request).unwrap());
+    let hive = Hive::empty("pubkey".to_string())
+        .with(GUARDIANS, 1)
+        .with(QUEENS, 2)
+        .with(EGGS, 3);
+    assert_eq!(hive, request.to_hive());
+    assert_eq!(request, HiveRequest::from(&hive));
+    let drones = hive.with("drones", 4);
+    assert_eq!(drones, HiveRequest::from(&drones).to_hive());
+}
+
+#[test]
+fn unit_registry() {
+    let mut registry = units::default_units();
+    registry.push(units::UnitConfig {
+        id: "drones".to_string(),
+        attack: 2,
+        defense: 5,
+        defense_spread: 0,
+        stakeable: true,
+        hatch_weight: 100,
+    });
+    let swarm = Swarm {
+        veterans: vec![2],
+        ..Swarm::empty(String::new())
+            .with(BERSERKERS, 1)
+            .with("drones", 3)
+    };
+    let balances = swarm.balances();
+    assert_eq!(Some(&2), balances.get("veteran_1"));
+    assert_eq!(Some(&3), balances.get("drones"));
+    assert_eq!(swarm, Swarm::from_balances(String::new(), &balances));
+
+    // hives keep the units of the registry but no berserkers
+    let hive = Hive::from_swarm(&swarm);
+    assert_eq!(Balances::from([("drones".to_string(), 3)]), hive.balances());
+    assert!(units::stakeable(&registry, &hive.balances()));
+    assert!(!units::stakeable(&registry, &swarm.balances()));
+    assert!(!units::stakeable(&units::default_units(), &hive.balances()));
+
+    // new units need no arithmetic of their own
+    let mut balance = swarm.clone();
+    balance.add(&swarm.negative());
+    assert_eq!(Swarm::empty(String::new()), balance);
+    assert!(swarm.negative().is_negative());
+
+    // 1 recruit, 2 veterans with 150 percent and 3 drones hitting with 2
+    assert_eq!(1 + 3 + 6, swarm.attack_power(&registry, &[150]));
+    let defenders = hive.clone().with(GUARDIANS, 10);
+    assert_eq!(
+        90 + 15,
+        units::defense_power(&registry, &defenders.balances(), |_| 0)
+    );
+
+    // the weights of queens, guardians, berserkers and drones follow each other
+    assert_eq!(200, units::total_hatch_weight(&registry));
+    let hatched: Vec<_> = [0, 1, 10, 100, 199, 200]
+        .iter()
+        .map(|draw| units::hatch_unit(&registry, *draw))
+        .collect();
+    assert_eq!(
+        vec![
+            Some("queens"),
+            Some("guardians"),
+            Some("berserkers"),
+            Some("drones"),
+            Some("drones"),
+            None
+        ],
+        hatched
+    );
+}
+
+#[test]
+fn power_band() {
+    let config = matchmaking::MatchmakingConfig::default();
+    assert_eq!((25, Some(400)), config.band(100));
+    // the band holds exactly the powers that are neither below nor above it
+    for attacker in [0, 1, 3, 99, 101] {
+        let (lowest, highest) = config.band(attacker);
+        for target in 0..1000 {
+            let above = target * config.band_percent > attacker * 100;
+            assert_eq!(
+                !config.below_band(attacker, target) && !above,
+                target >= lowest && Some(target) <= highest
+            );
+        }
+    }
+    let open = matchmaking::MatchmakingConfig {
+        band_percent: 0,
+        ..config
+    };
+    assert_eq!((0, None), open.band(100));
+}
+
+#[test]
+fn elo_ratings() {
+    // even odds move half the k factor
+    assert_eq!(16, ratings::rating_change(1200, 1200, true, 32));
+    assert_eq!(-16, ratings::rating_change(1200, 1200, false, 32));
+    // upsets move more than expected results
+    assert_eq!(29, ratings::rating_change(1000, 1400, true, 32));
+    assert_eq!(-29, ratings::rating_change(1400, 1000, false, 32));
+    assert_eq!(3, ratings::rating_change(1400, 1000, true, 32));
+    assert!((ratings::expected_win(1400, 1000) - 0.909).abs() < 0.001);
+}
+
+#[test]
+fn hive_estimates_are_coarse() {
+    let range = |min, max| scouting::Estimate { min, max };
+    assert_eq!(range(0, 0), scouting::estimate(0));
+    assert_eq!(range(1, 1), scouting::estimate(1));
+    assert_eq!(range(2, 3), scouting::estimate(3));
+    assert_eq!(range(64, 127), scouting::estimate(100));
+    assert_eq!(range(64, 127), scouting::estimate(127));
+    assert_eq!(range(128, 255), scouting::estimate(128));
+    assert_eq!(range(1 << 62, i64::MAX), scouting::estimate(i64::MAX));
+}
+
+#[actix_web::test]
+async fn hive_production() {
+    let (app, db) = init_app_and_db!(get_hive);
+    let keypair = generate_keypair();
+    let pubkey = get_pubkey(&keypair);
+    db_insert!(
+        db,
+        HIVE_COLL_NAME,
+        Hive::empty(pubkey.clone())
+            .with(GUARDIANS, 1)
+            .with(QUEENS, 2)
+            .with(EGGS, 10)
+    );
+    let two_hours_ago = chrono::Utc::now().timestamp() - 7200;
+    db_insert!(
+        db,
+        production::HIVE_CLOCKS_COLL_NAME,
+        production::HiveClock {
+            pubkey: pubkey.clone(),
+            settled_at: two_hours_ago,
+        }
+    );
+
+    // 2 queens laid 40 eggs, reads settle the production
+    perform_test!(
+        &app,
+        &keypair,
+        "/hive/get/".to_string() + &pubkey,
+        scouting::HiveEstimate::from(
+            &Hive::empty(pubkey.clone())
+                .with(GUARDIANS, 1)
+                .with(QUEENS, 2)
+                .with(EGGS, 50)
+        ),
+        StatusCode::OK
+    );
+    let produced = db
+        .collection::<ledger::LedgerEntry>(ledger::LEDGER_COLL_NAME)
+        .find_one(doc! { "pubkey": &pubkey, "operation": "produce" }, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(economy::check_entry(&produced, &GameConfig::default()).is_none());
+
+    // the guardian caps the hive at 100 eggs
+    db.collection::<production::HiveClock>(production::HIVE_CLOCKS_COLL_NAME)
+        .update_one(
+            doc! { "pubkey": &pubkey },
+            doc! { "$set": { "settled_at": two_hours_ago - 86400 } },
+            None,
+        )
+        .await
+        .unwrap();
+    perform_test!(
+        &app,
+        &keypair,
+        "/hive/get/".to_string() + &pubkey,
+        scouting::HiveEstimate::from(
+            &Hive::empty(pubkey.clone())
+                .with(GUARDIANS, 1)
+                .with(QUEENS, 2)
+                .with(EGGS, 100)
+        ),
+        StatusCode::OK
+    );
+}
+
+#[actix_web::test]
+#[ignore = "run with '-- --ignored' to clean the DB"]
+async fn clean_db() {
+    let uri = std::env::var("MONGODB_URI").unwrap();
+    let db = Client::with_uri_str(uri)
         .await
         .expect("failed to connect")
         .default_database()
@@ -898,4 +3700,84 @@ async fn clean_db() {
         .drop(None)
         .await
         .expect("drop collection should succeed");
+
+    db.collection::<Delegation>(DELEGATIONS_COLL_NAME)
+        .drop(None)
+        .await
+        .expect("drop collection should succeed");
+
+    db.collection::<Challenge>(CHALLENGES_COLL_NAME)
+        .drop(None)
+        .await
+        .expect("drop collection should succeed");
+
+    db.collection::<ledger::LedgerEntry>(ledger::LEDGER_COLL_NAME)
+        .drop(None)
+        .await
+        .expect("drop collection should succeed");
+
+    db.collection::<transparency::LogRecord>(transparency::TRANSPARENCY_COLL_NAME)
+        .drop(None)
+        .await
+        .expect("drop collection should succeed");
+
+    db.collection::<economy::Snapshot>(economy::SNAPSHOTS_COLL_NAME)
+        .drop(None)
+        .await
+        .expect("drop collection should succeed");
+
+    db.collection::<migrations::SchemaVersion>(migrations::SCHEMA_VERSION_COLL_NAME)
+        .drop(None)
+        .await
+        .expect("drop collection should succeed");
+
+    db.collection::<production::HiveClock>(production::HIVE_CLOCKS_COLL_NAME)
+        .drop(None)
+        .await
+        .expect("drop collection should succeed");
+
+    db.collection::<incubation::HatchJob>(incubation::HATCH_JOBS_COLL_NAME)
+        .drop(None)
+        .await
+        .expect("drop collection should succeed");
+
+    db.collection::<march::March>(march::MARCHES_COLL_NAME)
+        .drop(None)
+        .await
+        .expect("drop collection should succeed");
+
+    db.collection::<notifications::Notification>(notifications::NOTIFICATIONS_COLL_NAME)
+        .drop(None)
+        .await
+        .expect("drop collection should succeed");
+
+    db.collection::<scouting::Intel>(scouting::INTEL_COLL_NAME)
+        .drop(None)
+        .await
+        .expect("drop collection should succeed");
+
+    db.collection::<fortifications::Fortifications>(fortifications::FORTIFICATIONS_COLL_NAME)
+        .drop(None)
+        .await
+        .expect("drop collection should succeed");
+
+    db.collection::<ratings::Rating>(ratings::RATINGS_COLL_NAME)
+        .drop(None)
+        .await
+        .expect("drop collection should succeed");
+
+    db.collection::<bounties::Bounty>(bounties::BOUNTIES_COLL_NAME)
+        .drop(None)
+        .await
+        .expect("drop collection should succeed");
+
+    db.collection::<mercenaries::Offer>(mercenaries::MERCENARIES_COLL_NAME)
+        .drop(None)
+        .await
+        .expect("drop collection should succeed");
+
+    db.collection::<locks::StakeLock>(locks::STAKE_LOCKS_COLL_NAME)
+        .drop(None)
+        .await
+        .expect("drop collection should succeed");
 }
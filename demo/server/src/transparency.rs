@@ -0,0 +1,298 @@
+use {
+    anyhow::Result,
+    ed25519_dalek::*,
+    futures::stream::TryStreamExt,
+    mongodb::{
+        bson::doc,
+        error::{Error as MongoError, ErrorKind, WriteFailure},
+        options::{FindOneOptions, FindOptions, IndexOptions},
+        Database, IndexModel,
+    },
+    serde::{Deserialize, Serialize},
+    sha2::{Digest, Sha256},
+};
+
+pub const TRANSPARENCY_COLL_NAME: &str = "transparencyLog";
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+pub const PAGE_SIZE: i64 = 1000;
+/// Routes whose bodies reveal the composition of a hive. Public pages only
+/// show the digest of their bodies.
+pub const PRIVATE_ROUTES: [&str; 2] = ["/hive/stake", "/hive/unstake"];
+
+/// The exact JSON body of an accepted request, the key that signed it and
+/// the bs58 encoded ed25519 signature.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SignedRequest {
+    pub pubkey: String,
+    pub body: String,
+    pub signature: String,
+}
+
+/// Entry of the transparency log. `hash` covers all other fields, including
+/// the hash of the previous entry, so the log can only be appended to.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct LogRecord {
+    pub seq: i64,
+    pub route: String,
+    pub pubkey: String,
+    pub body: String,
+    pub signature: String,
+    /// Hex encoded sha256 of the body, hashed in its place so that the body
+    /// can be left out of public pages. Older records have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_sha256: Option<String>,
+    /// HTTP status the request was answered with. Records written before
+    /// outcomes were logged have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+/// A verified request waiting for its outcome before it is appended.
+pub struct PendingRecord {
+    pub route: String,
+    pub request: SignedRequest,
+    pub db: Database,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct LogHead {
+    pub seq: i64,
+    pub hash: String,
+}
+
+pub fn verify_signed_body(body: &str, signature: &str, pubkey: &str) -> Result<()> {
+    let decoded_signature: &[u8] = &bs58::decode(signature).into_vec()?;
+    let decoded_signature = Signature::from_bytes(decoded_signature)?;
+    let decoded_pubkey: &[u8] = &bs58::decode(pubkey).into_vec()?;
+    let decoded_pubkey = PublicKey::from_bytes(decoded_pubkey)?;
+    decoded_pubkey.verify(body.as_bytes(), &decoded_signature)?;
+    Ok(())
+}
+
+pub fn body_digest(body: &str) -> String {
+    format!("{:x}", Sha256::digest(body.as_bytes()))
+}
+
+/// Hex encoded sha256 over the newline separated fields of a record. The
+/// digest of the body is hashed instead of the body when there is one, the
+/// status only when there is one.
+pub fn record_hash(record: &LogRecord) -> String {
+    let mut hasher = Sha256::new();
+    let status = record.status.map(|status| status.to_string());
+    for field in [
+        Some(record.prev_hash.as_str()),
+        Some(&record.seq.to_string()),
+        Some(&record.route),
+        Some(&record.pubkey),
+        Some(&record.signature),
+        Some(record.body_sha256.as_ref().unwrap_or(&record.body)),
+        status.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        hasher.update(field.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Leaves the body of a record on a private route out, keeping its digest.
+/// Older records hash their body and are kept as they are.
+pub fn redact(mut record: LogRecord) -> LogRecord {
+    let private = PRIVATE_ROUTES
+        .iter()
+        .any(|route| record.route.ends_with(route));
+    if private && record.body_sha256.is_some() {
+        record.body.clear();
+    }
+    record
+}
+
+fn is_duplicate_key(e: &MongoError) -> bool {
+    matches!(
+        e.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == 11000
+    )
+}
+
+pub async fn head(db: &Database) -> Result<LogHead, MongoError> {
+    let options = FindOneOptions::builder().sort(doc! { "seq": -1 }).build();
+    match db
+        .collection::<LogRecord>(TRANSPARENCY_COLL_NAME)
+        .find_one(None, options)
+        .await?
+    {
+        Some(record) => Ok(LogHead {
+            seq: record.seq,
+            hash: record.hash,
+        }),
+        None => Ok(LogHead {
+            seq: 0,
+            hash: GENESIS_HASH.to_string(),
+        }),
+    }
+}
+
+/// Appends a record with the status the request was answered with after
+/// the current head. Concurrent appends race on the unique `seq` index and
+/// the loser retries on top of the new head.
+pub async fn append(
+    route: &str,
+    request: SignedRequest,
+    status: u16,
+    db: &Database,
+) -> Result<LogHead, MongoError> {
+    loop {
+        let head = head(db).await?;
+        let seq = head.seq + 1;
+        let mut record = LogRecord {
+            seq,
+            route: route.to_string(),
+            pubkey: request.pubkey.clone(),
+            body_sha256: Some(body_digest(&request.body)),
+            body: request.body.clone(),
+            signature: request.signature.clone(),
+            status: Some(status),
+            prev_hash: head.hash,
+            hash: String::new(),
+        };
+        record.hash = record_hash(&record);
+        match db
+            .collection::<LogRecord>(TRANSPARENCY_COLL_NAME)
+            .insert_one(&record, None)
+            .await
+        {
+            Ok(_) => {
+                return Ok(LogHead {
+                    seq,
+                    hash: record.hash,
+                })
+            }
+            Err(e) if is_duplicate_key(&e) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Returns up to `PAGE_SIZE` records starting at `from`, with the bodies of
+/// private routes left out.
+pub async fn page(from: i64, db: &Database) -> Result<Vec<LogRecord>, MongoError> {
+    let options = FindOptions::builder()
+        .sort(doc! { "seq": 1 })
+        .limit(PAGE_SIZE)
+        .build();
+    let records: Vec<LogRecord> = db
+        .collection::<LogRecord>(TRANSPARENCY_COLL_NAME)
+        .find(doc! { "seq": { "$gte": from } }, options)
+        .await?
+        .try_collect()
+        .await?;
+    Ok(records.into_iter().map(redact).collect())
+}
+
+/// Checks the signature, the hash and the link to the previous record of
+/// every record. Redacted bodies can only be checked against the hash, not
+/// against the signature. A log starting at seq 1 must link to the genesis
+/// hash. Returns the head of the verified log.
+pub fn verify_chain(records: &[LogRecord]) -> Result<LogHead, String> {
+    let mut head = match records.first() {
+        Some(first) if first.seq == 1 => LogHead {
+            seq: 0,
+            hash: GENESIS_HASH.to_string(),
+        },
+        Some(first) => LogHead {
+            seq: first.seq - 1,
+            hash: first.prev_hash.clone(),
+        },
+        None => {
+            return Ok(LogHead {
+                seq: 0,
+                hash: GENESIS_HASH.to_string(),
+            })
+        }
+    };
+    for record in records {
+        if record.seq != head.seq + 1 {
+            return Err(format!("record {} follows record {}", record.seq, head.seq));
+        }
+        if record.prev_hash != head.hash {
+            return Err(format!("record {} breaks the chain", record.seq));
+        }
+        if record_hash(record) != record.hash {
+            return Err(format!("record {} has a wrong hash", record.seq));
+        }
+        match &record.body_sha256 {
+            Some(_) if record.body.is_empty() => {}
+            Some(digest) if *digest != body_digest(&record.body) => {
+                return Err(format!("record {} has a wrong body", record.seq));
+            }
+            _ => {
+                if verify_signed_body(&record.body, &record.signature, &record.pubkey).is_err() {
+                    return Err(format!("record {} has an invalid signature", record.seq));
+                }
+            }
+        }
+        head = LogHead {
+            seq: record.seq,
+            hash: record.hash.clone(),
+        };
+    }
+    Ok(head)
+}
+
+/// `verify-log <file> [head_hash]`: verifies a log downloaded from
+/// `/transparency/log/{from}` (a JSON array of records) and optionally
+/// compares its head with the hash published at `/transparency/head`.
+pub fn run_verify(args: &[String]) -> bool {
+    let path = match args.first() {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: server verify-log <file> [head_hash]");
+            return false;
+        }
+    };
+    let records: Vec<LogRecord> = match std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|log| serde_json::from_str(&log).map_err(|e| e.to_string()))
+    {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("could not read {}: {}", path, e);
+            return false;
+        }
+    };
+    match verify_chain(&records) {
+        Ok(head) if args.get(1).is_some_and(|expected| *expected != head.hash) => {
+            println!("head {} does not match the published head", head.hash);
+            false
+        }
+        Ok(head) => {
+            println!(
+                "{} records verified, head {} {}",
+                records.len(),
+                head.seq,
+                head.hash
+            );
+            true
+        }
+        Err(e) => {
+            println!("verification failed: {}", e);
+            false
+        }
+    }
+}
+
+pub async fn create_transparency_indexes(db: &Database) {
+    let options = IndexOptions::builder().unique(true).build();
+    let model = IndexModel::builder()
+        .keys(doc! { "seq": 1 })
+        .options(options)
+        .build();
+    db.collection::<LogRecord>(TRANSPARENCY_COLL_NAME)
+        .create_index(model, None)
+        .await
+        .expect("creating an index should succeed");
+}
@@ -0,0 +1,194 @@
+use {
+    serde::{Deserialize, Serialize},
+    std::collections::BTreeMap,
+};
+
+pub const SACRED_QUEENS: &str = "sacred_queens";
+pub const QUEENS: &str = "queens";
+pub const GUARDIANS: &str = "guardians";
+pub const BERSERKERS: &str = "berserkers";
+pub const EGGS: &str = "eggs";
+
+/// Tokens every swarm document writes, empty or not, so that clients can
+/// rely on them.
+pub const TOKENS: [&str; 5] = [SACRED_QUEENS, QUEENS, GUARDIANS, BERSERKERS, EGGS];
+
+/// Tokens every hive document writes. Hives hold neither sacred queens nor
+/// berserkers.
+pub const HIVE_TOKENS: [&str; 3] = [GUARDIANS, QUEENS, EGGS];
+
+/// Amounts keyed by unit id. Empty balances are left out, so two maps with
+/// the same amounts compare equal.
+pub type Balances = BTreeMap<String, i64>;
+
+/// A unit type of a world. Every hatched egg becomes a unit drawn with
+/// probability `hatch_weight` over the sum of all weights.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct UnitConfig {
+    pub id: String,
+    /// Attack power of a unit sent to a march.
+    pub attack: i64,
+    /// Defense of a unit staked in a hive. Each battle adds a random bonus
+    /// between zero and `defense_spread` to all units of the type.
+    pub defense: i64,
+    pub defense_spread: i64,
+    /// Whether the unit can be staked in a hive.
+    pub stakeable: bool,
+    pub hatch_weight: i64,
+}
+
+/// Hatches every egg into a queen (1%), a guardian (9%) or a berserker.
+/// Queens defend with 0 to 9, guardians with 9 or 10 and berserkers attack
+/// with 1.
+pub fn default_units() -> Vec<UnitConfig> {
+    let unit = |id: &str, attack, defense, defense_spread, stakeable, hatch_weight| UnitConfig {
+        id: id.to_string(),
+        attack,
+        defense,
+        defense_spread,
+        stakeable,
+        hatch_weight,
+    };
+    vec![
+        unit(QUEENS, 0, 0, 9, true, 1),
+        unit(GUARDIANS, 0, 9, 1, true, 9),
+        unit(BERSERKERS, 1, 0, 0, false, 90),
+    ]
+}
+
+pub fn find<'a>(units: &'a [UnitConfig], id: &str) -> Option<&'a UnitConfig> {
+    units.iter().find(|unit| unit.id == id)
+}
+
+/// Whether every unit of `balances` can be staked in a hive. Eggs are not
+/// units and can always be staked.
+pub fn stakeable(units: &[UnitConfig], balances: &Balances) -> bool {
+    balances
+        .iter()
+        .filter(|(id, amount)| id.as_str() != EGGS && **amount != 0)
+        .all(|(id, _)| find(units, id).is_some_and(|unit| unit.stakeable))
+}
+
+/// Id of the unit an egg hatches into for a `draw` below the sum of the
+/// hatch weights.
+pub fn hatch_unit(units: &[UnitConfig], mut draw: i64) -> Option<&str> {
+    for unit in units {
+        if draw < unit.hatch_weight {
+            return Some(&unit.id);
+        }
+        draw -= unit.hatch_weight.max(0);
+    }
+    None
+}
+
+pub fn total_hatch_weight(units: &[UnitConfig]) -> i64 {
+    units.iter().map(|unit| unit.hatch_weight.max(0)).sum()
+}
+
+/// Defense of the units of `balances` with one random bonus per unit type,
+/// `spread` picks a bonus between zero and its argument.
+pub fn defense_power(
+    units: &[UnitConfig],
+    balances: &Balances,
+    mut spread: impl FnMut(i64) -> i64,
+) -> i64 {
+    units
+        .iter()
+        .map(|unit| {
+            let count = balances.get(&unit.id).copied().unwrap_or(0);
+            count * (unit.defense + spread(unit.defense_spread))
+        })
+        .sum()
+}
+
+pub fn add(balances: &mut Balances, addend: &Balances) {
+    for (id, amount) in addend {
+        *balances.entry(id.clone()).or_insert(0) += amount;
+    }
+    balances.retain(|_, amount| *amount != 0);
+}
+
+pub fn negative(balances: &Balances) -> Balances {
+    balances
+        .iter()
+        .map(|(id, amount)| (id.clone(), -amount))
+        .collect()
+}
+
+pub fn is_negative(balances: &Balances) -> bool {
+    balances.values().any(|amount| amount.is_negative())
+}
+
+/// Amount of `id` in `balances`.
+pub fn get(balances: &Balances, id: &str) -> i64 {
+    balances.get(id).copied().unwrap_or(0)
+}
+
+/// Adds `amount` to the balance of `id`.
+pub fn change(balances: &mut Balances, id: &str, amount: i64) {
+    let balance = balances.entry(id.to_string()).or_insert(0);
+    *balance += amount;
+    if *balance == 0 {
+        balances.remove(id);
+    }
+}
+
+/// Stores balances in the fields of the document that holds them, keyed by
+/// unit id. The `TOKENS` are written even when empty. Fields that are not
+/// integers, like `_id`, are not balances and are skipped when reading.
+pub mod flat {
+    use {
+        super::{Balances, TOKENS},
+        mongodb::bson::Bson,
+        serde::{de::Error, Deserialize, Deserializer, Serializer},
+        std::collections::BTreeMap,
+    };
+
+    pub fn serialize<S: Serializer>(balances: &Balances, serializer: S) -> Result<S::Ok, S::Error> {
+        write(&TOKENS, balances, serializer)
+    }
+
+    /// Writes `tokens` in their order, then the other balances.
+    pub fn write<S: Serializer>(
+        tokens: &[&str],
+        balances: &Balances,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let units = balances
+            .iter()
+            .filter(|(id, _)| !tokens.contains(&id.as_str()))
+            .map(|(id, amount)| (id.as_str(), *amount));
+        let tokens = tokens.iter().map(|id| (*id, super::get(balances, id)));
+        serializer.collect_map(tokens.chain(units))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Balances, D::Error> {
+        let fields = BTreeMap::<String, Bson>::deserialize(deserializer)?;
+        let mut balances = Balances::new();
+        for (id, value) in fields {
+            let amount = match value {
+                Bson::Int64(amount) => amount,
+                Bson::Int32(amount) => i64::from(amount),
+                Bson::Double(_) => return Err(D::Error::custom(format!("{} is not whole", id))),
+                _ => continue,
+            };
+            if amount != 0 {
+                balances.insert(id, amount);
+            }
+        }
+        Ok(balances)
+    }
+}
+
+/// `flat` for hive documents, which only write the `HIVE_TOKENS`.
+pub mod flat_hive {
+    pub use super::flat::deserialize;
+    use {
+        super::{Balances, HIVE_TOKENS},
+        serde::Serializer,
+    };
+
+    pub fn serialize<S: Serializer>(balances: &Balances, serializer: S) -> Result<S::Ok, S::Error> {
+        super::flat::write(&HIVE_TOKENS, balances, serializer)
+    }
+}
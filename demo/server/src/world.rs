@@ -0,0 +1,189 @@
+use {
+    super::{
+        bounties::BOUNTY_SECS,
+        fortifications::FortificationConfig,
+        incubation::HATCH_SECS_PER_EGG,
+        locks::{default_locks, LockConfig},
+        march::MARCH_SECS,
+        matchmaking::MatchmakingConfig,
+        model::{AIRDROP_SACRED_QUEENS, EGGS_PER_SACRED_QUEEN, VETERAN_ATTACK_PERCENT},
+        production::{HIVE_CAPACITY_PER_GUARDIAN, HIVE_EGGS_PER_QUEEN_HOUR},
+        ratings::RATING_K_FACTOR,
+        scouting::{INTEL_SECS, SCOUT_BERSERKERS, SCOUT_EGGS},
+        units::{default_units, UnitConfig},
+    },
+    actix_web::{dev::Payload, error::ErrorNotFound, web, FromRequest, HttpRequest},
+    futures::future::{ready, Ready},
+    mongodb::{Client, Database},
+    serde::{Deserialize, Serialize},
+    std::{collections::BTreeMap, path::Path},
+};
+
+/// Header that selects a world when the path has no `/worlds/{world}` prefix.
+pub const WORLD_HEADER: &str = "x-world";
+pub const DEFAULT_WORLD: &str = "default";
+
+/// Game rules of a world. Missing fields take the values of the main world.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct GameConfig {
+    pub airdrop_sacred_queens: i64,
+    pub eggs_per_sacred_queen: i64,
+    /// Eggs laid per hour by each queen staked in a hive.
+    pub hive_eggs_per_queen_hour: i64,
+    /// Eggs a hive can hold per staked guardian before it stops laying.
+    pub hive_capacity_per_guardian: i64,
+    /// Seconds an egg spends in the hatchery.
+    pub hatch_secs_per_egg: i64,
+    /// Whether a won raid also loots the eggs the hive owner has incubating.
+    pub incubation_raidable: bool,
+    /// Seconds a march takes to reach the hive it attacks.
+    pub march_secs: i64,
+    /// Berserkers spent on a scout paid with berserkers.
+    pub scout_berserkers: i64,
+    /// Eggs spent on a scout paid with eggs.
+    pub scout_eggs: i64,
+    /// Seconds the exact numbers of a scouted hive stay readable.
+    pub intel_secs: i64,
+    /// Attack power of each veteran tier in percent of a recruit. Berserkers
+    /// that survive a won battle move up one tier.
+    pub veteran_attack_percent: Vec<i64>,
+    /// Levels and prices of the hive buildings.
+    pub fortifications: FortificationConfig,
+    /// Unit types eggs hatch into. Units without a field in `Swarm` and
+    /// `Hive` are kept in their `units` maps.
+    pub units: Vec<UnitConfig>,
+    /// Power band of suggested and fully looted targets.
+    pub matchmaking: MatchmakingConfig,
+    /// Most rating points a single attack moves between attacker and
+    /// defender.
+    pub rating_k_factor: i64,
+    /// Seconds a bounty stays open before its eggs go back to the sponsor.
+    pub bounty_secs: i64,
+    /// Lock periods sacred queens can be staked for and their yields.
+    pub stake_locks: Vec<LockConfig>,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            airdrop_sacred_queens: AIRDROP_SACRED_QUEENS,
+            eggs_per_sacred_queen: EGGS_PER_SACRED_QUEEN,
+            hive_eggs_per_queen_hour: HIVE_EGGS_PER_QUEEN_HOUR,
+            hive_capacity_per_guardian: HIVE_CAPACITY_PER_GUARDIAN,
+            hatch_secs_per_egg: HATCH_SECS_PER_EGG,
+            incubation_raidable: false,
+            march_secs: MARCH_SECS,
+            scout_berserkers: SCOUT_BERSERKERS,
+            scout_eggs: SCOUT_EGGS,
+            intel_secs: INTEL_SECS,
+            veteran_attack_percent: VETERAN_ATTACK_PERCENT.to_vec(),
+            fortifications: FortificationConfig::default(),
+            units: default_units(),
+            matchmaking: MatchmakingConfig::default(),
+            rating_k_factor: RATING_K_FACTOR,
+            bounty_secs: BOUNTY_SECS,
+            stake_locks: default_locks(),
+        }
+    }
+}
+
+/// A game world: its own database and rules. Cheap to clone.
+#[derive(Clone)]
+pub struct World {
+    pub name: String,
+    pub client: Client,
+    pub db: Database,
+    pub config: GameConfig,
+}
+
+/// Entry of the `WORLDS_FILE` JSON array.
+#[derive(Deserialize)]
+struct WorldEntry {
+    name: String,
+    database: String,
+    #[serde(default)]
+    config: GameConfig,
+}
+
+/// All worlds served by the process. The default world uses the database of
+/// `MONGODB_URI` and answers requests that do not select a world.
+pub struct Worlds {
+    default: World,
+    named: BTreeMap<String, World>,
+}
+
+impl Worlds {
+    pub fn single(client: Client) -> Self {
+        Worlds {
+            default: World {
+                name: DEFAULT_WORLD.to_string(),
+                db: client.default_database().expect("default db not specified"),
+                client,
+                config: GameConfig::default(),
+            },
+            named: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the worlds of `file`, a JSON array of
+    /// `{"name": .., "database": .., "config": {..}}`.
+    pub fn load(client: Client, file: Option<&Path>) -> std::io::Result<Self> {
+        let mut worlds = Worlds::single(client);
+        let file = match file {
+            Some(file) => file,
+            None => return Ok(worlds),
+        };
+        let entries: Vec<WorldEntry> = serde_json::from_reader(std::fs::File::open(file)?)?;
+        for entry in entries {
+            if entry.name == DEFAULT_WORLD || worlds.named.contains_key(&entry.name) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("world {} is defined twice", entry.name),
+                ));
+            }
+            let world = World {
+                name: entry.name.clone(),
+                client: worlds.default.client.clone(),
+                db: worlds.default.client.database(&entry.database),
+                config: entry.config,
+            };
+            worlds.named.insert(entry.name, world);
+        }
+        Ok(worlds)
+    }
+
+    pub fn default_world(&self) -> &World {
+        &self.default
+    }
+
+    pub fn get(&self, name: Option<&str>) -> Option<&World> {
+        match name {
+            None | Some(DEFAULT_WORLD) => Some(&self.default),
+            Some(name) => self.named.get(name),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &World> {
+        std::iter::once(&self.default).chain(self.named.values())
+    }
+}
+
+/// Resolves the world of a request from the `{world}` path segment or the
+/// `x-world` header. Unknown worlds are answered with 404.
+impl FromRequest for World {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let worlds = req
+            .app_data::<web::Data<Worlds>>()
+            .expect("worlds not configured");
+        let name = req.match_info().get("world").or_else(|| {
+            req.headers()
+                .get(WORLD_HEADER)
+                .and_then(|name| name.to_str().ok())
+        });
+        ready(worlds.get(name).cloned().ok_or_else(|| ErrorNotFound("{}")))
+    }
+}